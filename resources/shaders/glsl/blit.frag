@@ -0,0 +1,16 @@
+#version 150 core
+
+in vec2 v_TexCoord;
+
+layout(std140) uniform PostLocals {
+    vec4 u_TexelSize;
+    vec4 u_Params;
+};
+
+uniform sampler2D t_Source;
+
+out vec4 o_Color;
+
+void main() {
+    o_Color = texture(t_Source, v_TexCoord);
+}
@@ -0,0 +1,40 @@
+#version 150 core
+
+in vec2 v_TexCoord;
+
+layout(std140) uniform PostLocals {
+    vec4 u_TexelSize;
+    vec4 u_Params;
+};
+
+uniform sampler2D t_Source;
+
+out vec4 o_Color;
+
+// Single-pass bloom gathering bright texels from a widening ring of taps.
+// Params: threshold, intensity, radius in texels.
+vec3 bright(vec2 coord) {
+    vec3 color = texture(t_Source, coord).rgb;
+    return max(color - vec3(u_Params.x), vec3(0.0));
+}
+
+void main() {
+    vec4 color = texture(t_Source, v_TexCoord);
+    vec3 glow = vec3(0.0);
+    float weight = 0.0;
+
+    for (int ring = 1; ring <= 4; ++ring) {
+        float distance = u_Params.z * float(ring) / 4.0;
+        float falloff = 1.0 / float(ring);
+
+        for (int tap = 0; tap < 8; ++tap) {
+            float angle = float(tap) * 0.785398 + float(ring) * 0.392699;
+            vec2 offset = vec2(cos(angle), sin(angle)) * distance * u_TexelSize.xy;
+
+            glow += bright(v_TexCoord + offset) * falloff;
+            weight += falloff;
+        }
+    }
+
+    o_Color = vec4(color.rgb + glow / weight * u_Params.y, color.a);
+}
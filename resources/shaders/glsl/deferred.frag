@@ -0,0 +1,84 @@
+#version 150 core
+
+#define MAX_LIGHTS 256
+#define DIRECTIONAL 0.0
+#define SPOT 2.0
+
+struct PackedLight {
+    vec4 position_range;
+    vec4 direction_kind;
+    vec4 color_intensity;
+    vec4 cone_shadow;
+};
+
+in vec2 v_TexCoord;
+
+layout(std140) uniform DeferredLocals {
+    mat4 u_InverseViewProjection;
+    vec4 u_CameraPosition;
+    vec4 u_ClipParams;
+    uvec4 u_LightCounts;
+};
+
+layout(std140) uniform Lights {
+    PackedLight u_Lights[MAX_LIGHTS];
+};
+
+uniform sampler2D t_Albedo;
+uniform sampler2D t_Normal;
+uniform sampler2D t_Material;
+uniform sampler2D t_Depth;
+
+out vec4 o_Color;
+
+void main() {
+    float depth = texture(t_Depth, v_TexCoord).r;
+    if (depth == u_ClipParams.z) {
+        discard;
+    }
+
+    // Clip parameters hold the window-to-device depth scale and offset, the cleared depth and
+    // the device Y direction.
+    vec2 device = v_TexCoord * 2.0 - 1.0;
+    vec4 clip = vec4(device.x, device.y * u_ClipParams.w, depth * u_ClipParams.x + u_ClipParams.y, 1.0);
+    vec4 world = clip * u_InverseViewProjection;
+    vec3 position = world.xyz / world.w;
+
+    vec3 albedo = texture(t_Albedo, v_TexCoord).rgb;
+    vec3 normal = normalize(texture(t_Normal, v_TexCoord).xyz * 2.0 - 1.0);
+    vec4 material = texture(t_Material, v_TexCoord);
+    vec3 view = normalize(u_CameraPosition.xyz - position);
+
+    vec3 color = albedo * material.b * 0.03;
+
+    for (uint i = 0u; i < u_LightCounts.w; ++i) {
+        PackedLight light = u_Lights[i];
+        vec3 incident;
+        float attenuation = 1.0;
+
+        if (light.direction_kind.w == DIRECTIONAL) {
+            incident = -light.direction_kind.xyz;
+        } else {
+            vec3 offset = light.position_range.xyz - position;
+            float distance = length(offset);
+            incident = offset / distance;
+            attenuation = clamp(1.0 - distance / light.position_range.w, 0.0, 1.0);
+            attenuation *= attenuation;
+
+            if (light.direction_kind.w == SPOT) {
+                float cosine = dot(-incident, light.direction_kind.xyz);
+                attenuation *= smoothstep(light.cone_shadow.y, light.cone_shadow.x, cosine);
+            }
+        }
+
+        float diffuse = max(dot(normal, incident), 0.0);
+        vec3 half_vector = normalize(incident + view);
+        float shininess = mix(256.0, 2.0, material.r);
+        float specular = pow(max(dot(normal, half_vector), 0.0), shininess) * (1.0 - material.r);
+
+        vec3 radiance = light.color_intensity.rgb * light.color_intensity.a * attenuation;
+        color += (albedo * diffuse + vec3(specular) * mix(0.04, 1.0, material.g)) * radiance;
+    }
+
+    o_Color = vec4(color, 1.0);
+}
@@ -0,0 +1,9 @@
+#version 150 core
+
+out vec2 v_TexCoord;
+
+// Covers the screen with a single triangle generated from the vertex index.
+void main() {
+    v_TexCoord = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2);
+    gl_Position = vec4(v_TexCoord * 2.0 - 1.0, 0.0, 1.0);
+}
@@ -0,0 +1,50 @@
+#version 150 core
+
+in vec2 v_TexCoord;
+
+layout(std140) uniform PostLocals {
+    vec4 u_TexelSize;
+    vec4 u_Params;
+};
+
+uniform sampler2D t_Source;
+
+out vec4 o_Color;
+
+// Single-pass FXAA along the lines of the FXAA 3.11 console variant.
+// Params: edge threshold, minimum threshold, span limit.
+float luma(vec3 color) {
+    return dot(color, vec3(0.299, 0.587, 0.114));
+}
+
+void main() {
+    vec2 texel = u_TexelSize.xy;
+    vec3 center = texture(t_Source, v_TexCoord).rgb;
+    float north_west = luma(texture(t_Source, v_TexCoord + vec2(-1.0, -1.0) * texel).rgb);
+    float north_east = luma(texture(t_Source, v_TexCoord + vec2(1.0, -1.0) * texel).rgb);
+    float south_west = luma(texture(t_Source, v_TexCoord + vec2(-1.0, 1.0) * texel).rgb);
+    float south_east = luma(texture(t_Source, v_TexCoord + vec2(1.0, 1.0) * texel).rgb);
+    float middle = luma(center);
+
+    float lowest = min(middle, min(min(north_west, north_east), min(south_west, south_east)));
+    float highest = max(middle, max(max(north_west, north_east), max(south_west, south_east)));
+
+    if (highest - lowest < max(u_Params.y, highest * u_Params.x)) {
+        o_Color = vec4(center, 1.0);
+        return;
+    }
+
+    vec2 direction = vec2(-((north_west + north_east) - (south_west + south_east)),
+                          (north_west + south_west) - (north_east + south_east));
+    float reduce = max((north_west + north_east + south_west + south_east) * 0.03125, 1.0 / 128.0);
+    float scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+    direction = clamp(direction * scale, -u_Params.z, u_Params.z) * texel;
+
+    vec3 near = 0.5 * (texture(t_Source, v_TexCoord + direction * (1.0 / 3.0 - 0.5)).rgb +
+                       texture(t_Source, v_TexCoord + direction * (2.0 / 3.0 - 0.5)).rgb);
+    vec3 far = near * 0.5 + 0.25 * (texture(t_Source, v_TexCoord - direction * 0.5).rgb +
+                                    texture(t_Source, v_TexCoord + direction * 0.5).rgb);
+    float far_luma = luma(far);
+
+    o_Color = vec4(far_luma < lowest || far_luma > highest ? near : far, 1.0);
+}
@@ -0,0 +1,18 @@
+#version 150 core
+
+in vec2 v_TexCoord;
+
+layout(std140) uniform PostLocals {
+    vec4 u_TexelSize;
+    vec4 u_Params;
+};
+
+uniform sampler2D t_Source;
+
+out vec4 o_Color;
+
+// Params: gamma.
+void main() {
+    vec4 color = texture(t_Source, v_TexCoord);
+    o_Color = vec4(pow(color.rgb, vec3(1.0 / u_Params.x)), color.a);
+}
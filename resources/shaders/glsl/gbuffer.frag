@@ -0,0 +1,21 @@
+#version 150 core
+
+in vec3 v_Normal;
+in vec2 v_TexCoord;
+
+layout(std140) uniform Locals {
+    mat4 u_Model;
+    mat4 u_ViewProjection;
+    vec4 u_Albedo;
+    vec4 u_Material;
+};
+
+out vec4 o_Albedo;
+out vec4 o_Normal;
+out vec4 o_Material;
+
+void main() {
+    o_Albedo = u_Albedo;
+    o_Normal = vec4(normalize(v_Normal) * 0.5 + 0.5, 1.0);
+    o_Material = u_Material;
+}
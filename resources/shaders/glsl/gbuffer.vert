@@ -0,0 +1,22 @@
+#version 150 core
+
+in vec3 a_Position;
+in vec3 a_Normal;
+in vec2 a_TexCoord;
+
+layout(std140) uniform Locals {
+    mat4 u_Model;
+    mat4 u_ViewProjection;
+    vec4 u_Albedo;
+    vec4 u_Material;
+};
+
+out vec3 v_Normal;
+out vec2 v_TexCoord;
+
+// Matrices are laid out for row vectors, so points are multiplied from the left.
+void main() {
+    v_Normal = (vec4(a_Normal, 0.0) * u_Model).xyz;
+    v_TexCoord = a_TexCoord;
+    gl_Position = vec4(a_Position, 1.0) * u_Model * u_ViewProjection;
+}
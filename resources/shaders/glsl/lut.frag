@@ -0,0 +1,33 @@
+#version 150 core
+
+in vec2 v_TexCoord;
+
+layout(std140) uniform PostLocals {
+    vec4 u_TexelSize;
+    vec4 u_Params;
+};
+
+uniform sampler2D t_Source;
+
+out vec4 o_Color;
+
+uniform sampler2D t_Lut;
+
+// Grades through a 2D strip of blue slices, each a red-green square. Params: slice count,
+// blend strength.
+void main() {
+    vec4 color = texture(t_Source, v_TexCoord);
+    vec3 graded = clamp(color.rgb, 0.0, 1.0);
+    float size = u_Params.x;
+
+    float blue = graded.b * (size - 1.0);
+    float lower = floor(blue);
+    float upper = min(lower + 1.0, size - 1.0);
+    vec2 texel = vec2(0.5 / (size * size), 0.5 / size);
+    vec2 coord = vec2(graded.r * (size - 1.0) / (size * size), graded.g * (size - 1.0) / size) + texel;
+
+    vec3 first = texture(t_Lut, coord + vec2(lower / size, 0.0)).rgb;
+    vec3 second = texture(t_Lut, coord + vec2(upper / size, 0.0)).rgb;
+
+    o_Color = vec4(mix(color.rgb, mix(first, second, blue - lower), u_Params.y), color.a);
+}
@@ -0,0 +1,22 @@
+#version 150 core
+
+in vec2 v_TexCoord;
+
+layout(std140) uniform PostLocals {
+    vec4 u_TexelSize;
+    vec4 u_Params;
+};
+
+uniform sampler2D t_Source;
+
+out vec4 o_Color;
+
+// Filmic curve fitted to the ACES reference transform. Params: exposure.
+vec3 aces(vec3 x) {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+void main() {
+    vec4 color = texture(t_Source, v_TexCoord);
+    o_Color = vec4(aces(color.rgb * u_Params.x), color.a);
+}
@@ -0,0 +1,21 @@
+#version 150 core
+
+in vec2 v_TexCoord;
+
+layout(std140) uniform PostLocals {
+    vec4 u_TexelSize;
+    vec4 u_Params;
+};
+
+uniform sampler2D t_Source;
+
+out vec4 o_Color;
+
+// Params: intensity, inner radius, outer radius.
+void main() {
+    vec4 color = texture(t_Source, v_TexCoord);
+    float distance = length(v_TexCoord - 0.5) * 1.41421356;
+    float shade = 1.0 - smoothstep(u_Params.y, u_Params.z, distance) * u_Params.x;
+
+    o_Color = vec4(color.rgb * shade, color.a);
+}
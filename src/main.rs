@@ -4,6 +4,7 @@ extern crate gfx_core;
 extern crate glutin;
 extern crate nalgebra;
 extern crate winit;
+extern crate crossbeam;
 extern crate gfx_device_gl;
 extern crate gfx_window_glutin;
 
@@ -16,6 +16,25 @@ use gfx_device_vulkan;
 #[cfg(feature = "vulkan")]
 use gfx_window_vulkan;
 
+/// Clip-space conventions a backend expects from projection matrices.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ClipSpace {
+    /// Normalized device depth spans [0, 1] rather than [-1, 1].
+    pub zero_to_one_depth: bool,
+    /// Normalized device Y points down the framebuffer rather than up.
+    pub y_down: bool,
+}
+
+/// Shading language a backend consumes.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ShaderTarget {
+    /// GLSL of a version such as 150, for OpenGL ES when `es` is set.
+    Glsl { version: u16, es: bool },
+    /// Metal shading language.
+    Msl,
+    SpirV,
+}
+
 /// OpenGL rendering backend.
 pub struct OpenGL;
 
@@ -33,13 +52,19 @@ pub trait Backend where Self::ColorFormat: gfx::format::Formatted,
     type Device: gfx::Device;
     type Factory: gfx::Factory<Self::Resources>;
     type CommandBuffer: gfx::CommandBuffer<Self::Resources>;
-    type ColorFormat;
-    type DepthFormat;
+    type ColorFormat: gfx::format::BlendFormat;
+    type DepthFormat: gfx::format::DepthFormat;
     type Window;
     type ShaderModel;
     type DepthStencilView;
     type RenderTargetView;
-    type Sampler;
+    type Sampler: Clone + ::std::fmt::Debug + Eq + ::std::hash::Hash;
+
+    /// Clip-space conventions of the backend's graphics API.
+    fn clip_space() -> ClipSpace;
+
+    /// Shading language to translate shaders to for a device's shader model.
+    fn shader_target(model: &Self::ShaderModel) -> ShaderTarget;
 }
 
 impl Backend for OpenGL {
@@ -54,6 +79,15 @@ impl Backend for OpenGL {
     type DepthStencilView = gfx::handle::DepthStencilView<gfx_device_gl::Resources, Self::DepthFormat>;
     type RenderTargetView = gfx::handle::RenderTargetView<gfx_device_gl::Resources, Self::ColorFormat>;
     type Sampler = gfx::handle::Sampler<gfx_device_gl::Resources>;
+
+    fn clip_space() -> ClipSpace {
+        ClipSpace { zero_to_one_depth: false, y_down: false }
+    }
+
+    /// The model is the shading language version reported by the device, so 1.50 is GLSL 150.
+    fn shader_target(model: &Self::ShaderModel) -> ShaderTarget {
+        ShaderTarget::Glsl { version: (model.major * 100 + model.minor) as u16, es: model.is_embedded }
+    }
 }
 
 #[cfg(feature = "metal")]
@@ -69,6 +103,14 @@ impl Backend for Metal {
     type DepthStencilView = gfx::handle::DepthStencilView<gfx_device_metal::Resources, Self::DepthFormat>;
     type RenderTargetView = gfx::handle::RenderTargetView<gfx_device_metal::Resources, Self::ColorFormat>;
     type Sampler = gfx::handle::Sampler<gfx_device_metal::Resources>;
+
+    fn clip_space() -> ClipSpace {
+        ClipSpace { zero_to_one_depth: true, y_down: false }
+    }
+
+    fn shader_target(_: &Self::ShaderModel) -> ShaderTarget {
+        ShaderTarget::Msl
+    }
 }
 
 #[cfg(feature = "vulkan")]
@@ -84,6 +126,14 @@ impl Backend for Vulkan {
     type DepthStencilView = gfx::handle::DepthStencilView<gfx_device_vulkan::Resources, Self::DepthFormat>;
     type RenderTargetView = gfx::handle::RenderTargetView<gfx_device_vulkan::Resources, Self::ColorFormat>;
     type Sampler = gfx::handle::Sampler<gfx_device_vulkan::Resources>;
+
+    fn clip_space() -> ClipSpace {
+        ClipSpace { zero_to_one_depth: true, y_down: true }
+    }
+
+    fn shader_target(_: &Self::ShaderModel) -> ShaderTarget {
+        ShaderTarget::SpirV
+    }
 }
 
 
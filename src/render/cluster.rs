@@ -0,0 +1,291 @@
+use gfx;
+use nalgebra::{Matrix4, Vector3};
+use core::platform::Platform;
+use system::bounds::{Aabb, Sphere};
+use system::camera::CameraManager;
+use system::entity::Entity;
+use system::light::{LightKind, LightManager, PackedLight};
+use system::scene;
+use render::backend;
+use render::error::{RenderError, RenderResult};
+
+/// Dimensions of the cluster grid.
+///
+/// The screen is split into `tiles_x` by `tiles_y` tiles and the view depth between the camera's
+/// clip planes into `slices` exponentially growing slices, so near clusters stay small.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ClusterConfig {
+    pub tiles_x: u32,
+    pub tiles_y: u32,
+    pub slices: u32,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        ClusterConfig {
+            tiles_x: 16,
+            tiles_y: 9,
+            slices: 24,
+        }
+    }
+}
+
+/// GPU copies of the cluster tables, ready to bind as shader resources.
+pub struct ClusterBuffers<B: backend::Backend> {
+    pub grid: gfx::handle::Buffer<B::Resources, [u32; 2]>,
+    pub indices: gfx::handle::Buffer<B::Resources, u32>,
+    pub lights: gfx::handle::Buffer<B::Resources, PackedLight>,
+}
+
+/// Assigns point and spot lights to view-space clusters (froxels).
+///
+/// Each cluster owns an `[offset, count]` entry in the grid table pointing into the light index
+/// list, whose entries index the light manager's packed array. Clusters are laid out X fastest,
+/// then Y, then depth slice. Directional lights affect every cluster and are left out of the
+/// tables; shaders read them directly from the start of the packed array.
+///
+/// Assignment visits clusters and lights in a fixed order, so identical inputs always produce
+/// identical tables.
+pub struct ClusterBuilder {
+    config: ClusterConfig,
+    bounds: Vec<Aabb>,
+    projection: Matrix4<f32>,
+    clip_planes: (f32, f32),
+    grid: Vec<[u32; 2]>,
+    indices: Vec<u32>,
+}
+
+impl ClusterBuilder {
+    pub fn new(config: ClusterConfig) -> ClusterBuilder {
+        ClusterBuilder {
+            config,
+            bounds: Vec::new(),
+            projection: Matrix4::from_element(0.0f32),
+            clip_planes: (0.0f32, 0.0f32),
+            grid: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> ClusterConfig {
+        self.config
+    }
+
+    pub fn cluster_count(&self) -> usize {
+        (self.config.tiles_x * self.config.tiles_y * self.config.slices) as usize
+    }
+
+    /// Per-cluster `[offset, count]` entries into the index list.
+    pub fn grid(&self) -> &[[u32; 2]] {
+        &self.grid
+    }
+
+    /// Packed light indices referenced by the grid.
+    pub fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+
+    /// View-space bounds of each cluster.
+    pub fn cluster_bounds(&self) -> &[Aabb] {
+        &self.bounds
+    }
+
+    /// Index of the depth slice containing a view-space depth, for use by shaders and tests.
+    pub fn slice_for(&self, depth: f32) -> u32 {
+        let (near, far) = self.clip_planes;
+        let slice = ((depth / near).ln() / (far / near).ln() * self.config.slices as f32).floor();
+
+        (slice.max(0.0f32) as u32).min(self.config.slices - 1)
+    }
+
+    /// Rebuilds the cluster tables for a camera from the light manager's last update.
+    pub fn build(&mut self, cameras: &CameraManager, camera: Entity, lights: &LightManager) {
+        let projection = cameras.projection(camera);
+        let clip_planes = cameras.clip_planes(camera);
+
+        if projection != self.projection || clip_planes != self.clip_planes || self.bounds.len() != self.cluster_count() {
+            self.projection = projection;
+            self.clip_planes = clip_planes;
+            self.rebuild_bounds(cameras.depth_range(camera));
+        }
+
+        let view = cameras.view(camera);
+        let packed = lights.packed();
+        let first = lights.count(LightKind::Directional);
+
+        let spheres: Vec<(u32, Sphere)> = (first..packed.len()).map(|index| {
+            let light = &packed[index];
+            let position = Vector3::new(light.position_range[0], light.position_range[1], light.position_range[2]);
+
+            (index as u32, Sphere::new(scene::transform_point(&view, &position), light.position_range[3]))
+        }).collect();
+
+        self.grid.clear();
+        self.indices.clear();
+
+        for cluster in self.bounds.iter() {
+            let offset = self.indices.len() as u32;
+
+            for &(index, ref sphere) in spheres.iter() {
+                if cluster.intersects_sphere(sphere) {
+                    self.indices.push(index);
+                }
+            }
+
+            self.grid.push([offset, self.indices.len() as u32 - offset]);
+        }
+    }
+
+    /// Uploads the grid, index list and packed lights into immutable shader resource buffers.
+    pub fn upload<B>(&self, platform: &mut Platform<B>, lights: &LightManager) -> RenderResult<ClusterBuffers<B>> where B: backend::Backend {
+        use gfx::traits::FactoryExt;
+
+        // Empty buffers cannot be created, so pad the tables with a single unused entry.
+        let indices: &[u32] = if self.indices.is_empty() { &[0] } else { &self.indices };
+        let grid: &[[u32; 2]] = if self.grid.is_empty() { &[[0, 0]] } else { &self.grid };
+        let packed = lights.packed();
+        let empty = [PackedLight {
+            position_range: [0.0f32; 4],
+            direction_kind: [0.0f32; 4],
+            color_intensity: [0.0f32; 4],
+            cone_shadow: [0.0f32; 4],
+        }];
+        let packed: &[PackedLight] = if packed.is_empty() { &empty } else { packed };
+
+        let grid = platform.create_buffer_immutable(grid, gfx::buffer::Role::Constant, gfx::SHADER_RESOURCE)
+            .map_err(RenderError::BufferCreation)?;
+        let indices = platform.create_buffer_immutable(indices, gfx::buffer::Role::Constant, gfx::SHADER_RESOURCE)
+            .map_err(RenderError::BufferCreation)?;
+        let lights = platform.create_buffer_immutable(packed, gfx::buffer::Role::Constant, gfx::SHADER_RESOURCE)
+            .map_err(RenderError::BufferCreation)?;
+
+        Ok(ClusterBuffers { grid, indices, lights })
+    }
+
+    /// Recomputes view-space cluster boxes by unprojecting the tile corners.
+    fn rebuild_bounds(&mut self, depth_range: (f32, f32)) {
+        self.bounds.clear();
+
+        let inverse = match self.projection.try_inverse() {
+            Some(inverse) => inverse,
+            None => return
+        };
+
+        let unproject = |x: f32, y: f32, z: f32| {
+            let clip = [x, y, z, 1.0f32];
+            let mut out = [0.0f32; 4];
+
+            for c in 0..4 {
+                for r in 0..4 {
+                    out[c] += clip[r] * inverse[(r, c)];
+                }
+            }
+
+            Vector3::new(out[0] / out[3], out[1] / out[3], out[2] / out[3])
+        };
+
+        // Point on the line through an NDC position at a given distance in front of the camera.
+        let at_depth = |x: f32, y: f32, depth: f32| {
+            let near = unproject(x, y, depth_range.0);
+            let far = unproject(x, y, depth_range.1);
+            let t = (depth + near[2]) / (near[2] - far[2]);
+
+            near + (far - near) * t
+        };
+
+        let ClusterConfig { tiles_x, tiles_y, slices } = self.config;
+        let (near, far) = self.clip_planes;
+
+        for k in 0..slices {
+            let front = near * (far / near).powf(k as f32 / slices as f32);
+            let back = near * (far / near).powf((k + 1) as f32 / slices as f32);
+
+            for j in 0..tiles_y {
+                let y0 = -1.0f32 + 2.0f32 * j as f32 / tiles_y as f32;
+                let y1 = -1.0f32 + 2.0f32 * (j + 1) as f32 / tiles_y as f32;
+
+                for i in 0..tiles_x {
+                    let x0 = -1.0f32 + 2.0f32 * i as f32 / tiles_x as f32;
+                    let x1 = -1.0f32 + 2.0f32 * (i + 1) as f32 / tiles_x as f32;
+
+                    let first = at_depth(x0, y0, front);
+                    let mut aabb = Aabb::new(first, first);
+
+                    for &(x, y) in [(x0, y0), (x1, y0), (x0, y1), (x1, y1)].iter() {
+                        for &depth in [front, back].iter() {
+                            let point = at_depth(x, y, depth);
+                            aabb = aabb.union(&Aabb::new(point, point));
+                        }
+                    }
+
+                    self.bounds.push(aabb);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+    use system::camera::{Projection, Viewport};
+    use system::scene::SceneManager;
+    use render::backend::ClipSpace;
+
+    const CLIP: ClipSpace = ClipSpace { zero_to_one_depth: false, y_down: false };
+
+    fn setup() -> (SceneManager, CameraManager, LightManager) {
+        let mut scene = SceneManager::new();
+        let mut cameras = CameraManager::new();
+        let mut lights = LightManager::new();
+
+        cameras.create_camera(0, Projection::Perspective { fov_y: PI / 2.0f32 }, Viewport::new(0.0f32, 0.0f32, 160.0f32, 90.0f32));
+        cameras.set_clip_planes(0, 0.1f32, 100.0f32);
+        cameras.update(&scene, CLIP);
+
+        scene.create_transform(1);
+        scene.set_local_position(1, Vector3::new(0.0f32, 0.0f32, -5.0f32));
+        lights.create_light(1, LightKind::Point);
+        lights.set_range(1, 0.5f32);
+
+        lights.create_light(2, LightKind::Directional);
+        lights.update(&scene);
+
+        (scene, cameras, lights)
+    }
+
+    #[test]
+    fn assigning_lights() {
+        let (_, cameras, lights) = setup();
+        let mut builder = ClusterBuilder::new(ClusterConfig::default());
+
+        builder.build(&cameras, 0, &lights);
+
+        assert_eq!(builder.grid().len(), builder.cluster_count());
+
+        let slice = builder.slice_for(5.0f32) as usize;
+        let per_slice = 16 * 9;
+        let lit: Vec<usize> = (0..builder.cluster_count()).filter(|&c| builder.grid()[c][1] > 0).collect();
+
+        assert!(!lit.is_empty());
+        assert!(lit.iter().all(|&c| (c / per_slice) as i32 - slice as i32 <= 1 && slice as i32 - (c / per_slice) as i32 <= 1));
+
+        // Only the point light is clustered; it follows the directional light in the packed array.
+        assert!(builder.indices().iter().all(|&index| index == 1));
+    }
+
+    #[test]
+    fn building_deterministically() {
+        let (_, cameras, lights) = setup();
+        let mut first = ClusterBuilder::new(ClusterConfig::default());
+        let mut second = ClusterBuilder::new(ClusterConfig::default());
+
+        first.build(&cameras, 0, &lights);
+        second.build(&cameras, 0, &lights);
+        second.build(&cameras, 0, &lights);
+
+        assert_eq!(first.grid(), second.grid());
+        assert_eq!(first.indices(), second.indices());
+    }
+}
@@ -1,14 +1,572 @@
+use gfx;
+use crossbeam;
+use std::ops::Range;
+use std::slice;
+use system::entity::Entity;
+use render::backend;
+use render::culling::VisibleSet;
+use render::effect::Effect;
+use render::pipeline::Data;
 
-// NOTE: GBuffer, Lighting, Deferred, PostProcess, Interface
+/// Rendering stages, in the order their buckets are submitted.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Stage {
+    GBuffer,
+    Lighting,
+    Deferred,
+    PostProcess,
+    Interface,
+}
+
+/// Every stage in submission order.
+pub const STAGES: [Stage; 5] = [Stage::GBuffer, Stage::Lighting, Stage::Deferred, Stage::PostProcess, Stage::Interface];
+
+impl Stage {
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// Identifies an effect in the table passed to `submit`.
+pub type EffectId = u32;
+
+const LAYER_SHIFT: u64 = 56;
+const TRANSLUCENT_SHIFT: u64 = 55;
+const DEPTH_BITS: u64 = 24;
+const DEPTH_MASK: u64 = (1 << DEPTH_BITS) - 1;
+const MATERIAL_BITS: u64 = 31;
+const MATERIAL_MASK: u64 = (1 << MATERIAL_BITS) - 1;
+
+/// Packed 64-bit sort key for a draw.
+///
+/// From the most significant bit: an 8-bit view layer, a translucency bit, then 55 bits whose
+/// meaning depends on translucency. Opaque draws store a 31-bit material id above 24 bits of
+/// depth, grouping state changes first and drawing front to back within a material.
+/// Translucent draws store inverted depth above the material so they draw back to front.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct DrawKey(pub u64);
+
+impl DrawKey {
+    /// Key for an opaque draw. `depth` is normalized to [0, 1] between the camera clip planes.
+    pub fn opaque(layer: u8, material: u32, depth: f32) -> DrawKey {
+        DrawKey(((layer as u64) << LAYER_SHIFT)
+            | ((material as u64 & MATERIAL_MASK) << DEPTH_BITS)
+            | quantize_depth(depth))
+    }
+
+    /// Key for a translucent draw. `depth` is normalized to [0, 1] between the camera clip planes.
+    pub fn translucent(layer: u8, material: u32, depth: f32) -> DrawKey {
+        DrawKey(((layer as u64) << LAYER_SHIFT)
+            | (1 << TRANSLUCENT_SHIFT)
+            | ((DEPTH_MASK - quantize_depth(depth)) << MATERIAL_BITS)
+            | (material as u64 & MATERIAL_MASK))
+    }
+
+    pub fn layer(&self) -> u8 {
+        (self.0 >> LAYER_SHIFT) as u8
+    }
+
+    pub fn is_translucent(&self) -> bool {
+        (self.0 >> TRANSLUCENT_SHIFT) & 1 == 1
+    }
+
+    pub fn material(&self) -> u32 {
+        if self.is_translucent() {
+            (self.0 & MATERIAL_MASK) as u32
+        } else {
+            ((self.0 >> DEPTH_BITS) & MATERIAL_MASK) as u32
+        }
+    }
+}
+
+fn quantize_depth(depth: f32) -> u64 {
+    let clamped = depth.max(0.0f32).min(1.0f32);
+    (clamped * DEPTH_MASK as f32) as u64
+}
 
+/// Stable least-significant-digit radix sort of `keys`, writing the sorted permutation of
+/// indices into `order`.
+///
+/// Sorts eight bits per pass and skips passes in which every key shares the same digit, which
+/// is common for the layer and translucency bytes.
+pub fn radix_sort(keys: &[u64], order: &mut Vec<u32>, scratch: &mut Vec<u32>) {
+    order.clear();
+    order.extend(0..keys.len() as u32);
+
+    scratch.clear();
+    scratch.resize(keys.len(), 0);
+
+    for pass in 0..8 {
+        let shift = pass * 8;
+        let mut counts = [0usize; 256];
+
+        for &key in keys {
+            counts[((key >> shift) & 0xff) as usize] += 1;
+        }
+
+        if counts.iter().any(|&count| count == keys.len()) {
+            continue;
+        }
+
+        let mut offsets = [0usize; 256];
+        for digit in 1..256 {
+            offsets[digit] = offsets[digit - 1] + counts[digit - 1];
+        }
+
+        for &index in order.iter() {
+            let digit = ((keys[index as usize] >> shift) & 0xff) as usize;
+            scratch[offsets[digit]] = index;
+            offsets[digit] += 1;
+        }
+
+        ::std::mem::swap(order, scratch);
+    }
+}
+
+/// A collection of keyed commands that are sorted before submission.
 pub trait CommandBucket<K: Ord> {
+    type Command;
+
+    fn push(&mut self, key: K, command: Self::Command);
+    fn sort(&mut self);
+    fn clear(&mut self);
+    fn len(&self) -> usize;
+}
+
+/// Per-worker staging area for keyed commands.
+///
+/// Each recording thread owns one arena so insertion needs no synchronization. Arenas are merged
+/// into a bucket in worker order before sorting; since the sort is stable, commands with equal
+/// keys are submitted in the order of the entities they were recorded for.
+pub struct CommandArena<C> {
+    keys: Vec<u64>,
+    commands: Vec<C>,
+}
+
+impl<C> CommandArena<C> {
+    pub fn new() -> CommandArena<C> {
+        CommandArena {
+            keys: Vec::new(),
+            commands: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, key: DrawKey, command: C) {
+        self.keys.push(key.0);
+        self.commands.push(command);
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Keys and commands in insertion order.
+    pub fn into_parts(self) -> (Vec<u64>, Vec<C>) {
+        (self.keys, self.commands)
+    }
+}
+
+/// Splits `len` items into at most `workers` contiguous, nearly equal ranges in order.
+pub fn partition(len: usize, workers: usize) -> Vec<Range<usize>> {
+    let workers = workers.max(1).min(len.max(1));
+    let base = len / workers;
+    let remainder = len % workers;
+
+    let mut start = 0;
+    (0..workers).map(|worker| {
+        let end = start + base + if worker < remainder { 1 } else { 0 };
+        let range = start..end;
+        start = end;
+        range
+    }).collect()
+}
+
+/// Records commands for a visible set on several threads.
+///
+/// The set is partitioned with `partition` and `record` is invoked once per range on its own
+/// scoped thread with a fresh arena, the entities in the range and their depths. Arenas are
+/// returned in range order, ready to be merged into a bucket.
+pub fn record_parallel<C, F>(visible: &VisibleSet, workers: usize, record: F) -> Vec<CommandArena<C>>
+    where C: Send, F: Fn(&mut CommandArena<C>, &[Entity], &[f32]) + Sync {
+
+    let ranges = partition(visible.len(), workers);
+    let record = &record;
+
+    if ranges.len() == 1 {
+        let mut arena = CommandArena::new();
+        record(&mut arena, &visible.entities, &visible.depths);
+        return vec![arena];
+    }
+
+    crossbeam::scope(|scope| {
+        let handles: Vec<_> = ranges.into_iter().map(|range| {
+            let entities = &visible.entities[range.clone()];
+            let depths = &visible.depths[range];
+
+            scope.spawn(move || {
+                let mut arena = CommandArena::new();
+                record(&mut arena, entities, depths);
+                arena
+            })
+        }).collect();
+
+        handles.into_iter().map(|handle| handle.join()).collect()
+    })
+}
+
+/// Keyed commands sorted by key before submission.
+pub struct KeyedBucket<C> {
+    keys: Vec<u64>,
+    commands: Vec<C>,
+    order: Vec<u32>,
+    scratch: Vec<u32>,
+    sorted: bool,
+}
+
+impl<C> KeyedBucket<C> {
+    pub fn new() -> KeyedBucket<C> {
+        KeyedBucket {
+            keys: Vec::new(),
+            commands: Vec::new(),
+            order: Vec::new(),
+            scratch: Vec::new(),
+            sorted: true,
+        }
+    }
+
+    /// Appends the contents of per-worker arenas in the order given.
+    pub fn merge<I>(&mut self, arenas: I) where I: IntoIterator<Item = CommandArena<C>> {
+        for arena in arenas {
+            let (keys, commands) = arena.into_parts();
+
+            self.keys.extend(keys);
+            self.commands.extend(commands);
+            self.sorted = false;
+        }
+    }
+
+    /// Commands in submission order. Only meaningful after `sort`.
+    pub fn order(&self) -> &[u32] {
+        &self.order
+    }
+
+    /// Sorts the commands if needed and iterates over them in submission order.
+    pub fn sorted(&mut self) -> Sorted<C> {
+        if !self.sorted {
+            self.sort();
+        }
+
+        Sorted {
+            order: self.order.iter(),
+            commands: &self.commands,
+        }
+    }
+}
+
+impl<C> CommandBucket<DrawKey> for KeyedBucket<C> {
+    type Command = C;
+
+    fn push(&mut self, key: DrawKey, command: C) {
+        self.keys.push(key.0);
+        self.commands.push(command);
+        self.sorted = false;
+    }
+
+    fn sort(&mut self) {
+        radix_sort(&self.keys, &mut self.order, &mut self.scratch);
+        self.sorted = true;
+    }
+
+    fn clear(&mut self) {
+        self.keys.clear();
+        self.commands.clear();
+        self.order.clear();
+        self.sorted = true;
+    }
+
+    fn len(&self) -> usize {
+        self.commands.len()
+    }
+}
+
+/// Iterator over a keyed bucket's commands in submission order.
+pub struct Sorted<'a, C: 'a> {
+    order: slice::Iter<'a, u32>,
+    commands: &'a [C],
+}
+
+impl<'a, C> Iterator for Sorted<'a, C> {
+    type Item = &'a C;
+
+    fn next(&mut self) -> Option<&'a C> {
+        self.order.next().map(|&index| &self.commands[index as usize])
+    }
+}
+
+/// Draw of a slice with an effect and per-draw bindings.
+pub struct DrawCommand<B: backend::Backend> {
+    pub effect: EffectId,
+    pub slice: gfx::Slice<B::Resources>,
+    pub data: Data<B>,
+}
+
+/// Counters gathered while submitting a bucket.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SubmitStats {
+    /// Draw calls issued to the encoder.
+    pub draws: usize,
+    /// Commands folded into the previous draw instead of rebinding the same state.
+    pub skipped_binds: usize,
+}
+
+/// Bucket of draw commands for one rendering stage.
+pub struct DrawBucket<B: backend::Backend> {
+    stage: Stage,
+    commands: KeyedBucket<DrawCommand<B>>,
+}
+
+impl<B> DrawBucket<B> where B: backend::Backend {
+    pub fn new(stage: Stage) -> DrawBucket<B> {
+        DrawBucket {
+            stage,
+            commands: KeyedBucket::new(),
+        }
+    }
+
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    /// Appends the contents of per-worker arenas in the order given.
+    pub fn merge<I>(&mut self, arenas: I) where I: IntoIterator<Item = CommandArena<DrawCommand<B>>> {
+        self.commands.merge(arenas);
+    }
+
+    /// Commands in submission order. Only meaningful after `sort`.
+    pub fn order(&self) -> &[u32] {
+        self.commands.order()
+    }
+
+    /// Replays the sorted commands into an encoder, skipping redundant state changes.
+    ///
+    /// The encoder binds a pipeline and all of its data with every draw, so state is only
+    /// skipped by not drawing separately: a command with the same effect and bindings as the one
+    /// before it, whose slice continues that command's, is folded into the same draw.
+    pub fn submit<C>(&mut self, encoder: &mut gfx::Encoder<B::Resources, C>, effects: &[Effect<B>]) -> SubmitStats
+        where C: gfx::CommandBuffer<B::Resources> {
+
+        fold_draws(self.commands.sorted(), |command, slice| {
+            encoder.draw(slice, effects[command.effect as usize].pipeline(), &command.data);
+        })
+    }
+}
+
+impl<B> CommandBucket<DrawKey> for DrawBucket<B> where B: backend::Backend {
+    type Command = DrawCommand<B>;
+
+    fn push(&mut self, key: DrawKey, command: DrawCommand<B>) {
+        self.commands.push(key, command);
+    }
+
+    fn sort(&mut self) {
+        self.commands.sort();
+    }
+
+    fn clear(&mut self) {
+        self.commands.clear();
+    }
+
+    fn len(&self) -> usize {
+        self.commands.len()
+    }
+}
+
+/// Calls `draw` once per run of commands sharing an effect and bindings with contiguous slices,
+/// with the first command of the run and a slice covering all of them.
+fn fold_draws<'a, B, I, F>(commands: I, mut draw: F) -> SubmitStats
+    where B: backend::Backend + 'a, I: IntoIterator<Item = &'a DrawCommand<B>>, F: FnMut(&DrawCommand<B>, &gfx::Slice<B::Resources>) {
+
+    let mut stats = SubmitStats::default();
+    let mut pending: Option<(&DrawCommand<B>, gfx::Slice<B::Resources>)> = None;
+
+    for command in commands {
+        if let Some((ref first, ref mut slice)) = pending {
+            if first.effect == command.effect && first.data == command.data && continues(slice, &command.slice) {
+                slice.end = command.slice.end;
+                stats.skipped_binds += 1;
+                continue;
+            }
+        }
+
+        if let Some((first, slice)) = pending.take() {
+            draw(first, &slice);
+            stats.draws += 1;
+        }
+
+        pending = Some((command, command.slice.clone()));
+    }
+
+    if let Some((first, slice)) = pending {
+        draw(first, &slice);
+        stats.draws += 1;
+    }
+
+    stats
+}
 
+/// Whether `next` picks up where `slice` ends in the same buffers.
+fn continues<R>(slice: &gfx::Slice<R>, next: &gfx::Slice<R>) -> bool where R: gfx::Resources {
+    slice.end == next.start && slice.base_vertex == next.base_vertex && slice.instances == next.instances && slice.buffer == next.buffer
 }
 
-pub enum GBufferCommand {
-    
+/// One draw bucket per stage, submitted in stage order.
+pub struct StageBuckets<B: backend::Backend> {
+    buckets: Vec<DrawBucket<B>>,
 }
 
-pub struct GBufferCommandBucket {
+impl<B> StageBuckets<B> where B: backend::Backend {
+    pub fn new() -> StageBuckets<B> {
+        StageBuckets {
+            buckets: STAGES.iter().map(|&stage| DrawBucket::new(stage)).collect(),
+        }
+    }
+
+    pub fn bucket(&self, stage: Stage) -> &DrawBucket<B> {
+        &self.buckets[stage.index()]
+    }
+
+    pub fn bucket_mut(&mut self, stage: Stage) -> &mut DrawBucket<B> {
+        &mut self.buckets[stage.index()]
+    }
+
+    pub fn clear(&mut self) {
+        for bucket in self.buckets.iter_mut() {
+            bucket.clear();
+        }
+    }
+
+    /// Sorts and submits every stage's bucket in order.
+    pub fn submit<C>(&mut self, encoder: &mut gfx::Encoder<B::Resources, C>, effects: &[Effect<B>]) -> SubmitStats
+        where C: gfx::CommandBuffer<B::Resources> {
+
+        let mut stats = SubmitStats::default();
+
+        for bucket in self.buckets.iter_mut() {
+            let bucket_stats = bucket.submit(encoder, effects);
+            stats.draws += bucket_stats.draws;
+            stats.skipped_binds += bucket_stats.skipped_binds;
+        }
+
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use render::backend::OpenGL;
+
+    #[test]
+    fn packing_keys() {
+        let key = DrawKey::opaque(3, 42, 0.5f32);
+
+        assert_eq!(key.layer(), 3);
+        assert_eq!(key.material(), 42);
+        assert!(!key.is_translucent());
+
+        let key = DrawKey::translucent(3, 42, 0.5f32);
+        assert_eq!(key.material(), 42);
+        assert!(key.is_translucent());
+    }
+
+    #[test]
+    fn ordering_keys() {
+        // Opaque draws group by material, then front to back.
+        assert!(DrawKey::opaque(0, 1, 0.9f32) < DrawKey::opaque(0, 2, 0.1f32));
+        assert!(DrawKey::opaque(0, 1, 0.1f32) < DrawKey::opaque(0, 1, 0.9f32));
+
+        // Translucent draws follow opaque ones and run back to front.
+        assert!(DrawKey::opaque(0, 9, 1.0f32) < DrawKey::translucent(0, 0, 0.0f32));
+        assert!(DrawKey::translucent(0, 1, 0.9f32) < DrawKey::translucent(0, 1, 0.1f32));
+
+        // Layers dominate everything.
+        assert!(DrawKey::translucent(0, 1, 0.0f32) < DrawKey::opaque(1, 0, 0.0f32));
+    }
+
+    #[test]
+    fn radix_sorting() {
+        let keys = [DrawKey::opaque(1, 0, 0.0f32).0, 5, 3, 1 << 40, 3, 0];
+        let mut order = Vec::new();
+        let mut scratch = Vec::new();
+
+        radix_sort(&keys, &mut order, &mut scratch);
+
+        // Equal keys keep their submission order.
+        assert_eq!(order, vec![5, 2, 4, 1, 3, 0]);
+    }
+
+    #[test]
+    fn partitioning_work() {
+        assert_eq!(partition(10, 3), vec![0..4, 4..7, 7..10]);
+        assert_eq!(partition(2, 4), vec![0..1, 1..2]);
+        assert_eq!(partition(0, 4), vec![0..0]);
+    }
+
+    #[test]
+    fn recording_in_parallel() {
+        let visible = VisibleSet {
+            entities: (0..100).collect(),
+            depths: (0..100).map(|a| (a % 7) as f32 / 7.0f32).collect(),
+        };
+
+        let record = |arena: &mut CommandArena<Entity>, entities: &[Entity], depths: &[f32]| {
+            for (&entity, &depth) in entities.iter().zip(depths) {
+                arena.push(DrawKey::opaque(0, entity % 3, depth), entity);
+            }
+        };
+
+        let sorted = |arenas: Vec<CommandArena<Entity>>| {
+            let mut keys = Vec::new();
+            let mut commands = Vec::new();
+
+            for arena in arenas {
+                let (arena_keys, arena_commands) = arena.into_parts();
+                keys.extend(arena_keys);
+                commands.extend(arena_commands);
+            }
+
+            let (mut order, mut scratch) = (Vec::new(), Vec::new());
+            radix_sort(&keys, &mut order, &mut scratch);
+            order.iter().map(|&index| commands[index as usize]).collect::<Vec<Entity>>()
+        };
+
+        let serial = sorted(record_parallel(&visible, 1, &record));
+        let parallel = sorted(record_parallel(&visible, 4, &record));
+
+        assert_eq!(serial.len(), 100);
+        assert_eq!(serial, parallel);
+    }
+
+    #[test]
+    fn folding_redundant_draws() {
+        let command = |effect: EffectId, start: u32, end: u32| -> DrawCommand<OpenGL> {
+            DrawCommand {
+                effect,
+                slice: gfx::Slice { start, end, base_vertex: 0, instances: None, buffer: gfx::IndexBuffer::Auto },
+                data: Data::default(),
+            }
+        };
+
+        let commands = vec![command(0, 0, 6), command(0, 6, 12), command(0, 20, 26), command(1, 26, 32), command(1, 32, 38)];
+        let mut drawn = Vec::new();
+
+        let stats = fold_draws(commands.iter(), |command, slice| drawn.push((command.effect, slice.start, slice.end)));
 
+        assert_eq!(drawn, vec![(0, 0, 12), (0, 20, 26), (1, 26, 38)]);
+        assert_eq!(stats, SubmitStats { draws: 3, skipped_binds: 2 });
+    }
 }
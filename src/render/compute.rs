@@ -0,0 +1,153 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use render::error::{RenderError, RenderResult};
+use render::reflect::{ResourceKind, ShaderReflection, Stage};
+
+/// Describes a compute program and the resources it binds by name.
+///
+/// Storage buffers and images are listed as unordered access resources, separately from uniform
+/// blocks and sampled textures. Descriptions cannot be built into effects yet: gfx has neither a
+/// compute shader stage nor a dispatch command, and no backend offers either.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ComputeBuilder {
+    code: Cow<'static, [u8]>,
+    files: Vec<PathBuf>,
+    constants: Vec<String>,
+    textures: Vec<String>,
+    unordered: Vec<String>,
+    local_size: [u32; 3],
+}
+
+impl ComputeBuilder {
+    /// Describes a compute program from its code, binding nothing until told to.
+    pub fn new<S>(code: S) -> ComputeBuilder where S: Into<Cow<'static, [u8]>> {
+        ComputeBuilder {
+            code: code.into(),
+            files: Vec::new(),
+            constants: Vec::new(),
+            textures: Vec::new(),
+            unordered: Vec::new(),
+            local_size: [1, 1, 1],
+        }
+    }
+
+    pub fn from_file<P>(path: P) -> RenderResult<ComputeBuilder> where P: AsRef<Path> {
+        let path = path.as_ref();
+        let mut code = Vec::new();
+
+        File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut code))
+            .map_err(|e| RenderError::ShaderLoad(path.display().to_string(), e))?;
+
+        let mut builder = ComputeBuilder::new(code);
+        builder.files.push(path.to_path_buf());
+        Ok(builder)
+    }
+
+    /// Describes a SPIR-V compute program, with bindings and workgroup size reflected from it.
+    pub fn from_spirv(code: Vec<u8>) -> RenderResult<ComputeBuilder> {
+        let reflection = ShaderReflection::parse(&code)?;
+        if reflection.stage != Stage::Compute {
+            return Err(RenderError::ShaderReflection(format!("expected a compute shader, not {:?}", reflection.stage)));
+        }
+
+        let mut builder = ComputeBuilder::new(code);
+
+        for block in reflection.uniform_blocks.iter() {
+            builder = builder.with_constant(&block.name);
+        }
+
+        for resource in reflection.resources.iter() {
+            builder = match resource.kind {
+                ResourceKind::StorageBuffer | ResourceKind::StorageImage => builder.with_unordered(&resource.name),
+                ResourceKind::Texture | ResourceKind::SampledTexture => builder.with_texture(&resource.name),
+                ResourceKind::Sampler => builder
+            };
+        }
+
+        if let Some(local_size) = reflection.local_size {
+            builder = builder.with_local_size(local_size);
+        }
+
+        Ok(builder)
+    }
+
+    pub fn with_constant(mut self, name: &str) -> ComputeBuilder {
+        self.constants.push(name.to_string());
+        self
+    }
+
+    pub fn with_texture(mut self, name: &str) -> ComputeBuilder {
+        self.textures.push(name.to_string());
+        self
+    }
+
+    /// Binds a storage buffer or image, read and written through an unordered access view.
+    pub fn with_unordered(mut self, name: &str) -> ComputeBuilder {
+        self.unordered.push(name.to_string());
+        self
+    }
+
+    /// Sets the number of invocations per workgroup, as declared by the shader.
+    pub fn with_local_size(mut self, local_size: [u32; 3]) -> ComputeBuilder {
+        self.local_size = local_size;
+        self
+    }
+
+    pub fn code(&self) -> &[u8] {
+        &self.code
+    }
+
+    /// Shader file the program was loaded from, if any.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    pub fn constants(&self) -> &[String] {
+        &self.constants
+    }
+
+    pub fn textures(&self) -> &[String] {
+        &self.textures
+    }
+
+    pub fn unordered(&self) -> &[String] {
+        &self.unordered
+    }
+
+    pub fn local_size(&self) -> [u32; 3] {
+        self.local_size
+    }
+
+    /// Workgroups needed to cover a number of invocations along each axis.
+    pub fn groups(&self, invocations: [u32; 3]) -> [u32; 3] {
+        groups(self.local_size, invocations)
+    }
+}
+
+fn groups(local_size: [u32; 3], invocations: [u32; 3]) -> [u32; 3] {
+    let cover = |axis: usize| {
+        let size = local_size[axis].max(1);
+        (invocations[axis] + size - 1) / size
+    };
+
+    [cover(0), cover(1), cover(2)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counting_groups() {
+        assert_eq!(groups([64, 1, 1], [1000, 1, 1]), [16, 1, 1]);
+        assert_eq!(groups([8, 8, 1], [1920, 1080, 1]), [240, 135, 1]);
+        assert_eq!(groups([0, 1, 1], [3, 0, 1]), [3, 0, 1]);
+
+        let builder = ComputeBuilder::new(&b"void main() {}"[..]).with_unordered("particles").with_local_size([64, 1, 1]);
+        assert_eq!(builder.groups([100, 1, 1]), [2, 1, 1]);
+        assert_eq!(builder.unordered(), &["particles".to_string()][..]);
+    }
+}
@@ -0,0 +1,206 @@
+use std::{iter, slice};
+use nalgebra::{Matrix4, Vector3};
+use system::entity::Entity;
+use system::bounds::{BoundsManager, Frustum};
+use system::scene::{self, SceneManager};
+
+/// Entities that survived culling along with their view-space depth.
+///
+/// Depths are distances in front of the camera. Sort keys only hold depths between zero and one,
+/// so call `normalize_depths` with the camera's clip planes before packing them into keys.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VisibleSet {
+    pub entities: Vec<Entity>,
+    pub depths: Vec<f32>,
+}
+
+impl VisibleSet {
+    pub fn new() -> VisibleSet {
+        VisibleSet::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.entities.clear();
+        self.depths.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    pub fn iter(&self) -> iter::Zip<iter::Cloned<slice::Iter<Entity>>, iter::Cloned<slice::Iter<f32>>> {
+        self.entities.iter().cloned().zip(self.depths.iter().cloned())
+    }
+
+    /// Maps depths between the near and far planes linearly onto zero to one, ready for
+    /// `DrawKey`. Depths outside the planes are clamped.
+    pub fn normalize_depths(&mut self, near: f32, far: f32) {
+        assert!(far > near, "far plane {} must lie beyond near plane {}", far, near);
+
+        for depth in self.depths.iter_mut() {
+            *depth = ((*depth - near) / (far - near)).max(0.0f32).min(1.0f32);
+        }
+    }
+
+    fn push(&mut self, entity: Entity, depth: f32) {
+        self.entities.push(entity);
+        self.depths.push(depth);
+    }
+}
+
+/// Tests every bounded entity against a frustum.
+///
+/// The bounding sphere is tested first as a cheap rejection; survivors are then tested with
+/// their box.
+pub fn cull(frustum: &Frustum, view: &Matrix4<f32>, bounds: &BoundsManager, visible: &mut VisibleSet) {
+    for &entity in bounds.entities() {
+        test(frustum, view, bounds, entity, visible);
+    }
+}
+
+/// Culls by walking the transform hierarchy, rejecting whole subtrees whose hierarchical bounds
+/// fall outside the frustum.
+///
+/// Requires hierarchical bounds to be enabled on the bounds manager to reject anything early;
+/// otherwise every bounded entity is tested individually. Bounded entities without a transform
+/// are tested on their own.
+pub fn cull_hierarchy(frustum: &Frustum, view: &Matrix4<f32>, scene: &SceneManager, bounds: &BoundsManager, visible: &mut VisibleSet) {
+    for root in scene.roots() {
+        descend(frustum, view, scene, bounds, root, visible);
+    }
+
+    for &entity in bounds.entities() {
+        if !scene.has_transform(entity) {
+            test(frustum, view, bounds, entity, visible);
+        }
+    }
+}
+
+fn descend(frustum: &Frustum, view: &Matrix4<f32>, scene: &SceneManager, bounds: &BoundsManager, entity: Entity, visible: &mut VisibleSet) {
+    if let Some(enclosing) = bounds.hierarchy_aabb(entity) {
+        if !frustum.intersects_aabb(&enclosing) {
+            return;
+        }
+    }
+
+    if bounds.has_bounds(entity) {
+        test(frustum, view, bounds, entity, visible);
+    }
+
+    for child in scene.children(entity) {
+        descend(frustum, view, scene, bounds, child, visible);
+    }
+}
+
+fn test(frustum: &Frustum, view: &Matrix4<f32>, bounds: &BoundsManager, entity: Entity, visible: &mut VisibleSet) {
+    let sphere = bounds.world_sphere(entity);
+    if !frustum.intersects_sphere(&sphere) {
+        return;
+    }
+
+    if !frustum.intersects_aabb(&bounds.world_aabb(entity)) {
+        return;
+    }
+
+    visible.push(entity, view_depth(view, &sphere.center));
+}
+
+/// Depth of a world-space point in front of a camera view matrix.
+///
+/// Cameras look down -Z, so this is the negated view-space Z.
+pub fn view_depth(view: &Matrix4<f32>, point: &Vector3<f32>) -> f32 {
+    -scene::transform_point(view, point)[2]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+    use system::bounds::Aabb;
+    use system::camera::{CameraManager, Projection, Viewport};
+    use render::backend::ClipSpace;
+    use render::command::DrawKey;
+
+    const CLIP: ClipSpace = ClipSpace { zero_to_one_depth: true, y_down: false };
+
+    fn setup() -> (SceneManager, BoundsManager, CameraManager) {
+        let mut scene = SceneManager::new();
+        let mut bounds = BoundsManager::new();
+        let mut cameras = CameraManager::new();
+
+        let camera = 0 as Entity;
+        scene.create_transform(camera);
+        cameras.create_camera(camera, Projection::Perspective { fov_y: PI / 2.0f32 }, Viewport::new(0.0f32, 0.0f32, 100.0f32, 100.0f32));
+        cameras.set_clip_planes(camera, 0.1f32, 100.0f32);
+        cameras.update(&scene, CLIP);
+
+        // One entity ahead of the camera, one behind it and one beyond the far plane.
+        for &(entity, z) in [(1, -10.0f32), (2, 10.0f32), (3, -200.0f32)].iter() {
+            scene.create_transform(entity);
+            scene.set_local_position(entity, Vector3::new(0.0f32, 0.0f32, z));
+            bounds.create_bounds(entity, Aabb::from_center(Vector3::from_element(0.0f32), Vector3::from_element(1.0f32)));
+        }
+
+        bounds.update(&mut scene);
+
+        (scene, bounds, cameras)
+    }
+
+    #[test]
+    fn culling_entities() {
+        let (_, bounds, cameras) = setup();
+        let mut visible = VisibleSet::new();
+
+        cull(&cameras.frustum(0), &cameras.view(0), &bounds, &mut visible);
+
+        assert_eq!(visible.entities, vec![1]);
+        assert_eq!(visible.depths, vec![10.0f32]);
+    }
+
+    #[test]
+    fn culling_hierarchies() {
+        let (mut scene, mut bounds, cameras) = setup();
+
+        // Parent the visible entity under the one behind the camera; its subtree box still
+        // reaches into the frustum, so it must not be rejected.
+        scene.link(1, 2);
+        bounds.set_hierarchical(true);
+        bounds.update(&mut scene);
+
+        let mut visible = VisibleSet::new();
+        cull_hierarchy(&cameras.frustum(0), &cameras.view(0), &scene, &bounds, &mut visible);
+
+        assert_eq!(visible.entities, vec![1]);
+    }
+
+    #[test]
+    fn normalizing_depths() {
+        let (mut scene, mut bounds, cameras) = setup();
+
+        // A second entity behind the first, still inside the far plane.
+        scene.create_transform(4);
+        scene.set_local_position(4, Vector3::new(0.0f32, 0.0f32, -50.0f32));
+        bounds.create_bounds(4, Aabb::from_center(Vector3::from_element(0.0f32), Vector3::from_element(1.0f32)));
+        bounds.update(&mut scene);
+
+        let mut visible = VisibleSet::new();
+        cull(&cameras.frustum(0), &cameras.view(0), &bounds, &mut visible);
+
+        let (near, far) = cameras.clip_planes(0);
+        visible.normalize_depths(near, far);
+
+        assert_eq!(visible.entities, vec![1, 4]);
+        assert!(visible.depths.iter().all(|&depth| depth > 0.0f32 && depth < 1.0f32));
+
+        let opaque: Vec<DrawKey> = visible.depths.iter().map(|&depth| DrawKey::opaque(0, 0, depth)).collect();
+        let translucent: Vec<DrawKey> = visible.depths.iter().map(|&depth| DrawKey::translucent(0, 0, depth)).collect();
+
+        // Near to far for opaque draws, far to near for translucent ones.
+        assert!(opaque[0] < opaque[1]);
+        assert!(translucent[0] > translucent[1]);
+    }
+}
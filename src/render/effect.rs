@@ -1,61 +1,89 @@
 use gfx::{self, Primitive, ShaderSet};
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use gfx::shade;
 use gfx::preset::depth::{LESS_EQUAL_TEST, LESS_EQUAL_WRITE, PASS_TEST};
 use gfx::pso::PipelineState;
-use gfx::state::{Depth, Rasterizer};
+use gfx::state::{Blend, ColorMask, Comparison, Depth, Offset, Rasterizer, Stencil, StencilOp};
 use core::platform::Platform;
 
 use render::pipeline::{Meta, Data, Init};
+use render::reflect::{PipelineLayout, ShaderReflection};
 use render::error::{RenderError, RenderResult};
 use render::backend;
+use render::target::TargetRegistry;
+
+/// Shader code either compiled into the binary or loaded at runtime.
+type ShaderCode = Cow<'static, [u8]>;
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 enum ProgramSource {
-    Minimal(&'static [u8], &'static [u8]),
-    Geometry(&'static [u8], &'static [u8], &'static [u8]),
-    Tessellation(&'static [u8], &'static [u8], &'static [u8])
+    Minimal(ShaderCode, ShaderCode),
+    Geometry(ShaderCode, ShaderCode, ShaderCode),
+    Tessellation(ShaderCode, ShaderCode, ShaderCode, ShaderCode)
 }
 
 impl ProgramSource {
+    /// Reads one shader per stage, in pipeline order: vertex, then hull and domain or geometry,
+    /// then pixel.
+    fn load(paths: &[PathBuf]) -> RenderResult<ProgramSource> {
+        let mut stages = Vec::with_capacity(paths.len());
+
+        for path in paths.iter() {
+            stages.push(Cow::Owned(read_shader(path)?));
+        }
+
+        let mut stages = stages.into_iter();
+        let mut next = || stages.next().unwrap();
+
+        Ok(match paths.len() {
+            2 => ProgramSource::Minimal(next(), next()),
+            3 => ProgramSource::Geometry(next(), next(), next()),
+            4 => ProgramSource::Tessellation(next(), next(), next(), next()),
+            count => panic!("programs have two to four stages, not {}", count)
+        })
+    }
+
     pub fn compile<B>(&self, platform: &mut Platform<B>) -> RenderResult<gfx::ShaderSet<B::Resources>> where B: backend::Backend {
         use gfx::Factory;
-        use gfx::traits::FactoryExt;
 
         match *self {
             ProgramSource::Minimal(ref vertex_src, ref pixel_src) => {
                 let vertex = platform.create_shader_vertex(vertex_src)
-                    .map_err(|e| shade::ProgramError::Vertex(e));
+                    .map_err(|e| RenderError::ProgramCreation(shade::ProgramError::Vertex(e)))?;
 
                 let pixel = platform.create_shader_pixel(pixel_src)
-                    .map_err(|e| shade::ProgramError::Pixel(e));
+                    .map_err(|e| RenderError::ProgramCreation(shade::ProgramError::Pixel(e)))?;
 
                 Ok(ShaderSet::Simple(vertex, pixel))
             },
             ProgramSource::Geometry(ref vertex_src, ref geometry_src, ref pixel_src) => {
                 let vertex = platform.create_shader_vertex(vertex_src)
-                    .map_err(|e| shade::ProgramError::Vertex(e));
+                    .map_err(|e| RenderError::ProgramCreation(shade::ProgramError::Vertex(e)))?;
 
                 let geometry = platform.create_shader_geometry(geometry_src)
-                    .map_err(|e| shade::ProgramError::Geometry(e));
+                    .map_err(|e| RenderError::ProgramCreation(shade::ProgramError::Geometry(e)))?;
 
                 let pixel = platform.create_shader_pixel(pixel_src)
-                    .map_err(|e| shade::ProgramError::Pixel(e));
+                    .map_err(|e| RenderError::ProgramCreation(shade::ProgramError::Pixel(e)))?;
 
                 Ok(ShaderSet::Geometry(vertex, geometry, pixel))
             },
             ProgramSource::Tessellation(ref vertex_src, ref hull_src, ref domain_src, ref pixel_src) => {
                 let vertex = platform.create_shader_vertex(vertex_src)
-                    .map_err(|e| shade::ProgramError::Vertex(e));
+                    .map_err(|e| RenderError::ProgramCreation(shade::ProgramError::Vertex(e)))?;
 
                 let hull = platform.create_shader_hull(hull_src)
-                    .map_err(|e| shade::ProgramError::Hull(e));
+                    .map_err(|e| RenderError::ProgramCreation(shade::ProgramError::Hull(e)))?;
 
                 let domain = platform.create_shader_domain(domain_src)
-                    .map_err(|e| shade::ProgramError::Domain(e));
+                    .map_err(|e| RenderError::ProgramCreation(shade::ProgramError::Domain(e)))?;
 
                 let pixel = platform.create_shader_pixel(pixel_src)
-                    .map_err(|e| shade::ProgramError::Pixel(e));
+                    .map_err(|e| RenderError::ProgramCreation(shade::ProgramError::Pixel(e)))?;
 
                 Ok(ShaderSet::Tessellated(vertex, hull, domain, pixel))
             }
@@ -66,15 +94,18 @@ impl ProgramSource {
 /// Describes a rendering effect.
 ///
 /// An effect is constituted by a pipeline state configuration along with the data necessary
-/// to execute the pipeline.
+/// to execute the pipeline. Effects compare and hash by the description they were built from,
+/// so effects with identical programs and state are interchangeable.
 #[derive(Derivative)]
-#[derivative(Clone, Debug, Eq, Hash, PartialEq)]
+#[derivative(Clone(bound = ""), Debug(bound = ""), Eq(bound = ""), Hash(bound = ""), PartialEq(bound = ""))]
 pub struct Effect<B: backend::Backend> {
+    #[derivative(Hash = "ignore", PartialEq = "ignore")]
     pipeline: PipelineState<B::Resources, Meta<B>>,
-    #[derivative(Hash = "ignore")]
+    #[derivative(Hash = "ignore", PartialEq = "ignore")]
     pso_data: Data<B>,
-    #[derivative(Hash = "ignore")]
+    #[derivative(Hash = "ignore", PartialEq = "ignore")]
     samplers: HashMap<String, B::Sampler>,
+    description: EffectBuilder<B>,
 }
 
 impl<B> Effect<B> where B: backend::Backend {
@@ -83,16 +114,70 @@ impl<B> Effect<B> where B: backend::Backend {
 
         EffectBuilder::new_minimal(vertex_src, pixel_src)
     }
+
+    pub fn pipeline(&self) -> &PipelineState<B::Resources, Meta<B>> {
+        &self.pipeline
+    }
+
+    /// The builder this effect was built from.
+    pub fn description(&self) -> &EffectBuilder<B> {
+        &self.description
+    }
+
+    pub fn data(&self) -> &Data<B> {
+        &self.pso_data
+    }
+
+    pub fn data_mut(&mut self) -> &mut Data<B> {
+        &mut self.pso_data
+    }
+
+    /// Reloads the shader files the effect was built from and swaps in the new pipeline state.
+    ///
+    /// On failure the error is returned and the effect keeps its previous program. Bound data is
+    /// kept either way. Effects built from generated code have no files to read again; they are
+    /// reloaded through whatever generated them, such as `Permutations::reload`.
+    pub fn reload(&mut self, platform: &mut Platform<B>) -> RenderResult<()> {
+        let mut description = self.description.clone();
+        description.reload_program()?;
+
+        self.rebuild(platform, description)
+    }
+
+    /// Builds a new description and swaps in its pipeline state, keeping bound data.
+    ///
+    /// On failure the error is returned and the effect is left as it was.
+    pub fn rebuild(&mut self, platform: &mut Platform<B>, description: EffectBuilder<B>) -> RenderResult<()> {
+        let rebuilt = description.build(platform)?;
+        self.pipeline = rebuilt.pipeline;
+        self.description = rebuilt.description;
+
+        Ok(())
+    }
+
+    /// Binds named targets from a registry to the effect's color outputs, given as output and
+    /// target name pairs, and depth target.
+    pub fn bind_targets(&mut self, targets: &TargetRegistry<B>, colors: &[(&str, &str)], depth: Option<&str>) -> RenderResult<()> {
+        targets.bind(&mut self.pso_data, colors, depth)
+    }
 }
 
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+/// Collects the program and fixed-function state of an effect before it is built.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""), Debug(bound = ""), Eq(bound = ""), Hash(bound = ""), PartialEq(bound = ""))]
 pub struct EffectBuilder<B: backend::Backend> {
     initializer: Init<'static, B>,
     depth_state: Depth,
+    stencil: Stencil,
     primitive: Primitive,
     program: ProgramSource,
+    files: Vec<PathBuf>,
+    dependencies: Vec<PathBuf>,
+    unknown_targets: Vec<String>,
+    layout: Option<PipelineLayout>,
     rasterizer: Rasterizer,
+    #[derivative(Hash = "ignore")]
     samplers: HashMap<String, B::Sampler>,
 }
 
@@ -101,8 +186,13 @@ impl<B> Default for EffectBuilder<B> where B: backend::Backend {
         EffectBuilder {
             initializer: Init::default(),
             depth_state: LESS_EQUAL_WRITE,
+            stencil: Stencil::new(Comparison::Always, 0, (StencilOp::Keep, StencilOp::Keep, StencilOp::Keep)),
             primitive: Primitive::TriangleList,
-            program: ProgramSource::Simple("".as_bytes(), "".as_bytes()),
+            program: ProgramSource::Minimal(Cow::Borrowed(&[]), Cow::Borrowed(&[])),
+            files: Vec::new(),
+            dependencies: Vec::new(),
+            unknown_targets: Vec::new(),
+            layout: None,
             rasterizer: Rasterizer::new_fill().with_cull_back(),
             samplers: HashMap::default()
         }
@@ -116,8 +206,302 @@ impl<B> EffectBuilder<B> where B: backend::Backend {
         let (vertex_src, pixel_src) = (vertex_src.into(), pixel_src.into());
 
         EffectBuilder {
-            program: ProgramSource::Minimal(vertex_src, pixel_src)
+            program: ProgramSource::Minimal(Cow::Borrowed(vertex_src), Cow::Borrowed(pixel_src)),
+            .. Default::default()
+        }
+    }
+
+    /// Describes an effect from vertex and pixel shader sources generated at runtime.
+    pub fn from_code(vertex_src: Vec<u8>, pixel_src: Vec<u8>) -> EffectBuilder<B> {
+        EffectBuilder {
+            program: ProgramSource::Minimal(Cow::Owned(vertex_src), Cow::Owned(pixel_src)),
             .. Default::default()
         }
     }
+
+    /// Describes an effect from SPIR-V vertex and pixel shaders, deriving its initializer from
+    /// the shaders' interfaces.
+    pub fn from_spirv(vertex_src: Vec<u8>, pixel_src: Vec<u8>) -> RenderResult<EffectBuilder<B>> {
+        let layout = PipelineLayout::new(&[ShaderReflection::parse(&vertex_src)?, ShaderReflection::parse(&pixel_src)?])?;
+
+        Ok(EffectBuilder::from_code(vertex_src, pixel_src).with_layout(layout))
+    }
+
+    /// Describes an effect whose vertex and pixel shaders are loaded from disk.
+    pub fn from_files<P>(vertex_path: P, pixel_path: P) -> RenderResult<EffectBuilder<B>> where P: AsRef<Path> {
+        EffectBuilder::from_paths(vec![vertex_path.as_ref().to_path_buf(), pixel_path.as_ref().to_path_buf()])
+    }
+
+    /// Describes an effect whose vertex, geometry and pixel shaders are loaded from disk.
+    pub fn from_geometry_files<P>(vertex_path: P, geometry_path: P, pixel_path: P) -> RenderResult<EffectBuilder<B>> where P: AsRef<Path> {
+        EffectBuilder::from_paths(vec![vertex_path.as_ref().to_path_buf(), geometry_path.as_ref().to_path_buf(), pixel_path.as_ref().to_path_buf()])
+    }
+
+    /// Describes an effect whose vertex, hull, domain and pixel shaders are loaded from disk.
+    pub fn from_tessellation_files<P>(vertex_path: P, hull_path: P, domain_path: P, pixel_path: P) -> RenderResult<EffectBuilder<B>> where P: AsRef<Path> {
+        EffectBuilder::from_paths(vec![vertex_path.as_ref().to_path_buf(), hull_path.as_ref().to_path_buf(),
+                                       domain_path.as_ref().to_path_buf(), pixel_path.as_ref().to_path_buf()])
+    }
+
+    fn from_paths(files: Vec<PathBuf>) -> RenderResult<EffectBuilder<B>> {
+        Ok(EffectBuilder {
+            program: ProgramSource::load(&files)?,
+            files,
+            .. Default::default()
+        })
+    }
+
+    /// Shader files the program was loaded from, empty if it was compiled in.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Files besides the shader files whose changes make the effect stale, such as the sources
+    /// generated code was produced from.
+    pub fn dependencies(&self) -> &[PathBuf] {
+        &self.dependencies
+    }
+
+    /// Replaces the files the effect depends on besides its shader files.
+    pub fn with_dependencies(mut self, dependencies: Vec<PathBuf>) -> EffectBuilder<B> {
+        self.dependencies = dependencies;
+        self
+    }
+
+    /// Replaces the program with generated vertex and pixel shader sources, keeping all other
+    /// state.
+    pub fn with_program(mut self, vertex_src: Vec<u8>, pixel_src: Vec<u8>) -> EffectBuilder<B> {
+        self.program = ProgramSource::Minimal(Cow::Owned(vertex_src), Cow::Owned(pixel_src));
+        self.files.clear();
+        self
+    }
+
+    /// Reads the shader files again. Does nothing for compiled in programs.
+    pub fn reload_program(&mut self) -> RenderResult<()> {
+        if !self.files.is_empty() {
+            self.program = ProgramSource::load(&self.files)?;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the initializer naming the shader resources the effect binds.
+    pub fn with_initializer(mut self, initializer: Init<'static, B>) -> EffectBuilder<B> {
+        self.initializer = initializer;
+        self.layout = None;
+        self
+    }
+
+    /// Binds the shader resources named by a reflected layout in place of the initializer.
+    pub fn with_layout(mut self, layout: PipelineLayout) -> EffectBuilder<B> {
+        self.layout = Some(layout);
+        self
+    }
+
+    pub fn with_depth_state(mut self, depth_state: Depth) -> EffectBuilder<B> {
+        self.depth_state = depth_state;
+        self
+    }
+
+    pub fn with_rasterizer(mut self, rasterizer: Rasterizer) -> EffectBuilder<B> {
+        self.rasterizer = rasterizer;
+        self
+    }
+
+    pub fn with_primitive(mut self, primitive: Primitive) -> EffectBuilder<B> {
+        self.primitive = primitive;
+        self
+    }
+
+    /// Sets how a color target blends with what is already there. The target must have been
+    /// named by the initializer, typed or raw; otherwise `build` fails.
+    pub fn with_blend(mut self, target: &str, blend: Blend) -> EffectBuilder<B> {
+        let found = match self.color_target(target) {
+            Some(ColorTarget::Typed(_, slot)) => { *slot = blend; true },
+            Some(ColorTarget::Raw(_, slot)) => { *slot = Some(blend); true },
+            None => false
+        };
+
+        if !found {
+            self.unknown_targets.push(target.to_string());
+        }
+
+        self
+    }
+
+    /// Sets which channels of a color target are written. The target must have been named by
+    /// the initializer, typed or raw; otherwise `build` fails.
+    pub fn with_color_mask(mut self, target: &str, mask: ColorMask) -> EffectBuilder<B> {
+        let found = match self.color_target(target) {
+            Some(ColorTarget::Typed(slot, _)) | Some(ColorTarget::Raw(slot, _)) => { *slot = mask; true },
+            None => false
+        };
+
+        if !found {
+            self.unknown_targets.push(target.to_string());
+        }
+
+        self
+    }
+
+    /// Sets the stencil test and operations, applied when the effect has a depth target.
+    pub fn with_stencil(mut self, stencil: Stencil) -> EffectBuilder<B> {
+        self.stencil = stencil;
+        self
+    }
+
+    /// Offsets rasterized depth by a slope-scaled factor plus a constant number of units.
+    pub fn with_depth_bias(mut self, slope: i32, units: i32) -> EffectBuilder<B> {
+        self.rasterizer.offset = Some(Offset(slope, units));
+        self
+    }
+
+    /// Compiles and links the program and creates the pipeline state for it.
+    ///
+    /// The builder's depth and stencil state replace those in the initializer's depth target, if
+    /// any. Every variable the initializer and program disagree on is reported at once. Blend
+    /// or mask state set for a color target the initializer does not name fails the build.
+    pub fn build(&self, platform: &mut Platform<B>) -> RenderResult<Effect<B>> {
+        use gfx::Factory;
+
+        if let Some(target) = self.unknown_targets.first() {
+            return Err(RenderError::NoSuchTarget(target.clone()));
+        }
+
+        let shaders = self.program.compile(platform)?;
+        let program = platform.create_program(&shaders)
+            .map_err(|e| RenderError::ProgramCreation(shade::ProgramError::Link(e)))?;
+
+        let pipeline = match self.layout {
+            Some(ref layout) => {
+                let attributes = layout.attributes();
+                self.create_pipeline(platform, &program, layout.initializer(&attributes))?
+            },
+            None => self.create_pipeline(platform, &program, self.initializer.clone())?
+        };
+
+        Ok(Effect {
+            pipeline,
+            pso_data: Data::default(),
+            samplers: self.samplers.clone(),
+            description: self.clone(),
+        })
+    }
+
+    fn create_pipeline<'d>(&self, platform: &mut Platform<B>, program: &gfx::handle::Program<B::Resources>, mut initializer: Init<'d, B>)
+        -> RenderResult<PipelineState<B::Resources, Meta<B>>> {
+
+        use gfx::traits::FactoryExt;
+
+        if let Some(ref mut target) = initializer.depth_target {
+            *target = (self.depth_state, self.stencil);
+        }
+
+        initializer.check(program.get_info()).map_err(RenderError::Binding)?;
+
+        platform.create_pipeline_from_program(program, self.primitive, self.rasterizer, initializer)
+            .map_err(|e| RenderError::PipelineCreation(e.into()))
+    }
+
+    fn color_target(&mut self, target: &str) -> Option<ColorTarget> {
+        if let Some(ref mut layout) = self.layout {
+            return layout.color_target_mut(target).map(|color| ColorTarget::Typed(&mut color.1, &mut color.2));
+        }
+
+        if let Some(color) = self.initializer.color_targets.iter_mut().find(|color| color.0 == target) {
+            return Some(ColorTarget::Typed(&mut color.1, &mut color.2));
+        }
+
+        self.initializer.raw_color_targets.iter_mut().find(|color| color.0 == target).map(|color| ColorTarget::Raw(&mut color.2, &mut color.3))
+    }
+}
+
+/// Mask and blend state of a typed or raw color target. Raw targets may leave blending off.
+enum ColorTarget<'a> {
+    Typed(&'a mut ColorMask, &'a mut Blend),
+    Raw(&'a mut ColorMask, &'a mut Option<Blend>),
+}
+
+fn read_shader(path: &Path) -> RenderResult<Vec<u8>> {
+    let mut code = Vec::new();
+
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut code))
+        .map_err(|e| RenderError::ShaderLoad(path.display().to_string(), e))?;
+
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    use gfx::preset::blend;
+    use gfx::state::MASK_ALL;
+    use render::backend::OpenGL;
+
+    fn builder() -> EffectBuilder<OpenGL> {
+        let mut initializer = Init::default();
+        initializer.color_targets.push(("o_Color", MASK_ALL, blend::REPLACE));
+
+        EffectBuilder::new_minimal("vertex".as_bytes(), "pixel".as_bytes())
+            .with_initializer(initializer)
+    }
+
+    fn hash(builder: &EffectBuilder<OpenGL>) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        builder.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn comparing_state() {
+        let additive = builder().with_blend("o_Color", blend::ADD);
+
+        assert_eq!(additive, builder().with_blend("o_Color", blend::ADD));
+        assert_eq!(hash(&additive), hash(&builder().with_blend("o_Color", blend::ADD)));
+        assert!(additive != builder());
+        assert!(builder().with_depth_bias(1, 2) != builder());
+        assert!(builder().with_color_mask("o_Color", ColorMask::empty()) != builder());
+    }
+
+    #[test]
+    fn blending_raw_targets() {
+        use gfx::format::{ChannelType, Format, SurfaceType};
+
+        let mut initializer = Init::default();
+        initializer.raw_color_targets.push(("o_Albedo", Format(SurfaceType::R8_G8_B8_A8, ChannelType::Unorm), MASK_ALL, None));
+
+        let raw = EffectBuilder::<OpenGL>::new_minimal("vertex".as_bytes(), "pixel".as_bytes()).with_initializer(initializer);
+        let blended = raw.clone().with_blend("o_Albedo", blend::ADD).with_color_mask("o_Albedo", ColorMask::empty());
+
+        assert_eq!(blended.initializer.raw_color_targets[0].2, ColorMask::empty());
+        assert_eq!(blended.initializer.raw_color_targets[0].3, Some(blend::ADD));
+        assert!(blended.unknown_targets.is_empty());
+
+        assert_eq!(raw.with_blend("o_Missing", blend::ADD).unknown_targets, vec!["o_Missing".to_string()]);
+    }
+
+    #[test]
+    fn loading_files() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/resources/shaders/glsl");
+        let mut builder = EffectBuilder::<OpenGL>::from_files(root.join("test.vert"), root.join("test.frag")).unwrap();
+
+        assert_eq!(builder.files().len(), 2);
+        assert!(builder.reload_program().is_ok());
+
+        let generated = builder.clone()
+            .with_program(b"vertex".to_vec(), b"pixel".to_vec())
+            .with_dependencies(vec![root.join("test.frag")]);
+
+        assert!(generated.files().is_empty());
+        assert_eq!(generated.dependencies(), &[root.join("test.frag")][..]);
+        assert!(generated != builder);
+
+        match EffectBuilder::<OpenGL>::from_files(root.join("missing.vert"), root.join("test.frag")) {
+            Err(RenderError::ShaderLoad(ref path, _)) => assert!(path.ends_with("missing.vert")),
+            _ => panic!("expected a load error")
+        }
+    }
 }
@@ -1,30 +1,65 @@
 use gfx;
+use gfx_core::factory::ResourceViewError;
+use render::pipeline::BindingError;
 
 use std::fmt;
+use std::io;
 use std::error;
 
 pub type RenderResult<T> = Result<T, RenderError>;
 
 #[derive(Debug)]
 pub enum RenderError {
+    Binding(BindingError),
     BufferCreation(gfx::buffer::CreationError),
+    BufferUpdate(gfx::UpdateError<usize>),
+    DuplicateTarget(String),
+    GraphCycle(Vec<String>),
+    IncludeCycle(Vec<String>),
+    MissingInput(String, String),
     NoSuchTarget(String),
-    ProgramCreation(gfx::shade::ProgramError)
+    PipelineCreation(gfx::PipelineStateError<String>),
+    ProgramCreation(gfx::shade::ProgramError),
+    ShaderLoad(String, io::Error),
+    ShaderReflection(String),
+    ShaderTranslation(String),
+    TargetCreation(gfx::CombinedError),
+    Unsupported(String),
+    ViewCreation(ResourceViewError)
 }
 
 impl error::Error for RenderError {
     fn description(&self) -> &str {
         match *self {
+            RenderError::Binding(_) => "Initializer does not match the program.",
             RenderError::BufferCreation(_) => "Failed to create buffer.",
+            RenderError::BufferUpdate(_) => "Failed to update buffer.",
+            RenderError::DuplicateTarget(_) => "Target with this name already exists.",
+            RenderError::GraphCycle(_) => "Render graph passes depend on each other.",
+            RenderError::IncludeCycle(_) => "Shader files include each other.",
+            RenderError::MissingInput(_, _) => "Pass reads a target nothing writes.",
             RenderError::NoSuchTarget(_) => "Target with this name does not exist.",
-            RenderError::ProgramCreation(_) => "Failed to create shader program."
+            RenderError::PipelineCreation(_) => "Failed to create pipeline state.",
+            RenderError::ProgramCreation(_) => "Failed to create shader program.",
+            RenderError::ShaderLoad(_, _) => "Failed to load shader.",
+            RenderError::ShaderReflection(_) => "Failed to reflect shader module.",
+            RenderError::ShaderTranslation(_) => "Failed to translate shader.",
+            RenderError::TargetCreation(_) => "Failed to create render target.",
+            RenderError::Unsupported(_) => "Feature is not supported by the backend.",
+            RenderError::ViewCreation(_) => "Failed to create resource view."
         }
     }
 
     fn cause(&self) -> Option<&error::Error> {
         match *self {
+            RenderError::Binding(ref e) => Some(e),
             RenderError::BufferCreation(ref e) => Some(e),
+            RenderError::BufferUpdate(ref e) => Some(e),
+            RenderError::PipelineCreation(ref e) => Some(e),
             RenderError::ProgramCreation(ref e) => Some(e),
+            RenderError::ShaderLoad(_, ref e) => Some(e),
+            RenderError::TargetCreation(ref e) => Some(e),
+            RenderError::ViewCreation(ref e) => Some(e),
             _ => None
         }
     }
@@ -33,9 +68,22 @@ impl error::Error for RenderError {
 impl fmt::Display for RenderError {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match *self {
+            RenderError::Binding(ref e) => write!(fmt, "Binding shader variables failed: {}", e),
             RenderError::BufferCreation(ref e) => write!(fmt, "Buffer creation failed: {}", e),
+            RenderError::BufferUpdate(ref e) => write!(fmt, "Buffer update failed: {}", e),
+            RenderError::DuplicateTarget(ref e) => write!(fmt, "Duplicate target: {}", e),
+            RenderError::GraphCycle(ref e) => write!(fmt, "Render graph cycle between passes: {}", e.join(", ")),
+            RenderError::IncludeCycle(ref e) => write!(fmt, "Shader include cycle: {}", e.join(" -> ")),
+            RenderError::MissingInput(ref pass, ref target) => write!(fmt, "Pass {} reads unwritten target: {}", pass, target),
             RenderError::NoSuchTarget(ref e) => write!(fmt, "Nonexistent target: {}", e),
+            RenderError::PipelineCreation(ref e) => write!(fmt, "Pipeline state creation failed: {}", e),
             RenderError::ProgramCreation(ref e) => write!(fmt, "Program compilation failed: {}", e),
+            RenderError::ShaderLoad(ref path, ref e) => write!(fmt, "Loading shader {} failed: {}", path, e),
+            RenderError::ShaderReflection(ref e) => write!(fmt, "Shader reflection failed: {}", e),
+            RenderError::ShaderTranslation(ref e) => write!(fmt, "Shader translation failed: {}", e),
+            RenderError::TargetCreation(ref e) => write!(fmt, "Render target creation failed: {}", e),
+            RenderError::Unsupported(ref e) => write!(fmt, "Unsupported by the backend: {}", e),
+            RenderError::ViewCreation(ref e) => write!(fmt, "Resource view creation failed: {}", e),
         }
     }
 }
@@ -0,0 +1,497 @@
+use gfx;
+use std::collections::BTreeMap;
+use gfx::format::{ChannelType, Format, SurfaceType, Swizzle};
+use gfx::memory::Typed;
+use gfx::pso::buffer::{ElemOffset, Element};
+use gfx::preset::blend::REPLACE;
+use gfx::state::{Comparison, Depth, MASK_ALL, Rasterizer, Stencil, StencilOp};
+use gfx::texture;
+use nalgebra::Matrix4;
+use core::platform::Platform;
+use system::camera::CameraManager;
+use system::entity::Entity;
+use system::light::{LightKind, LightManager, PackedLight};
+use render::backend;
+use render::command::{CommandBucket, DrawBucket, DrawCommand, DrawKey, EffectId};
+use render::effect::{Effect, EffectBuilder};
+use render::error::{RenderError, RenderResult};
+use render::pipeline::{self, Data, Init};
+
+/// Largest number of lights shaded by the deferred resolve in a frame.
+///
+/// Matches `MAX_LIGHTS` in the resolve shader; 256 packed lights exactly fill the smallest
+/// uniform block every OpenGL implementation must support.
+pub const MAX_LIGHTS: usize = 256;
+
+/// Byte stride of the vertices consumed by the G-buffer effect.
+pub const GEOMETRY_STRIDE: u8 = 32;
+
+static GEOMETRY_ATTRIBUTES: [(&'static str, Element<Format>); 3] = [
+    ("a_Position", Element { format: Format(SurfaceType::R32_G32_B32, ChannelType::Float), offset: 0 as ElemOffset }),
+    ("a_Normal", Element { format: Format(SurfaceType::R32_G32_B32, ChannelType::Float), offset: 12 as ElemOffset }),
+    ("a_TexCoord", Element { format: Format(SurfaceType::R32_G32, ChannelType::Float), offset: 24 as ElemOffset }),
+];
+
+/// Outputs of the G-buffer effect, in the order of the targets they write.
+const GEOMETRY_OUTPUTS: [&'static str; 3] = ["o_Albedo", "o_Normal", "o_Material"];
+
+/// Textures the resolve samples, in the order of `GBuffer::resources`.
+const RESOLVE_TEXTURES: [&'static str; 4] = ["t_Albedo", "t_Normal", "t_Material", "t_Depth"];
+
+// Per-draw constants of the G-buffer effect.
+//
+// `albedo` is the linear base color and `material` holds roughness, metalness, ambient
+// occlusion and a spare slot, written straight into the material target.
+gfx_defines! {
+    constant GeometryLocals {
+        model: [[f32; 4]; 4] = "u_Model",
+        view_projection: [[f32; 4]; 4] = "u_ViewProjection",
+        albedo: [f32; 4] = "u_Albedo",
+        material: [f32; 4] = "u_Material",
+    }
+
+    constant DeferredLocals {
+        inverse_view_projection: [[f32; 4]; 4] = "u_InverseViewProjection",
+        camera_position: [f32; 4] = "u_CameraPosition",
+        clip_params: [f32; 4] = "u_ClipParams",
+        light_counts: [u32; 4] = "u_LightCounts",
+    }
+}
+
+/// Formats of the G-buffer color targets.
+///
+/// Formats are chosen at runtime so the layout can trade precision for bandwidth without
+/// changing the shaders that write and read it. Depth always uses the backend's depth format,
+/// which pipelines bind it through.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct GBufferLayout {
+    pub albedo: Format,
+    pub normal: Format,
+    pub material: Format,
+}
+
+impl GBufferLayout {
+    /// Color formats in the order of the geometry effect's outputs.
+    pub fn colors(&self) -> [Format; 3] {
+        [self.albedo, self.normal, self.material]
+    }
+}
+
+impl Default for GBufferLayout {
+    fn default() -> Self {
+        GBufferLayout {
+            albedo: Format(SurfaceType::R8_G8_B8_A8, ChannelType::Srgb),
+            normal: Format(SurfaceType::R10_G10_B10_A2, ChannelType::Unorm),
+            material: Format(SurfaceType::R8_G8_B8_A8, ChannelType::Unorm),
+        }
+    }
+}
+
+/// A color target along with the views needed to write and sample it.
+pub struct ColorAttachment<R: gfx::Resources> {
+    pub texture: gfx::handle::RawTexture<R>,
+    /// View for sampling the target, or `None` if it is multisampled. Multisampled targets are
+    /// never resolved, so they can only be rendered to.
+    pub resource: Option<gfx::handle::RawShaderResourceView<R>>,
+    pub target: gfx::handle::RawRenderTargetView<R>,
+}
+
+impl<R> ColorAttachment<R> where R: gfx::Resources {
+    /// Creates a single-sampled 2D texture that can be rendered to and sampled.
+    pub fn new<B>(platform: &mut Platform<B>, format: Format, width: u16, height: u16) -> RenderResult<ColorAttachment<R>>
+        where B: backend::Backend<Resources = R> {
+
+        ColorAttachment::with_samples(platform, format, width, height, 1)
+    }
+
+    /// Creates a 2D texture with the given number of samples per pixel.
+    ///
+    /// Multisampled textures get no shader resource view.
+    pub fn with_samples<B>(platform: &mut Platform<B>, format: Format, width: u16, height: u16, samples: u8) -> RenderResult<ColorAttachment<R>>
+        where B: backend::Backend<Resources = R> {
+
+        use gfx::Factory;
+
+        let Format(surface, channel) = format;
+        let info = texture::Info {
+            kind: texture::Kind::D2(width, height, aa_mode(samples)),
+            levels: 1,
+            format: surface,
+            bind: if samples > 1 { gfx::RENDER_TARGET } else { gfx::RENDER_TARGET | gfx::SHADER_RESOURCE },
+            usage: gfx::memory::Usage::Data,
+        };
+
+        let texture = platform.create_texture_raw(info, Some(channel), None)
+            .map_err(|e| RenderError::TargetCreation(e.into()))?;
+
+        let resource = if samples > 1 {
+            None
+        } else {
+            Some(platform.view_texture_as_shader_resource_raw(&texture, texture::ResourceDesc {
+                channel,
+                layer: None,
+                min: 0,
+                max: 0,
+                swizzle: Swizzle::new(),
+            }).map_err(|e| RenderError::TargetCreation(e.into()))?)
+        };
+
+        let target = platform.view_texture_as_render_target_raw(&texture, texture::RenderDesc {
+            channel,
+            level: 0,
+            layer: None,
+        }).map_err(|e| RenderError::TargetCreation(e.into()))?;
+
+        Ok(ColorAttachment { texture, resource, target })
+    }
+}
+
+/// A depth target along with the views needed to write and sample it.
+pub struct DepthAttachment<R: gfx::Resources> {
+    pub texture: gfx::handle::RawTexture<R>,
+    /// View for sampling the target, or `None` if it is multisampled.
+    pub resource: Option<gfx::handle::RawShaderResourceView<R>>,
+    pub target: gfx::handle::RawDepthStencilView<R>,
+}
+
+impl<R> DepthAttachment<R> where R: gfx::Resources {
+    /// Creates a single-sampled 2D depth texture that can be rendered to and sampled.
+    pub fn new<B>(platform: &mut Platform<B>, format: Format, width: u16, height: u16) -> RenderResult<DepthAttachment<R>>
+        where B: backend::Backend<Resources = R> {
+
+        DepthAttachment::with_samples(platform, format, width, height, 1)
+    }
+
+    /// Creates a 2D depth texture with the given number of samples per pixel.
+    ///
+    /// Multisampled textures get no shader resource view.
+    pub fn with_samples<B>(platform: &mut Platform<B>, format: Format, width: u16, height: u16, samples: u8) -> RenderResult<DepthAttachment<R>>
+        where B: backend::Backend<Resources = R> {
+
+        use gfx::Factory;
+
+        let Format(surface, channel) = format;
+        let info = texture::Info {
+            kind: texture::Kind::D2(width, height, aa_mode(samples)),
+            levels: 1,
+            format: surface,
+            bind: if samples > 1 { gfx::DEPTH_STENCIL } else { gfx::DEPTH_STENCIL | gfx::SHADER_RESOURCE },
+            usage: gfx::memory::Usage::Data,
+        };
+
+        let texture = platform.create_texture_raw(info, Some(channel), None)
+            .map_err(|e| RenderError::TargetCreation(e.into()))?;
+
+        let resource = if samples > 1 {
+            None
+        } else {
+            Some(platform.view_texture_as_shader_resource_raw(&texture, texture::ResourceDesc {
+                channel,
+                layer: None,
+                min: 0,
+                max: 0,
+                swizzle: Swizzle::new(),
+            }).map_err(|e| RenderError::TargetCreation(e.into()))?)
+        };
+
+        let target = platform.view_texture_as_depth_stencil_raw(&texture, texture::DepthStencilDesc {
+            level: 0,
+            layer: None,
+            flags: texture::DepthStencilFlags::empty(),
+        }).map_err(|e| RenderError::TargetCreation(e.into()))?;
+
+        Ok(DepthAttachment { texture, resource, target })
+    }
+}
+
+/// A color or depth target.
+pub enum Attachment<R: gfx::Resources> {
+    Color(ColorAttachment<R>),
+    Depth(DepthAttachment<R>),
+}
+
+fn aa_mode(samples: u8) -> texture::AaMode {
+    if samples > 1 { texture::AaMode::Multi(samples) } else { texture::AaMode::Single }
+}
+
+/// Albedo, normal, material and depth targets written by the geometry pass.
+///
+/// Targets are sized to the swapchain and recreated whenever the swapchain or the layout
+/// changes; views handed out before then keep the old textures alive but are no longer drawn to.
+pub struct GBuffer<B: backend::Backend> {
+    layout: GBufferLayout,
+    size: (u16, u16),
+    albedo: ColorAttachment<B::Resources>,
+    normal: ColorAttachment<B::Resources>,
+    material: ColorAttachment<B::Resources>,
+    depth: DepthAttachment<B::Resources>,
+}
+
+impl<B> GBuffer<B> where B: backend::Backend, B::DepthFormat: gfx::format::DepthFormat {
+    pub fn new(platform: &mut Platform<B>, layout: GBufferLayout, width: u16, height: u16) -> RenderResult<GBuffer<B>> {
+        Ok(GBuffer {
+            layout,
+            size: (width, height),
+            albedo: ColorAttachment::new(platform, layout.albedo, width, height)?,
+            normal: ColorAttachment::new(platform, layout.normal, width, height)?,
+            material: ColorAttachment::new(platform, layout.material, width, height)?,
+            depth: DepthAttachment::new(platform, <B::DepthFormat as gfx::format::Formatted>::get_format(), width, height)?,
+        })
+    }
+
+    pub fn layout(&self) -> GBufferLayout {
+        self.layout
+    }
+
+    pub fn size(&self) -> (u16, u16) {
+        self.size
+    }
+
+    /// Recreates the targets at a new swapchain size. Returns whether anything was recreated.
+    pub fn resize(&mut self, platform: &mut Platform<B>, width: u16, height: u16) -> RenderResult<bool> {
+        if self.size == (width, height) {
+            return Ok(false);
+        }
+
+        *self = GBuffer::new(platform, self.layout, width, height)?;
+        Ok(true)
+    }
+
+    /// Recreates the targets with a new layout. Returns whether anything was recreated.
+    ///
+    /// Geometry effects name the formats they write, so they must be rebuilt for the new layout.
+    pub fn set_layout(&mut self, platform: &mut Platform<B>, layout: GBufferLayout) -> RenderResult<bool> {
+        if self.layout == layout {
+            return Ok(false);
+        }
+
+        *self = GBuffer::new(platform, layout, self.size.0, self.size.1)?;
+        Ok(true)
+    }
+
+    pub fn albedo(&self) -> &ColorAttachment<B::Resources> {
+        &self.albedo
+    }
+
+    pub fn normal(&self) -> &ColorAttachment<B::Resources> {
+        &self.normal
+    }
+
+    pub fn material(&self) -> &ColorAttachment<B::Resources> {
+        &self.material
+    }
+
+    pub fn depth(&self) -> &DepthAttachment<B::Resources> {
+        &self.depth
+    }
+
+    /// Clears depth ahead of the geometry pass.
+    ///
+    /// Color targets are left alone: the resolve skips every pixel still at the cleared depth,
+    /// so stale color there is never read.
+    pub fn clear<C>(&self, encoder: &mut gfx::Encoder<B::Resources, C>, depth: f32) where C: gfx::CommandBuffer<B::Resources> {
+        let target = self.depth_target();
+
+        encoder.clear_depth(&target, depth);
+    }
+
+    /// Points a geometry draw's outputs at the G-buffer.
+    ///
+    /// Color targets are bound raw in their layout formats, as declared by a geometry effect
+    /// built for the same layout. Stencil reference values are left at zero.
+    pub fn bind(&self, data: &mut Data<B>) {
+        let targets = [&self.albedo, &self.normal, &self.material];
+
+        data.raw_color_targets = pipeline::named(GEOMETRY_OUTPUTS.iter().cloned()
+            .zip(targets.iter().map(|attachment| attachment.target.clone())));
+        data.depth_target = Some((self.depth_target(), (0, 0)));
+    }
+
+    /// Shader resource views in the order the resolve shader samples them.
+    pub fn resources(&self) -> Vec<gfx::handle::RawShaderResourceView<B::Resources>> {
+        // G-buffer targets are single-sampled, so each of them has a view.
+        vec![&self.albedo.resource, &self.normal.resource, &self.material.resource, &self.depth.resource]
+            .into_iter()
+            .map(|resource| resource.clone().expect("single-sampled targets are viewable"))
+            .collect()
+    }
+
+    /// Depth is created in the backend's depth format, so the typed view matches the texture.
+    fn depth_target(&self) -> gfx::handle::DepthStencilView<B::Resources, B::DepthFormat> {
+        Typed::new(self.depth.target.clone())
+    }
+}
+
+/// Describes the effect that rasterizes geometry into a G-buffer with the given layout.
+///
+/// Vertices hold a position, normal and texture coordinate as tightly packed floats. Cameras
+/// with reversed depth need the greater-equal depth test.
+pub fn geometry_effect<B>(layout: GBufferLayout, reversed_z: bool) -> EffectBuilder<B> where B: backend::Backend {
+    let depth = Depth {
+        fun: if reversed_z { Comparison::GreaterEqual } else { Comparison::LessEqual },
+        write: true,
+    };
+    let stencil = Stencil::new(Comparison::Always, 0, (StencilOp::Keep, StencilOp::Keep, StencilOp::Keep));
+
+    Effect::new_minimal(&include_bytes!("../../resources/shaders/glsl/gbuffer.vert")[..],
+                        &include_bytes!("../../resources/shaders/glsl/gbuffer.frag")[..])
+        .with_depth_state(depth)
+        .with_initializer(Init {
+            constants: vec!["Locals"],
+            globals: Vec::new(),
+            color_targets: Vec::new(),
+            raw_color_targets: GEOMETRY_OUTPUTS.iter().cloned()
+                .zip(layout.colors().iter().cloned())
+                .map(|(output, format)| (output, format, MASK_ALL, Some(REPLACE)))
+                .collect(),
+            depth_target: Some((depth, stencil)),
+            samplers: Vec::new(),
+            textures: Vec::new(),
+            vertices: vec![(&GEOMETRY_ATTRIBUTES[..], GEOMETRY_STRIDE, 0)],
+        })
+}
+
+/// Describes the full-screen effect that lights the G-buffer.
+pub fn resolve_effect<B>() -> EffectBuilder<B> where B: backend::Backend {
+    Effect::new_minimal(&include_bytes!("../../resources/shaders/glsl/fullscreen.vert")[..],
+                        &include_bytes!("../../resources/shaders/glsl/deferred.frag")[..])
+        .with_depth_state(Depth { fun: Comparison::Always, write: false })
+        .with_rasterizer(Rasterizer::new_fill())
+        .with_initializer(Init {
+            constants: vec!["DeferredLocals", "Lights"],
+            globals: Vec::new(),
+            color_targets: vec![("o_Color", MASK_ALL, REPLACE)],
+            raw_color_targets: Vec::new(),
+            depth_target: None,
+            samplers: RESOLVE_TEXTURES.to_vec(),
+            textures: RESOLVE_TEXTURES.to_vec(),
+            vertices: Vec::new(),
+        })
+}
+
+/// Slice drawing a single triangle that covers the screen without any vertex buffer.
+pub fn fullscreen_slice<R: gfx::Resources>() -> gfx::Slice<R> {
+    gfx::Slice {
+        start: 0,
+        end: 3,
+        base_vertex: 0,
+        instances: None,
+        buffer: gfx::IndexBuffer::Auto,
+    }
+}
+
+/// Converts a matrix into the column-major arrays expected by constant buffers.
+pub fn matrix_array(matrix: &Matrix4<f32>) -> [[f32; 4]; 4] {
+    let mut array = [[0.0f32; 4]; 4];
+
+    for c in 0..4 {
+        for r in 0..4 {
+            array[c][r] = matrix[(r, c)];
+        }
+    }
+
+    array
+}
+
+/// Shades the G-buffer with every packed light from the light manager.
+///
+/// Each frame the packed lights are streamed into a constant buffer and a single full-screen
+/// draw is recorded into the `Deferred` stage bucket.
+pub struct DeferredResolve<B: backend::Backend> {
+    effect: EffectId,
+    locals: gfx::handle::Buffer<B::Resources, DeferredLocals>,
+    lights: gfx::handle::Buffer<B::Resources, PackedLight>,
+    sampler: gfx::handle::Sampler<B::Resources>,
+}
+
+impl<B> DeferredResolve<B> where B: backend::Backend, B::DepthFormat: gfx::format::DepthFormat {
+    /// Creates the resolve's buffers; `effect` identifies the effect built from `resolve_effect`.
+    pub fn new(platform: &mut Platform<B>, effect: EffectId) -> DeferredResolve<B> {
+        use gfx::Factory;
+        use gfx::traits::FactoryExt;
+
+        let sampler = platform.create_sampler(texture::SamplerInfo::new(texture::FilterMethod::Scale, texture::WrapMode::Clamp));
+
+        DeferredResolve {
+            effect,
+            locals: platform.create_constant_buffer(1),
+            lights: platform.create_constant_buffer(MAX_LIGHTS),
+            sampler,
+        }
+    }
+
+    pub fn effect(&self) -> EffectId {
+        self.effect
+    }
+
+    /// Uploads the camera and lights and records the resolve draw into `output`.
+    ///
+    /// Lights beyond `MAX_LIGHTS` are dropped; since directional lights lead the packed array
+    /// they are the last to go.
+    pub fn record<C>(&self, encoder: &mut gfx::Encoder<B::Resources, C>, gbuffer: &GBuffer<B>, cameras: &CameraManager, camera: Entity,
+                     lights: &LightManager, output: gfx::handle::RenderTargetView<B::Resources, B::ColorFormat>, bucket: &mut DrawBucket<B>) -> RenderResult<()>
+        where C: gfx::CommandBuffer<B::Resources> {
+
+        let packed = lights.packed();
+        let total = packed.len().min(MAX_LIGHTS);
+
+        if total > 0 {
+            encoder.update_buffer(&self.lights, &packed[..total], 0)
+                .map_err(RenderError::BufferUpdate)?;
+        }
+
+        let clamp = |kind: LightKind, before: usize| lights.count(kind).min(total - before.min(total));
+        let directional = clamp(LightKind::Directional, 0);
+        let point = clamp(LightKind::Point, directional);
+        let spot = clamp(LightKind::Spot, directional + point);
+
+        let clip = B::clip_space();
+        let (scale, offset) = if clip.zero_to_one_depth { (1.0f32, 0.0f32) } else { (2.0f32, -1.0f32) };
+        let cleared = if cameras.reversed_z(camera) { 0.0f32 } else { 1.0f32 };
+        let y_sign = if clip.y_down { -1.0f32 } else { 1.0f32 };
+        let position = cameras.position(camera);
+        let inverse = cameras.view_projection(camera).try_inverse().unwrap_or_else(|| Matrix4::identity());
+
+        encoder.update_constant_buffer(&self.locals, &DeferredLocals {
+            inverse_view_projection: matrix_array(&inverse),
+            camera_position: [position[0], position[1], position[2], 1.0f32],
+            clip_params: [scale, offset, cleared, y_sign],
+            light_counts: [directional as u32, point as u32, spot as u32, total as u32],
+        });
+
+        let data = Data {
+            constants: pipeline::named(vec![("DeferredLocals", self.locals.raw().clone()), ("Lights", self.lights.raw().clone())]),
+            globals: BTreeMap::new(),
+            color_targets: pipeline::named(vec![("o_Color", output)]),
+            raw_color_targets: BTreeMap::new(),
+            depth_target: None,
+            samplers: pipeline::named(RESOLVE_TEXTURES.iter().map(|&name| (name, self.sampler.clone()))),
+            textures: pipeline::named(RESOLVE_TEXTURES.iter().cloned().zip(gbuffer.resources())),
+            vertices: Vec::new(),
+        };
+
+        bucket.push(DrawKey::opaque(0, self.effect, 0.0f32), DrawCommand {
+            effect: self.effect,
+            slice: fullscreen_slice(),
+            data,
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converting_matrices() {
+        let mut matrix = Matrix4::identity();
+        matrix[(3, 0)] = 5.0f32;
+
+        let array = matrix_array(&matrix);
+
+        assert_eq!(array[0], [1.0f32, 0.0f32, 0.0f32, 5.0f32]);
+        assert_eq!(array[3], [0.0f32, 0.0f32, 0.0f32, 1.0f32]);
+    }
+}
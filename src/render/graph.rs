@@ -0,0 +1,492 @@
+use std::collections::{BTreeMap, BTreeSet};
+use gfx;
+use gfx::format::{Format, SurfaceType};
+use core::platform::Platform;
+use render::backend;
+use render::error::{RenderError, RenderResult};
+use render::gbuffer::{Attachment, ColorAttachment, DepthAttachment};
+
+pub type PassId = usize;
+
+/// Format and size of a transient target owned by the graph.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct TargetDesc {
+    pub format: Format,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl TargetDesc {
+    pub fn new(format: Format, width: u16, height: u16) -> TargetDesc {
+        TargetDesc { format, width, height }
+    }
+
+    pub fn is_depth(&self) -> bool {
+        match self.format.0 {
+            SurfaceType::D16 | SurfaceType::D24 | SurfaceType::D24_S8 | SurfaceType::D32 => true,
+            _ => false
+        }
+    }
+}
+
+/// A pass along with the named targets it samples and renders to.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Pass {
+    pub name: String,
+    pub reads: Vec<String>,
+    pub writes: Vec<String>,
+}
+
+impl Pass {
+    pub fn new(name: &str) -> Pass {
+        Pass {
+            name: name.to_string(),
+            reads: Vec::new(),
+            writes: Vec::new(),
+        }
+    }
+
+    pub fn read(mut self, target: &str) -> Pass {
+        self.reads.push(target.to_string());
+        self
+    }
+
+    pub fn write(mut self, target: &str) -> Pass {
+        self.writes.push(target.to_string());
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Resource {
+    Transient(TargetDesc),
+    Imported,
+}
+
+/// Frame description in terms of passes and the named targets flowing between them.
+///
+/// Targets are either transient, in which case the graph decides when they live and which
+/// texture backs them, or imported from outside, like the backbuffer or shadow maps. Passes
+/// that contribute nothing to a target marked as an output are culled on compile.
+///
+/// When several passes write one target, a pass reading it sees the latest write declared
+/// before it, or the last write if none precedes it. A pass both reading and writing a target
+/// modifies the previous write in place.
+pub struct RenderGraph {
+    passes: Vec<Pass>,
+    resources: BTreeMap<String, Resource>,
+    outputs: BTreeSet<String>,
+}
+
+impl RenderGraph {
+    pub fn new() -> RenderGraph {
+        RenderGraph {
+            passes: Vec::new(),
+            resources: BTreeMap::new(),
+            outputs: BTreeSet::new(),
+        }
+    }
+
+    /// Declares a target whose storage is allocated and aliased by the graph.
+    pub fn create_transient(&mut self, name: &str, desc: TargetDesc) -> RenderResult<()> {
+        self.declare(name, Resource::Transient(desc))
+    }
+
+    /// Declares a target owned outside the graph. Imported targets count as written before the
+    /// first pass runs.
+    pub fn import(&mut self, name: &str) -> RenderResult<()> {
+        self.declare(name, Resource::Imported)
+    }
+
+    /// Marks a target whose contents are needed after the graph runs.
+    pub fn mark_output(&mut self, name: &str) -> RenderResult<()> {
+        if !self.resources.contains_key(name) {
+            return Err(RenderError::NoSuchTarget(name.to_string()));
+        }
+
+        self.outputs.insert(name.to_string());
+        Ok(())
+    }
+
+    pub fn add_pass(&mut self, pass: Pass) -> PassId {
+        self.passes.push(pass);
+        self.passes.len() - 1
+    }
+
+    pub fn pass(&self, pass: PassId) -> &Pass {
+        &self.passes[pass]
+    }
+
+    pub fn transient_desc(&self, name: &str) -> Option<TargetDesc> {
+        match self.resources.get(name) {
+            Some(&Resource::Transient(desc)) => Some(desc),
+            _ => None
+        }
+    }
+
+    /// Orders the passes, culls unused ones and assigns transient targets to aliased slots.
+    pub fn compile(&self) -> RenderResult<CompiledGraph> {
+        let dependencies = self.dependencies()?;
+
+        // Keep only passes that an output transitively depends on.
+        let mut kept = vec![false; self.passes.len()];
+        let mut stack: Vec<PassId> = (0..self.passes.len())
+            .filter(|&p| self.passes[p].writes.iter().any(|target| self.outputs.contains(target)))
+            .collect();
+
+        while let Some(pass) = stack.pop() {
+            if kept[pass] {
+                continue;
+            }
+
+            kept[pass] = true;
+            stack.extend(dependencies[pass].iter().cloned());
+        }
+
+        let order = self.sort(&dependencies, &kept)?;
+        let culled = (0..self.passes.len()).filter(|&p| !kept[p]).collect();
+        let (slots, assignments) = self.alias(&order);
+
+        Ok(CompiledGraph {
+            order,
+            culled,
+            slots,
+            assignments,
+        })
+    }
+
+    fn declare(&mut self, name: &str, resource: Resource) -> RenderResult<()> {
+        if self.resources.contains_key(name) {
+            return Err(RenderError::DuplicateTarget(name.to_string()));
+        }
+
+        self.resources.insert(name.to_string(), resource);
+        Ok(())
+    }
+
+    /// Passes each pass must run after.
+    fn dependencies(&self) -> RenderResult<Vec<BTreeSet<PassId>>> {
+        let mut writers: BTreeMap<&str, Vec<PassId>> = BTreeMap::new();
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            for target in pass.reads.iter().chain(pass.writes.iter()) {
+                if !self.resources.contains_key(target.as_str()) {
+                    return Err(RenderError::NoSuchTarget(target.clone()));
+                }
+            }
+
+            for target in pass.writes.iter() {
+                writers.entry(target.as_str()).or_insert_with(Vec::new).push(index);
+            }
+        }
+
+        let mut dependencies = vec![BTreeSet::new(); self.passes.len()];
+
+        // Writes to the same target happen in declaration order.
+        for list in writers.values() {
+            for window in list.windows(2) {
+                dependencies[window[1]].insert(window[0]);
+            }
+        }
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            for target in pass.reads.iter() {
+                let imported = self.resources[target.as_str()] == Resource::Imported;
+                let list = match writers.get(target.as_str()) {
+                    Some(list) => list,
+                    None if imported => continue,
+                    None => return Err(RenderError::MissingInput(pass.name.clone(), target.clone()))
+                };
+
+                let position = match list.iter().rposition(|&writer| writer < index) {
+                    Some(position) => position,
+                    None if !list.contains(&index) => list.len() - 1,
+                    None if imported => continue,
+                    None => return Err(RenderError::MissingInput(pass.name.clone(), target.clone()))
+                };
+
+                dependencies[index].insert(list[position]);
+
+                // The next write must wait until this read is done.
+                if let Some(&next) = list.get(position + 1) {
+                    if next != index {
+                        dependencies[next].insert(index);
+                    }
+                }
+            }
+        }
+
+        Ok(dependencies)
+    }
+
+    /// Topologically sorts the kept passes, preferring declaration order among ready passes.
+    fn sort(&self, dependencies: &[BTreeSet<PassId>], kept: &[bool]) -> RenderResult<Vec<PassId>> {
+        let mut remaining: Vec<usize> = dependencies.iter().map(|set| set.iter().filter(|&&d| kept[d]).count()).collect();
+        let mut dependents = vec![Vec::new(); self.passes.len()];
+
+        for (pass, set) in dependencies.iter().enumerate() {
+            for &dependency in set.iter() {
+                dependents[dependency].push(pass);
+            }
+        }
+
+        let mut ready: BTreeSet<PassId> = (0..self.passes.len()).filter(|&p| kept[p] && remaining[p] == 0).collect();
+        let mut order = Vec::new();
+
+        loop {
+            let pass = match ready.iter().next() {
+                Some(&pass) => pass,
+                None => break
+            };
+
+            ready.remove(&pass);
+            order.push(pass);
+
+            for &dependent in dependents[pass].iter() {
+                if kept[dependent] {
+                    remaining[dependent] -= 1;
+
+                    if remaining[dependent] == 0 {
+                        ready.insert(dependent);
+                    }
+                }
+            }
+        }
+
+        if order.len() < kept.iter().filter(|&&k| k).count() {
+            let cycle = (0..self.passes.len())
+                .filter(|&p| kept[p] && remaining[p] > 0)
+                .map(|p| self.passes[p].name.clone())
+                .collect();
+
+            return Err(RenderError::GraphCycle(cycle));
+        }
+
+        Ok(order)
+    }
+
+    /// Packs transient targets into as few slots as possible; targets with identical
+    /// descriptions whose lifetimes do not overlap share a slot.
+    fn alias(&self, order: &[PassId]) -> (Vec<TargetDesc>, BTreeMap<String, usize>) {
+        let mut lifetimes: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+
+        for (step, &pass) in order.iter().enumerate() {
+            let pass = &self.passes[pass];
+
+            for target in pass.reads.iter().chain(pass.writes.iter()) {
+                if let Resource::Transient(_) = self.resources[target.as_str()] {
+                    let lifetime = lifetimes.entry(target.as_str()).or_insert((step, step));
+                    lifetime.1 = step;
+                }
+            }
+        }
+
+        // Outputs must survive the whole graph.
+        for target in self.outputs.iter() {
+            if let Some(lifetime) = lifetimes.get_mut(target.as_str()) {
+                lifetime.1 = order.len();
+            }
+        }
+
+        let mut targets: Vec<(&str, (usize, usize))> = lifetimes.into_iter().collect();
+        targets.sort_by_key(|&(name, lifetime)| (lifetime.0, name));
+
+        let mut slots: Vec<TargetDesc> = Vec::new();
+        let mut free_after: Vec<usize> = Vec::new();
+        let mut assignments = BTreeMap::new();
+
+        for (name, (first, last)) in targets {
+            let desc = match self.resources[name] {
+                Resource::Transient(desc) => desc,
+                Resource::Imported => continue
+            };
+
+            let slot = match (0..slots.len()).find(|&s| slots[s] == desc && free_after[s] < first) {
+                Some(slot) => slot,
+                None => {
+                    slots.push(desc);
+                    free_after.push(0);
+                    slots.len() - 1
+                }
+            };
+
+            free_after[slot] = last;
+            assignments.insert(name.to_string(), slot);
+        }
+
+        (slots, assignments)
+    }
+}
+
+/// Execution plan produced by compiling a render graph.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CompiledGraph {
+    order: Vec<PassId>,
+    culled: Vec<PassId>,
+    slots: Vec<TargetDesc>,
+    assignments: BTreeMap<String, usize>,
+}
+
+impl CompiledGraph {
+    /// Passes in the order they must run.
+    pub fn order(&self) -> &[PassId] {
+        &self.order
+    }
+
+    /// Passes whose outputs nothing uses.
+    pub fn culled(&self) -> &[PassId] {
+        &self.culled
+    }
+
+    /// Descriptions of the textures backing transient targets.
+    pub fn slots(&self) -> &[TargetDesc] {
+        &self.slots
+    }
+
+    /// Slot backing a transient target used by a scheduled pass.
+    pub fn slot(&self, target: &str) -> RenderResult<usize> {
+        match self.assignments.get(target) {
+            Some(&slot) => Ok(slot),
+            None => Err(RenderError::NoSuchTarget(target.to_string()))
+        }
+    }
+}
+
+/// Textures backing a compiled graph's transient slots.
+///
+/// Allocation is skipped when the slots have not changed since the last compile, so graphs can
+/// be recompiled every frame.
+pub struct TransientPool<B: backend::Backend> {
+    slots: Vec<TargetDesc>,
+    attachments: Vec<Attachment<B::Resources>>,
+}
+
+impl<B> TransientPool<B> where B: backend::Backend {
+    pub fn new() -> TransientPool<B> {
+        TransientPool {
+            slots: Vec::new(),
+            attachments: Vec::new(),
+        }
+    }
+
+    /// Makes sure every slot of a compiled graph is backed by a texture.
+    pub fn allocate(&mut self, platform: &mut Platform<B>, graph: &CompiledGraph) -> RenderResult<()> {
+        if self.slots.as_slice() == graph.slots() {
+            return Ok(());
+        }
+
+        let mut attachments = Vec::with_capacity(graph.slots().len());
+
+        for desc in graph.slots().iter() {
+            attachments.push(if desc.is_depth() {
+                Attachment::Depth(DepthAttachment::new(platform, desc.format, desc.width, desc.height)?)
+            } else {
+                Attachment::Color(ColorAttachment::new(platform, desc.format, desc.width, desc.height)?)
+            });
+        }
+
+        self.slots = graph.slots().to_vec();
+        self.attachments = attachments;
+
+        Ok(())
+    }
+
+    pub fn color(&self, graph: &CompiledGraph, target: &str) -> RenderResult<&ColorAttachment<B::Resources>> {
+        match self.attachments.get(graph.slot(target)?) {
+            Some(&Attachment::Color(ref attachment)) => Ok(attachment),
+            _ => Err(RenderError::NoSuchTarget(target.to_string()))
+        }
+    }
+
+    pub fn depth(&self, graph: &CompiledGraph, target: &str) -> RenderResult<&DepthAttachment<B::Resources>> {
+        match self.attachments.get(graph.slot(target)?) {
+            Some(&Attachment::Depth(ref attachment)) => Ok(attachment),
+            _ => Err(RenderError::NoSuchTarget(target.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gfx::format::ChannelType;
+
+    fn color() -> TargetDesc {
+        TargetDesc::new(Format(SurfaceType::R16_G16_B16_A16, ChannelType::Float), 64, 64)
+    }
+
+    fn deferred() -> RenderGraph {
+        let mut graph = RenderGraph::new();
+
+        graph.create_transient("albedo", color()).unwrap();
+        graph.create_transient("lit", color()).unwrap();
+        graph.create_transient("bloom", color()).unwrap();
+        graph.create_transient("debug", color()).unwrap();
+        graph.import("backbuffer").unwrap();
+        graph.mark_output("backbuffer").unwrap();
+
+        graph.add_pass(Pass::new("post").read("lit").write("backbuffer"));
+        graph.add_pass(Pass::new("gbuffer").write("albedo"));
+        graph.add_pass(Pass::new("debug").read("albedo").write("debug"));
+        graph.add_pass(Pass::new("resolve").read("albedo").write("lit"));
+
+        graph
+    }
+
+    #[test]
+    fn ordering_passes() {
+        let compiled = deferred().compile().unwrap();
+
+        assert_eq!(compiled.order(), &[1, 3, 0]);
+        assert_eq!(compiled.culled(), &[2]);
+    }
+
+    #[test]
+    fn aliasing_transients() {
+        let mut graph = deferred();
+        graph.add_pass(Pass::new("blur").read("lit").write("bloom"));
+        graph.add_pass(Pass::new("composite").read("bloom").read("lit").write("backbuffer"));
+
+        let compiled = graph.compile().unwrap();
+
+        // The albedo target is dead once the resolve has run, so the bloom target reuses it.
+        assert_eq!(compiled.slots().len(), 2);
+        assert_eq!(compiled.slot("albedo").unwrap(), compiled.slot("bloom").unwrap());
+        assert!(compiled.slot("debug").is_err());
+    }
+
+    #[test]
+    fn reporting_errors() {
+        let mut graph = deferred();
+        graph.add_pass(Pass::new("broken").read("missing").write("backbuffer"));
+
+        match graph.compile() {
+            Err(RenderError::NoSuchTarget(ref name)) if name == "missing" => (),
+            _ => panic!("expected a missing target")
+        }
+
+        let mut graph = RenderGraph::new();
+        graph.create_transient("a", color()).unwrap();
+        graph.create_transient("b", color()).unwrap();
+        graph.import("backbuffer").unwrap();
+        graph.mark_output("backbuffer").unwrap();
+        graph.add_pass(Pass::new("first").read("b").write("a"));
+        graph.add_pass(Pass::new("second").read("a").write("b").write("backbuffer"));
+
+        match graph.compile() {
+            Err(RenderError::GraphCycle(ref passes)) => assert_eq!(passes.len(), 2),
+            _ => panic!("expected a cycle")
+        }
+
+        let mut graph = RenderGraph::new();
+        graph.create_transient("a", color()).unwrap();
+        graph.import("backbuffer").unwrap();
+        graph.mark_output("backbuffer").unwrap();
+        graph.add_pass(Pass::new("only").read("a").write("backbuffer"));
+
+        match graph.compile() {
+            Err(RenderError::MissingInput(ref pass, ref target)) => assert_eq!((pass.as_str(), target.as_str()), ("only", "a")),
+            _ => panic!("expected a missing input")
+        }
+    }
+}
@@ -3,3 +3,16 @@ pub mod pipeline;
 pub mod effect;
 pub mod backend;
 pub mod error;
+pub mod culling;
+pub mod cluster;
+pub mod shadow;
+pub mod command;
+pub mod gbuffer;
+pub mod postprocess;
+pub mod graph;
+pub mod target;
+pub mod reload;
+pub mod preprocess;
+pub mod reflect;
+pub mod translate;
+pub mod compute;
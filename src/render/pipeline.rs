@@ -2,115 +2,355 @@ use gfx;
 use gfx_core;
 use gfx::pso::{PipelineData, PipelineInit, DataBind, DataLink};
 use gfx::pso::{self, target};
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
 use render::backend;
 
-#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+/// Links of the shader variables a pipeline binds, keyed by variable name.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""), Debug(bound = ""), Default(bound = ""), Eq(bound = ""), Hash(bound = ""), PartialEq(bound = ""))]
 pub struct Meta<R: backend::Backend> {
-    constants: Vec<gfx::RawConstantBuffer>,
-    globals: Vec<gfx::RawGlobal>,
-    color_targets: Vec<gfx::RenderTarget<R::ColorFormat>>,
+    constants: Vec<(String, gfx::RawConstantBuffer)>,
+    globals: Vec<(String, gfx::RawGlobal)>,
+    color_targets: Vec<(String, gfx::BlendTarget<R::ColorFormat>)>,
+    raw_color_targets: Vec<(String, gfx::RawRenderTarget)>,
     depth_target: Option<gfx::DepthStencilTarget<R::DepthFormat>>,
-    samplers: Vec<gfx::Sampler>,
-    textures: Vec<gfx::RawShaderResource>,
+    samplers: Vec<(String, gfx::Sampler)>,
+    textures: Vec<(String, gfx::RawShaderResource)>,
     vertices: Vec<gfx::RawVertexBuffer>,
 }
 
-#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+/// Names the shader variables a pipeline binds.
+///
+/// Entries are matched to the program's variables by name, so their order does not matter.
+/// Vertex buffers are the exception: they are bound to slots in order, and their elements name
+/// the attributes they feed.
+///
+/// Color targets are written through the backend's color format; raw color targets name their
+/// own format, for outputs such as G-buffer or HDR targets that use a different one.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""), Debug(bound = ""), Default(bound = ""), Eq(bound = ""), Hash(bound = ""), PartialEq(bound = ""))]
 pub struct Init<'d, R> where R: backend::Backend {
     pub constants: Vec<<gfx::RawConstantBuffer as DataLink<'d>>::Init>,
     pub globals: Vec<<gfx::RawGlobal as DataLink<'d>>::Init>,
-    pub color_targets: Vec<<target::RenderTarget<R::ColorFormat> as DataLink<'d>>::Init>,
-    pub depth_targets: Option<<target::DepthStencilTarget<R::DepthFormat> as DataLink<'d>>::Init>,
+    pub color_targets: Vec<<target::BlendTarget<R::ColorFormat> as DataLink<'d>>::Init>,
+    pub raw_color_targets: Vec<<gfx::RawRenderTarget as DataLink<'d>>::Init>,
+    pub depth_target: Option<<target::DepthStencilTarget<R::DepthFormat> as DataLink<'d>>::Init>,
     pub samplers: Vec<<gfx::Sampler as DataLink<'d>>::Init>,
     pub textures: Vec<<gfx::RawShaderResource as DataLink<'d>>::Init>,
     pub vertices: Vec<<gfx::RawVertexBuffer as DataLink<'d>>::Init>,
 }
 
+/// Kind of shader variable a binding refers to.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum BindingKind {
+    ConstantBuffer,
+    Global,
+    Output,
+    Sampler,
+    Texture,
+    VertexAttribute,
+}
+
+impl fmt::Display for BindingKind {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(match *self {
+            BindingKind::ConstantBuffer => "constant buffer",
+            BindingKind::Global => "global",
+            BindingKind::Output => "output",
+            BindingKind::Sampler => "sampler",
+            BindingKind::Texture => "texture",
+            BindingKind::VertexAttribute => "vertex attribute"
+        })
+    }
+}
+
+/// Shader variable named by a program or an initializer.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Binding {
+    pub kind: BindingKind,
+    pub name: String,
+}
+
+impl Binding {
+    pub fn new(kind: BindingKind, name: &str) -> Binding {
+        Binding { kind, name: name.to_string() }
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{} {}", self.kind, self.name)
+    }
+}
+
+/// Every disagreement between an initializer and the program it is linked to.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct BindingError {
+    /// Program variables the initializer does not name.
+    pub missing: Vec<Binding>,
+    /// Initializer entries the program does not declare.
+    pub extra: Vec<Binding>,
+    /// Variables whose declared type the initializer's entry cannot bind.
+    pub mismatched: Vec<Binding>,
+}
+
+impl BindingError {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.mismatched.is_empty()
+    }
+}
+
+impl error::Error for BindingError {
+    fn description(&self) -> &str {
+        "Initializer does not match the program's variables."
+    }
+}
+
+impl fmt::Display for BindingError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let groups = [("missing", &self.missing), ("extra", &self.extra), ("mismatched", &self.mismatched)];
+        let mut first = true;
+
+        for &(label, bindings) in groups.iter().filter(|group| !group.1.is_empty()) {
+            let names: Vec<String> = bindings.iter().map(|binding| binding.to_string()).collect();
+            write!(fmt, "{}{}: {}", if first { "" } else { "; " }, label, names.join(", "))?;
+            first = false;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'d, R> Init<'d, R> where R: backend::Backend {
+    /// Compares the initializer against a program's variables by name.
+    ///
+    /// Linking stops at the first problem it finds; this lists them all, so it is worth running
+    /// before creating a pipeline state.
+    pub fn check(&self, info: &gfx::ProgramInfo) -> Result<(), BindingError> {
+        let mut error = BindingError::default();
+
+        {
+            let mut compare = |kind: BindingKind, declared: Vec<&str>, named: Vec<&str>, mismatched: Vec<&str>| {
+                for name in declared.iter().filter(|name| !named.contains(*name)) {
+                    error.missing.push(Binding::new(kind, name));
+                }
+
+                for name in named.iter().filter(|name| !declared.contains(*name)) {
+                    error.extra.push(Binding::new(kind, name));
+                }
+
+                for name in mismatched.iter() {
+                    error.mismatched.push(Binding::new(kind, name));
+                }
+            };
+
+            compare(BindingKind::ConstantBuffer,
+                    info.constant_buffers.iter().map(|var| var.name.as_str()).collect(),
+                    self.constants.iter().cloned().collect(),
+                    info.constant_buffers.iter()
+                        .filter(|var| self.constants.iter().any(|init| {
+                            let mut link = <gfx::RawConstantBuffer as DataLink<'d>>::new();
+                            link.link_constant_buffer(var, init).map_or(false, |res| res.is_err())
+                        }))
+                        .map(|var| var.name.as_str())
+                        .collect());
+
+            compare(BindingKind::Global,
+                    info.globals.iter().map(|var| var.name.as_str()).collect(),
+                    self.globals.iter().cloned().collect(),
+                    info.globals.iter()
+                        .filter(|var| self.globals.iter().any(|init| {
+                            let mut link = <gfx::RawGlobal as DataLink<'d>>::new();
+                            link.link_global_constant(var, init).map_or(false, |res| res.is_err())
+                        }))
+                        .map(|var| var.name.as_str())
+                        .collect());
+
+            compare(BindingKind::Output,
+                    info.outputs.iter().map(|var| var.name.as_str()).collect(),
+                    self.color_targets.iter().map(|init| init.0).chain(self.raw_color_targets.iter().map(|init| init.0)).collect(),
+                    info.outputs.iter()
+                        .filter(|var| self.color_targets.iter().any(|init| {
+                            let mut link = <target::BlendTarget<R::ColorFormat> as DataLink<'d>>::new();
+                            link.link_output(var, init).map_or(false, |res| res.is_err())
+                        }) || self.raw_color_targets.iter().any(|init| {
+                            let mut link = <gfx::RawRenderTarget as DataLink<'d>>::new();
+                            link.link_output(var, init).map_or(false, |res| res.is_err())
+                        }))
+                        .map(|var| var.name.as_str())
+                        .collect());
+
+            compare(BindingKind::Sampler,
+                    info.samplers.iter().map(|var| var.name.as_str()).collect(),
+                    self.samplers.iter().cloned().collect(),
+                    Vec::new());
+
+            compare(BindingKind::Texture,
+                    info.textures.iter().map(|var| var.name.as_str()).collect(),
+                    self.textures.iter().cloned().collect(),
+                    info.textures.iter()
+                        .filter(|var| self.textures.iter().any(|init| {
+                            let mut link = <gfx::RawShaderResource as DataLink<'d>>::new();
+                            link.link_resource_view(var, init).map_or(false, |res| res.is_err())
+                        }))
+                        .map(|var| var.name.as_str())
+                        .collect());
+
+            compare(BindingKind::VertexAttribute,
+                    info.vertex_attributes.iter().map(|var| var.name.as_str()).collect(),
+                    self.vertices.iter().flat_map(|init| init.0.iter().map(|element| element.0)).collect(),
+                    info.vertex_attributes.iter()
+                        .filter(|var| self.vertices.iter().any(|init| {
+                            let mut link = <gfx::RawVertexBuffer as DataLink<'d>>::new();
+                            link.link_input(var, init).map_or(false, |res| res.is_err())
+                        }))
+                        .map(|var| var.name.as_str())
+                        .collect());
+        }
+
+        if error.is_empty() {
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+}
+
 impl<'d, R> PipelineInit for Init<'d, R> where R: backend::Backend {
     type Meta = Meta<R>;
 
     fn link_to<'s>(&self, desc: &mut pso::Descriptor, info: &'s gfx::ProgramInfo) -> Result<Self::Meta, pso::InitError<&'s str>> {
         let mut meta = Meta::default();
 
-        for (info, buffer) in info.constant_buffers.iter().zip(&self.constants) {
-            let mut meta_constants = <gfx::RawConstantBuffer as DataLink<'d>>::new();
-            if let Some(res) = meta_constants.link_constant_buffer(info, buffer) {
-                let d = res.map_err(|e| pso::InitError::ConstantBuffer(info.name.as_str(), Some(e)))?;
-                meta.constants.push(meta_constants);
-                desc.constant_buffers[info.slot as usize] = Some(d);
+        for info in info.constant_buffers.iter() {
+            let mut link = <gfx::RawConstantBuffer as DataLink<'d>>::new();
+            let linked = self.constants.iter().filter_map(|buffer| link.link_constant_buffer(info, buffer)).next();
+
+            match linked {
+                Some(res) => {
+                    let d = res.map_err(|e| pso::InitError::ConstantBuffer(info.name.as_str(), Some(e)))?;
+                    desc.constant_buffers[info.slot as usize] = Some(d);
+                    meta.constants.push((info.name.clone(), link));
+                },
+                None => return Err(pso::InitError::ConstantBuffer(info.name.as_str(), None))
             }
         }
 
-        for (info, global) in info.globals.iter().zip(&self.globals) {
-            let mut meta_globals = <gfx::RawGlobal as DataLink<'d>>::new();
-            if let Some(res) = meta_globals.link_global_constant(info, global) {
-                res.map_err(|e| pso::InitError::GlobalConstant(info.name.as_str(), Some(e)))?;
-                meta.globals.push(meta_globals);
+        for info in info.globals.iter() {
+            let mut link = <gfx::RawGlobal as DataLink<'d>>::new();
+            let linked = self.globals.iter().filter_map(|global| link.link_global_constant(info, global)).next();
+
+            match linked {
+                Some(res) => {
+                    res.map_err(|e| pso::InitError::GlobalConstant(info.name.as_str(), Some(e)))?;
+                    meta.globals.push((info.name.clone(), link));
+                },
+                None => return Err(pso::InitError::GlobalConstant(info.name.as_str(), None))
             }
         }
 
-        for (info, color) in info.outputs.iter().zip(&self.out_colors) {
-            let mut meta_color_targets = <target::RenderTarget<R::ColorFormat> as DataLink<'d>>::new();
-            if let Some(res) = meta_color_targets.link_output(info, color) {
+        for info in info.outputs.iter() {
+            let mut link = <target::BlendTarget<R::ColorFormat> as DataLink<'d>>::new();
+            let linked = self.color_targets.iter().filter_map(|color| link.link_output(info, color)).next();
+
+            if let Some(res) = linked {
                 let d = res.map_err(|e| pso::InitError::PixelExport(info.name.as_str(), Some(e)))?;
-                meta.color_targets.push(meta_color_targets);
                 desc.color_targets[info.slot as usize] = Some(d);
+                meta.color_targets.push((info.name.clone(), link));
+                continue;
+            }
+
+            let mut link = <gfx::RawRenderTarget as DataLink<'d>>::new();
+            let linked = self.raw_color_targets.iter().filter_map(|color| link.link_output(info, color)).next();
+
+            match linked {
+                Some(res) => {
+                    let d = res.map_err(|e| pso::InitError::PixelExport(info.name.as_str(), Some(e)))?;
+                    desc.color_targets[info.slot as usize] = Some(d);
+                    meta.raw_color_targets.push((info.name.clone(), link));
+                },
+                None => return Err(pso::InitError::PixelExport(info.name.as_str(), None))
             }
         }
 
-        if let Some(depth) = self.out_depth {
-            let mut meta_depth_target = <target::DepthStencilTarget<R::DepthFormat> as DataLink<'d>>::new();
-            if let Some(d) = meta_depth_target.link_depth_stencil(&depth) {
-                desc.scissor = meta_depth_target.link_scissor();
-                meta.out_depth = Some(meta_depth_target);
+        if let Some(ref depth) = self.depth_target {
+            let mut link = <target::DepthStencilTarget<R::DepthFormat> as DataLink<'d>>::new();
+            if let Some(d) = link.link_depth_stencil(depth) {
+                desc.scissor = link.link_scissor();
                 desc.depth_stencil = Some(d);
+                meta.depth_target = Some(link);
             }
         }
 
-        for (info, smp) in info.samplers.iter().zip(&self.samplers) {
-            let mut meta_samplers = <gfx::Sampler as DataLink<'d>>::new();
-            if let Some(d) = meta_samplers.link_sampler(info, smp) {
-                meta.samplers.push(meta_samplers);
-                desc.samplers[info.slot as usize] = Some(d);
+        for info in info.samplers.iter() {
+            let mut link = <gfx::Sampler as DataLink<'d>>::new();
+            let linked = self.samplers.iter().filter_map(|sampler| link.link_sampler(info, sampler)).next();
+
+            match linked {
+                Some(d) => {
+                    desc.samplers[info.slot as usize] = Some(d);
+                    meta.samplers.push((info.name.clone(), link));
+                },
+                None => return Err(pso::InitError::Sampler(info.name.as_str(), None))
             }
         }
 
-        for (info, tex) in info.textures.iter().zip(&self.textures) {
-            let mut meta_textures = <gfx::RawShaderResource as DataLink<'d>>::new();
-            if let Some(res) = meta_textures.link_resource_view(info, tex) {
-                let d = res.map_err(|_| pso::InitError::ResourceView(info.name.as_str(), Some(())))?;
-                meta.textures.push(meta_textures);
-                desc.resource_views[info.slot as usize] = Some(d);
+        for info in info.textures.iter() {
+            let mut link = <gfx::RawShaderResource as DataLink<'d>>::new();
+            let linked = self.textures.iter().filter_map(|texture| link.link_resource_view(info, texture)).next();
+
+            match linked {
+                Some(res) => {
+                    let d = res.map_err(|_| pso::InitError::ResourceView(info.name.as_str(), Some(())))?;
+                    desc.resource_views[info.slot as usize] = Some(d);
+                    meta.textures.push((info.name.clone(), link));
+                },
+                None => return Err(pso::InitError::ResourceView(info.name.as_str(), None))
             }
         }
 
-        for (i, vbuf) in self.vertex_bufs.iter().enumerate() {
-            let mut meta_vertices = <gfx::RawVertexBuffer as DataLink<'d>>::new();
-            if let Some(d) = meta_vertices.link_vertex_buffer(i as u8, vbuf) {
+        let mut imported = Vec::with_capacity(info.vertex_attributes.len());
+
+        for (i, buffer) in self.vertices.iter().enumerate() {
+            let mut link = <gfx::RawVertexBuffer as DataLink<'d>>::new();
+            if let Some(d) = link.link_vertex_buffer(i as u8, buffer) {
                 for attr in info.vertex_attributes.iter() {
-                    if let Some(res) = meta_vertices.link_input(attr, vbuf) {
+                    if let Some(res) = link.link_input(attr, buffer) {
                         let d = res.map_err(|e| pso::InitError::VertexImport(attr.name.as_str(), Some(e)))?;
                         desc.attributes[attr.slot as usize] = Some(d);
+                        imported.push(attr.slot);
                     }
                 }
 
-                meta.vertices.push(meta_vertices);
                 desc.vertex_buffers[i] = Some(d);
+                meta.vertices.push(link);
             }
         }
 
+        if let Some(attr) = info.vertex_attributes.iter().find(|attr| !imported.contains(&attr.slot)) {
+            return Err(pso::InitError::VertexImport(attr.name.as_str(), None));
+        }
+
         Ok(meta)
     }
 }
 
-#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+/// Resources bound to a pipeline's shader variables, keyed by variable name.
+///
+/// Variables without an entry are left unbound. Vertex buffers are bound to slots in the order
+/// the initializer lists them.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""), Debug(bound = ""), Default(bound = ""), Eq(bound = ""), Hash(bound = ""), PartialEq(bound = ""))]
 pub struct Data<B: backend::Backend> {
-    pub constants: Vec<<gfx::RawConstantBuffer as DataBind<B::Resources>>::Data>,
-    pub globals: Vec<<gfx::RawGlobal as DataBind<B::Resources>>::Data>,
-    pub color_targets: Vec<<gfx::RenderTarget<B::ColorFormat> as DataBind<B::Resources>>::Data>,
+    pub constants: BTreeMap<String, <gfx::RawConstantBuffer as DataBind<B::Resources>>::Data>,
+    pub globals: BTreeMap<String, <gfx::RawGlobal as DataBind<B::Resources>>::Data>,
+    pub color_targets: BTreeMap<String, <gfx::BlendTarget<B::ColorFormat> as DataBind<B::Resources>>::Data>,
+    pub raw_color_targets: BTreeMap<String, <gfx::RawRenderTarget as DataBind<B::Resources>>::Data>,
     pub depth_target: Option<<gfx::DepthStencilTarget<B::DepthFormat> as DataBind<B::Resources>>::Data>,
-    pub samplers: Vec<<gfx::Sampler as DataBind<B::Resources>>::Data>,
-    pub textures: Vec<<gfx::RawShaderResource as DataBind<B::Resources>>::Data>,
+    pub samplers: BTreeMap<String, <gfx::Sampler as DataBind<B::Resources>>::Data>,
+    pub textures: BTreeMap<String, <gfx::RawShaderResource as DataBind<B::Resources>>::Data>,
     pub vertices: Vec<<gfx::RawVertexBuffer as DataBind<B::Resources>>::Data>,
 }
 
@@ -118,39 +358,104 @@ impl<B> PipelineData<B::Resources> for Data<B> where B: backend::Backend {
     type Meta = Meta<B>;
 
     fn bake_to(&self, out: &mut gfx::pso::RawDataSet<B::Resources>, meta: &Self::Meta, manager: &mut gfx::handle::Manager<B::Resources>, access: &mut gfx::pso::AccessInfo<B::Resources>) {
-        let constants = meta.const_bufs.iter().zip(&self.constants);
-        for (meta_buffer, buffer) in constants {
-            meta_buffer.bind_to(out, &buffer, manager, access);
+        for &(ref name, ref meta_buffer) in meta.constants.iter() {
+            if let Some(buffer) = self.constants.get(name) {
+                meta_buffer.bind_to(out, buffer, manager, access);
+            }
         }
 
-        let globals = meta.globals.iter().zip(&self.globals);
-        for (meta_global, global) in globals {
-            meta_global.bind_to(out, &global, manager, access);
+        for &(ref name, ref meta_global) in meta.globals.iter() {
+            if let Some(global) = self.globals.get(name) {
+                meta_global.bind_to(out, global, manager, access);
+            }
+        }
+
+        for &(ref name, ref meta_target) in meta.color_targets.iter() {
+            if let Some(target) = self.color_targets.get(name) {
+                meta_target.bind_to(out, target, manager, access);
+            }
+        }
+
+        for &(ref name, ref meta_target) in meta.raw_color_targets.iter() {
+            if let Some(target) = self.raw_color_targets.get(name) {
+                meta_target.bind_to(out, target, manager, access);
+            }
         }
 
-        let color_targets = meta.color_targets.iter().zip(&self.color_targets);
-        for (meta_target, target) in color_targets {
-            meta_target.bind_to(out, &target, manager, access);
+        if let (Some(ref meta_target), Some(ref target)) = (meta.depth_target.as_ref(), self.depth_target.as_ref()) {
+            meta_target.bind_to(out, target, manager, access);
         }
 
-        let depth_target = (meta.depth_target.as_ref(), self.depth_target.as_ref());
-        if let (Some(ref meta_target), Some(ref target)) = depth_target {
-            meta_target.bind_to(out, &target, manager, access);
+        for &(ref name, ref meta_sampler) in meta.samplers.iter() {
+            if let Some(sampler) = self.samplers.get(name) {
+                meta_sampler.bind_to(out, sampler, manager, access);
+            }
         }
 
-        let samplers = meta.samplers.iter().zip(&self.samplers);
-        for (meta_sampler, sampler) in samplers {
-            meta_sampler.bind_to(out, &sampler, manager, access);
+        for &(ref name, ref meta_texture) in meta.textures.iter() {
+            if let Some(texture) = self.textures.get(name) {
+                meta_texture.bind_to(out, texture, manager, access);
+            }
         }
 
-        let textures = meta.textures.iter().zip(&self.textures);
-        for (meta_texture, texture) in textures {
-            meta_texture.bind_to(out, &texture, manager, access);
+        for (meta_buffer, buffer) in meta.vertices.iter().zip(&self.vertices) {
+            meta_buffer.bind_to(out, buffer, manager, access);
         }
+    }
+}
+
+/// Collects name and value pairs into the map `Data` keys its entries by.
+pub fn named<'a, V, I>(entries: I) -> BTreeMap<String, V> where I: IntoIterator<Item = (&'a str, V)> {
+    entries.into_iter().map(|(name, value)| (name.to_string(), value)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gfx::format::{ChannelType, Format, SurfaceType};
+    use gfx::pso::buffer::Element;
+    use gfx::preset::blend::REPLACE;
+    use gfx::shade::{AttributeVar, BaseType, ContainerType, OutputVar};
+    use gfx::state::MASK_ALL;
+    use render::backend::OpenGL;
+
+    static ATTRIBUTES: [(&'static str, Element<Format>); 1] = [
+        ("a_Position", Element { format: Format(SurfaceType::R32_G32_B32, ChannelType::Float), offset: 0 }),
+    ];
 
-        let vertices = meta.vertex_bufs.iter().zip(&self.vertex_bufs);
-        for (meta_buffer, buffer) in vertices {
-            meta_buffer.bind_to(out, &buffer, manager, access);
+    fn info() -> gfx::ProgramInfo {
+        gfx::ProgramInfo {
+            vertex_attributes: vec![AttributeVar { name: "a_Position".to_string(), slot: 0, base_type: BaseType::F32, container: ContainerType::Vector(3) }],
+            globals: Vec::new(),
+            constant_buffers: Vec::new(),
+            textures: Vec::new(),
+            unordereds: Vec::new(),
+            samplers: Vec::new(),
+            outputs: vec![OutputVar { name: "o_Color".to_string(), slot: 0, base_type: BaseType::F32, container: ContainerType::Vector(4) }],
+            output_depth: false,
+            knows_outputs: true,
         }
     }
+
+    #[test]
+    fn checking_bindings() {
+        let mut init: Init<OpenGL> = Init::default();
+        init.color_targets.push(("o_Color", MASK_ALL, REPLACE));
+        init.vertices.push((&ATTRIBUTES[..], 12, 0));
+        assert_eq!(init.check(&info()), Ok(()));
+
+        init.color_targets[0].0 = "o_Target";
+        init.constants.push("Locals");
+
+        let error = init.check(&info()).unwrap_err();
+        assert_eq!(error.missing, vec![Binding::new(BindingKind::Output, "o_Color")]);
+        assert_eq!(error.extra, vec![Binding::new(BindingKind::ConstantBuffer, "Locals"), Binding::new(BindingKind::Output, "o_Target")]);
+        assert!(error.mismatched.is_empty());
+        assert_eq!(error.to_string(), "missing: output o_Color; extra: constant buffer Locals, output o_Target");
+
+        init.color_targets.clear();
+        init.constants.clear();
+        init.raw_color_targets.push(("o_Color", Format(SurfaceType::R16_G16_B16_A16, ChannelType::Float), MASK_ALL, None));
+        assert_eq!(init.check(&info()), Ok(()));
+    }
 }
@@ -0,0 +1,349 @@
+use gfx;
+use std::collections::BTreeMap;
+use gfx::format::{ChannelType, Format, SurfaceType};
+use gfx::preset::blend::REPLACE;
+use gfx::state::{Comparison, Depth, MASK_ALL, Rasterizer};
+use gfx::texture;
+use core::platform::Platform;
+use render::backend;
+use render::command::{CommandBucket, DrawBucket, DrawCommand, DrawKey, EffectId};
+use render::effect::{Effect, EffectBuilder};
+use render::error::RenderResult;
+use render::gbuffer::{self, ColorAttachment};
+use render::pipeline::{self, Data, Init};
+
+// Constants shared by every post-process effect.
+//
+// `texel_size` holds the reciprocal target size followed by the size itself; the meaning of
+// `params` depends on the effect.
+gfx_defines! {
+    constant PostLocals {
+        texel_size: [f32; 4] = "u_TexelSize",
+        params: [f32; 4] = "u_Params",
+    }
+}
+
+/// A full-screen effect in the post-process stack.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PostEffect {
+    /// Maps HDR color into displayable range. Params: exposure.
+    Tonemap,
+    /// Encodes linear color for display. Params: gamma.
+    Gamma,
+    /// Smooths aliased edges. Params: edge threshold, minimum threshold, span limit.
+    Fxaa,
+    /// Adds a glow around bright areas. Params: threshold, intensity, radius in texels.
+    Bloom,
+    /// Darkens the screen edges. Params: intensity, inner radius, outer radius.
+    Vignette,
+    /// Grades color through a lookup table. Params: table size, blend strength.
+    ColorGrading,
+}
+
+impl PostEffect {
+    pub fn default_params(&self) -> [f32; 4] {
+        match *self {
+            PostEffect::Tonemap => [1.0f32, 0.0f32, 0.0f32, 0.0f32],
+            PostEffect::Gamma => [2.2f32, 0.0f32, 0.0f32, 0.0f32],
+            PostEffect::Fxaa => [0.125f32, 0.0312f32, 8.0f32, 0.0f32],
+            PostEffect::Bloom => [1.0f32, 0.5f32, 8.0f32, 0.0f32],
+            PostEffect::Vignette => [0.5f32, 0.6f32, 1.0f32, 0.0f32],
+            PostEffect::ColorGrading => [16.0f32, 1.0f32, 0.0f32, 0.0f32],
+        }
+    }
+
+    /// Describes the effect rendering this step, reading `t_Source` and writing `o_Color`.
+    ///
+    /// Passes write the stack's intermediate targets, so `format` must be the stack's format.
+    pub fn effect<B>(&self, format: Format) -> EffectBuilder<B> where B: backend::Backend {
+        let pixel: &'static [u8] = match *self {
+            PostEffect::Tonemap => &include_bytes!("../../resources/shaders/glsl/tonemap.frag")[..],
+            PostEffect::Gamma => &include_bytes!("../../resources/shaders/glsl/gamma.frag")[..],
+            PostEffect::Fxaa => &include_bytes!("../../resources/shaders/glsl/fxaa.frag")[..],
+            PostEffect::Bloom => &include_bytes!("../../resources/shaders/glsl/bloom.frag")[..],
+            PostEffect::Vignette => &include_bytes!("../../resources/shaders/glsl/vignette.frag")[..],
+            PostEffect::ColorGrading => &include_bytes!("../../resources/shaders/glsl/lut.frag")[..],
+        };
+
+        let resources = if *self == PostEffect::ColorGrading {
+            vec!["t_Source", "t_Lut"]
+        } else {
+            vec!["t_Source"]
+        };
+
+        fullscreen_effect(pixel, resources, Some(format))
+    }
+}
+
+/// Describes the effect copying the last post-process output to the backbuffer.
+pub fn blit_effect<B>() -> EffectBuilder<B> where B: backend::Backend {
+    fullscreen_effect(&include_bytes!("../../resources/shaders/glsl/blit.frag")[..], vec!["t_Source"], None)
+}
+
+/// Full-screen effect writing `o_Color` raw in `format`, or through the backend's color format
+/// if there is none.
+fn fullscreen_effect<B>(pixel: &'static [u8], resources: Vec<&'static str>, format: Option<Format>) -> EffectBuilder<B> where B: backend::Backend {
+    let (color_targets, raw_color_targets) = match format {
+        Some(format) => (Vec::new(), vec![("o_Color", format, MASK_ALL, Some(REPLACE))]),
+        None => (vec![("o_Color", MASK_ALL, REPLACE)], Vec::new())
+    };
+
+    Effect::new_minimal(&include_bytes!("../../resources/shaders/glsl/fullscreen.vert")[..], pixel)
+        .with_depth_state(Depth { fun: Comparison::Always, write: false })
+        .with_rasterizer(Rasterizer::new_fill())
+        .with_initializer(Init {
+            constants: vec!["PostLocals"],
+            globals: Vec::new(),
+            color_targets,
+            raw_color_targets,
+            depth_target: None,
+            samplers: resources.clone(),
+            textures: resources,
+            vertices: Vec::new(),
+        })
+}
+
+/// Surface a post-process pass reads from or writes to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Surface {
+    /// The scene color handed to the stack.
+    Input,
+    /// One of the two ping-pong targets.
+    Target(usize),
+    /// The swapchain image.
+    Backbuffer,
+}
+
+/// Source and destination of each of `passes` passes followed by the final blit.
+///
+/// Passes alternate between the two ping-pong targets so no pass samples the target it writes.
+pub fn route(passes: usize) -> Vec<(Surface, Surface)> {
+    let mut routes = Vec::with_capacity(passes + 1);
+    let mut source = Surface::Input;
+
+    for pass in 0..passes {
+        let destination = Surface::Target(pass % 2);
+        routes.push((source, destination));
+        source = destination;
+    }
+
+    routes.push((source, Surface::Backbuffer));
+    routes
+}
+
+struct PostPass<R: gfx::Resources> {
+    kind: PostEffect,
+    effect: EffectId,
+    enabled: bool,
+    params: [f32; 4],
+    locals: gfx::handle::Buffer<R, PostLocals>,
+}
+
+/// Ordered stack of full-screen effects applied to the lit scene.
+///
+/// Enabled effects run in the order they were pushed, ping-ponging between two targets, and the
+/// result is blitted to the backbuffer. Every pass is recorded into a draw bucket under its own
+/// layer, so sorting preserves the declared order.
+pub struct PostProcessStack<B: backend::Backend> {
+    format: Format,
+    size: (u16, u16),
+    targets: [ColorAttachment<B::Resources>; 2],
+    passes: Vec<PostPass<B::Resources>>,
+    blit: EffectId,
+    blit_locals: gfx::handle::Buffer<B::Resources, PostLocals>,
+    sampler: gfx::handle::Sampler<B::Resources>,
+    lut: Option<gfx::handle::RawShaderResourceView<B::Resources>>,
+}
+
+impl<B> PostProcessStack<B> where B: backend::Backend {
+    /// Creates an empty stack whose intermediate targets use a half-float HDR format.
+    ///
+    /// `blit` identifies the effect built from `blit_effect`.
+    pub fn new(platform: &mut Platform<B>, width: u16, height: u16, blit: EffectId) -> RenderResult<PostProcessStack<B>> {
+        PostProcessStack::with_format(platform, Format(SurfaceType::R16_G16_B16_A16, ChannelType::Float), width, height, blit)
+    }
+
+    pub fn with_format(platform: &mut Platform<B>, format: Format, width: u16, height: u16, blit: EffectId) -> RenderResult<PostProcessStack<B>> {
+        use gfx::Factory;
+        use gfx::traits::FactoryExt;
+
+        let targets = [
+            ColorAttachment::new(platform, format, width, height)?,
+            ColorAttachment::new(platform, format, width, height)?,
+        ];
+
+        let sampler = platform.create_sampler(texture::SamplerInfo::new(texture::FilterMethod::Bilinear, texture::WrapMode::Clamp));
+
+        Ok(PostProcessStack {
+            format,
+            size: (width, height),
+            targets,
+            passes: Vec::new(),
+            blit,
+            blit_locals: platform.create_constant_buffer(1),
+            sampler,
+            lut: None,
+        })
+    }
+
+    pub fn size(&self) -> (u16, u16) {
+        self.size
+    }
+
+    /// Format of the intermediate targets, which pass effects must be built for.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Recreates the ping-pong targets at a new swapchain size. Returns whether anything was
+    /// recreated.
+    pub fn resize(&mut self, platform: &mut Platform<B>, width: u16, height: u16) -> RenderResult<bool> {
+        if self.size == (width, height) {
+            return Ok(false);
+        }
+
+        self.targets = [
+            ColorAttachment::new(platform, self.format, width, height)?,
+            ColorAttachment::new(platform, self.format, width, height)?,
+        ];
+        self.size = (width, height);
+
+        Ok(true)
+    }
+
+    /// Appends an enabled effect to the end of the stack; `effect` identifies the effect built
+    /// from `PostEffect::effect` for the stack's format.
+    pub fn push(&mut self, platform: &mut Platform<B>, kind: PostEffect, effect: EffectId) {
+        use gfx::traits::FactoryExt;
+
+        assert!(!self.contains(kind), "post effect {:?} is already in the stack", kind);
+
+        self.passes.push(PostPass {
+            kind,
+            effect,
+            enabled: true,
+            params: kind.default_params(),
+            locals: platform.create_constant_buffer(1),
+        });
+    }
+
+    pub fn contains(&self, kind: PostEffect) -> bool {
+        self.passes.iter().any(|pass| pass.kind == kind)
+    }
+
+    /// Effects in the stack in the order they run, whether enabled or not.
+    pub fn effects(&self) -> Vec<PostEffect> {
+        self.passes.iter().map(|pass| pass.kind).collect()
+    }
+
+    pub fn set_enabled(&mut self, kind: PostEffect, enabled: bool) {
+        self.pass_mut(kind).enabled = enabled;
+    }
+
+    pub fn is_enabled(&self, kind: PostEffect) -> bool {
+        self.pass(kind).enabled
+    }
+
+    pub fn set_params(&mut self, kind: PostEffect, params: [f32; 4]) {
+        self.pass_mut(kind).params = params;
+    }
+
+    pub fn params(&self, kind: PostEffect) -> [f32; 4] {
+        self.pass(kind).params
+    }
+
+    /// Sets the lookup table sampled by color grading, laid out as a horizontal strip of blue
+    /// slices. Color grading is skipped while no table is set.
+    pub fn set_lut(&mut self, lut: Option<gfx::handle::RawShaderResourceView<B::Resources>>) {
+        self.lut = lut;
+    }
+
+    /// Uploads pass constants and records every enabled pass and the final blit.
+    pub fn record<C>(&self, encoder: &mut gfx::Encoder<B::Resources, C>, input: &gfx::handle::RawShaderResourceView<B::Resources>,
+                     backbuffer: gfx::handle::RenderTargetView<B::Resources, B::ColorFormat>, bucket: &mut DrawBucket<B>)
+        where C: gfx::CommandBuffer<B::Resources> {
+
+        // Effect, parameters, constants and whether the lookup table is sampled, per step.
+        let mut steps: Vec<(EffectId, [f32; 4], &gfx::handle::Buffer<B::Resources, PostLocals>, bool)> = self.passes.iter()
+            .filter(|pass| pass.enabled && (pass.kind != PostEffect::ColorGrading || self.lut.is_some()))
+            .map(|pass| (pass.effect, pass.params, &pass.locals, pass.kind == PostEffect::ColorGrading))
+            .collect();
+
+        steps.push((self.blit, [0.0f32; 4], &self.blit_locals, false));
+
+        let (width, height) = (self.size.0 as f32, self.size.1 as f32);
+        let texel_size = [1.0f32 / width, 1.0f32 / height, width, height];
+        let routes = route(steps.len() - 1);
+
+        for (index, (&(effect, params, locals, grading), (source, destination))) in steps.iter().zip(routes).enumerate() {
+            encoder.update_constant_buffer(locals, &PostLocals { texel_size, params });
+
+            let source = match source {
+                Surface::Target(target) => self.targets[target].resource.clone().expect("single-sampled targets are viewable"),
+                _ => input.clone(),
+            };
+
+            let mut textures = vec![("t_Source", source)];
+            if grading {
+                textures.extend(self.lut.clone().map(|lut| ("t_Lut", lut)));
+            }
+
+            let mut data = Data {
+                constants: pipeline::named(vec![("PostLocals", locals.raw().clone())]),
+                globals: BTreeMap::new(),
+                color_targets: BTreeMap::new(),
+                raw_color_targets: BTreeMap::new(),
+                depth_target: None,
+                samplers: pipeline::named(textures.iter().map(|&(name, _)| (name, self.sampler.clone()))),
+                textures: pipeline::named(textures),
+                vertices: Vec::new(),
+            };
+
+            // Intermediate targets keep their own format; only the backbuffer is typed.
+            match destination {
+                Surface::Target(target) => {
+                    data.raw_color_targets.insert("o_Color".to_string(), self.targets[target].target.clone());
+                },
+                _ => {
+                    data.color_targets.insert("o_Color".to_string(), backbuffer.clone());
+                }
+            }
+
+            bucket.push(DrawKey::opaque(index as u8, effect, 0.0f32), DrawCommand {
+                effect,
+                slice: gbuffer::fullscreen_slice(),
+                data,
+            });
+        }
+    }
+
+    fn pass(&self, kind: PostEffect) -> &PostPass<B::Resources> {
+        match self.passes.iter().find(|pass| pass.kind == kind) {
+            Some(pass) => pass,
+            None => panic!("post effect {:?} is not in the stack", kind)
+        }
+    }
+
+    fn pass_mut(&mut self, kind: PostEffect) -> &mut PostPass<B::Resources> {
+        match self.passes.iter_mut().find(|pass| pass.kind == kind) {
+            Some(pass) => pass,
+            None => panic!("post effect {:?} is not in the stack", kind)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routing_passes() {
+        assert_eq!(route(0), vec![(Surface::Input, Surface::Backbuffer)]);
+        assert_eq!(route(3), vec![
+            (Surface::Input, Surface::Target(0)),
+            (Surface::Target(0), Surface::Target(1)),
+            (Surface::Target(1), Surface::Target(0)),
+            (Surface::Target(0), Surface::Backbuffer),
+        ]);
+    }
+}
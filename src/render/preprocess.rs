@@ -0,0 +1,308 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use core::platform::Platform;
+use render::backend;
+use render::effect::{Effect, EffectBuilder};
+use render::error::{RenderError, RenderResult};
+
+/// Set of preprocessor definitions selecting a shader permutation.
+///
+/// Definitions are kept sorted so sets built in any order compare and hash alike.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct DefineSet {
+    defines: BTreeMap<String, String>,
+}
+
+impl DefineSet {
+    pub fn new() -> DefineSet {
+        DefineSet::default()
+    }
+
+    /// Adds a flag definition such as `SKINNED`.
+    pub fn with(self, name: &str) -> DefineSet {
+        self.with_value(name, "1")
+    }
+
+    pub fn with_value(mut self, name: &str, value: &str) -> DefineSet {
+        self.defines.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.defines.is_empty()
+    }
+
+    /// The definitions as GLSL preprocessor lines.
+    pub fn lines(&self) -> String {
+        self.defines.iter().map(|(name, value)| format!("#define {} {}\n", name, value)).collect()
+    }
+}
+
+/// Resolves `#include` directives and injects definitions into GLSL sources.
+///
+/// Include paths, quoted or in angle brackets, are relative to the shader root. Each file is
+/// included at most once per shader, so shared headers need no include guards; a file that ends
+/// up including itself is reported as a cycle. Definitions are inserted after the `#version`
+/// directive, which GLSL requires to come first.
+pub struct Preprocessor {
+    root: PathBuf,
+}
+
+impl Preprocessor {
+    pub fn new<P>(root: P) -> Preprocessor where P: AsRef<Path> {
+        Preprocessor {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Preprocesses a shader, given relative to the root, into a single source.
+    pub fn process(&self, shader: &str, defines: &DefineSet) -> RenderResult<String> {
+        self.process_with_files(shader, defines, &mut Vec::new())
+    }
+
+    /// Preprocesses a shader like `process`, appending the paths of the shader and every file
+    /// it includes to `files`.
+    pub fn process_with_files(&self, shader: &str, defines: &DefineSet, files: &mut Vec<PathBuf>) -> RenderResult<String> {
+        let mut included = Vec::new();
+        let mut body = String::new();
+
+        self.expand(shader, &mut included, &mut Vec::new(), &mut body)?;
+        files.extend(included);
+
+        let mut output = String::with_capacity(body.len());
+        let mut lines = body.lines().peekable();
+
+        if lines.peek().map_or(false, |line| line.trim_left().starts_with("#version")) {
+            output.push_str(lines.next().unwrap());
+            output.push('\n');
+        }
+
+        output.push_str(&defines.lines());
+
+        for line in lines {
+            output.push_str(line);
+            output.push('\n');
+        }
+
+        Ok(output)
+    }
+
+    /// Appends a file to the output with its includes expanded. `stack` holds the files being
+    /// expanded, outermost first, so a file found on it is an include cycle.
+    fn expand(&self, shader: &str, included: &mut Vec<PathBuf>, stack: &mut Vec<PathBuf>, output: &mut String) -> RenderResult<()> {
+        let path = self.root.join(shader);
+
+        if let Some(start) = stack.iter().position(|file| *file == path) {
+            let mut cycle: Vec<String> = stack[start..].iter().map(|file| file.display().to_string()).collect();
+            cycle.push(path.display().to_string());
+            return Err(RenderError::IncludeCycle(cycle));
+        }
+
+        if included.contains(&path) {
+            return Ok(());
+        }
+
+        let code = read_source(&path)?;
+        included.push(path.clone());
+        stack.push(path);
+
+        for line in code.lines() {
+            match include_target(line) {
+                Some(target) => self.expand(target, included, stack, output)?,
+                None => {
+                    output.push_str(line);
+                    output.push('\n');
+                }
+            }
+        }
+
+        stack.pop();
+        Ok(())
+    }
+}
+
+/// Path named by an `#include` line, if the line is one.
+fn include_target(line: &str) -> Option<&str> {
+    let line = line.trim();
+    if !line.starts_with('#') {
+        return None;
+    }
+
+    let directive = line[1..].trim_left();
+    if !directive.starts_with("include") {
+        return None;
+    }
+
+    let target = directive["include".len()..].trim();
+    let quoted = (target.starts_with('"') && target.ends_with('"')) || (target.starts_with('<') && target.ends_with('>'));
+
+    if quoted && target.len() > 2 {
+        Some(&target[1..target.len() - 1])
+    } else {
+        None
+    }
+}
+
+fn read_source(path: &Path) -> RenderResult<String> {
+    use std::fs::File;
+    use std::io::Read;
+
+    let mut code = String::new();
+
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut code))
+        .map_err(|e| RenderError::ShaderLoad(path.display().to_string(), e))?;
+
+    Ok(code)
+}
+
+/// Effects built from preprocessed shader permutations.
+///
+/// Each unique combination of shaders and definitions is preprocessed and compiled once; later
+/// requests return the cached effect. Caches are typed by backend, so every backend compiles
+/// its own copy.
+///
+/// Effects record the shaders and includes they were preprocessed from, so a `ShaderWatcher`
+/// can track them; changed permutations are preprocessed again through `reload`.
+pub struct Permutations<B: backend::Backend> {
+    preprocessor: Preprocessor,
+    effects: Vec<Effect<B>>,
+    keys: Vec<(String, String, DefineSet)>,
+    index: HashMap<(String, String, DefineSet), usize>,
+}
+
+impl<B> Permutations<B> where B: backend::Backend {
+    pub fn new(preprocessor: Preprocessor) -> Permutations<B> {
+        Permutations {
+            preprocessor,
+            effects: Vec::new(),
+            keys: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+
+    pub fn preprocessor(&self) -> &Preprocessor {
+        &self.preprocessor
+    }
+
+    pub fn len(&self) -> usize {
+        self.effects.len()
+    }
+
+    /// Index of the effect built from a vertex and pixel shader with the given definitions,
+    /// building it on first request.
+    ///
+    /// `configure` sets up everything but the program, such as the initializer and fixed
+    /// function state, and is only invoked when the permutation is built.
+    pub fn get<F>(&mut self, platform: &mut Platform<B>, vertex: &str, pixel: &str, defines: &DefineSet, configure: F) -> RenderResult<usize>
+        where F: FnOnce(EffectBuilder<B>) -> EffectBuilder<B> {
+
+        let key = (vertex.to_string(), pixel.to_string(), defines.clone());
+
+        if let Some(&index) = self.index.get(&key) {
+            return Ok(index);
+        }
+
+        let (vertex_code, pixel_code, files) = self.preprocess(vertex, pixel, defines)?;
+        let builder = EffectBuilder::from_code(vertex_code, pixel_code).with_dependencies(files);
+        let effect = configure(builder).build(platform)?;
+
+        self.effects.push(effect);
+        self.keys.push(key.clone());
+        self.index.insert(key, self.effects.len() - 1);
+
+        Ok(self.effects.len() - 1)
+    }
+
+    /// Preprocesses a permutation's shaders again and swaps in the rebuilt pipeline state.
+    ///
+    /// On failure the error is returned and the effect keeps its previous program.
+    pub fn reload(&mut self, platform: &mut Platform<B>, index: usize) -> RenderResult<()> {
+        let (vertex_code, pixel_code, files) = {
+            let (ref vertex, ref pixel, ref defines) = self.keys[index];
+            self.preprocess(vertex, pixel, defines)?
+        };
+
+        let effect = &mut self.effects[index];
+        let description = effect.description().clone()
+            .with_program(vertex_code, pixel_code)
+            .with_dependencies(files);
+
+        effect.rebuild(platform, description)
+    }
+
+    pub fn effect(&self, index: usize) -> &Effect<B> {
+        &self.effects[index]
+    }
+
+    pub fn effects(&self) -> &[Effect<B>] {
+        &self.effects
+    }
+
+    fn preprocess(&self, vertex: &str, pixel: &str, defines: &DefineSet) -> RenderResult<(Vec<u8>, Vec<u8>, Vec<PathBuf>)> {
+        let mut files = Vec::new();
+        let vertex_code = self.preprocessor.process_with_files(vertex, defines, &mut files)?;
+        let pixel_code = self.preprocessor.process_with_files(pixel, defines, &mut files)?;
+
+        Ok((vertex_code.into_bytes(), pixel_code.into_bytes(), files))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preprocessor() -> Preprocessor {
+        Preprocessor::new(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/resources/shaders/preprocess"))
+    }
+
+    #[test]
+    fn resolving_includes() {
+        let source = preprocessor().process("main.frag", &DefineSet::new()).unwrap();
+
+        assert!(source.starts_with("#version 150 core\n"));
+        assert!(!source.contains("#include"));
+        assert_eq!(source.matches("float saturate").count(), 1);
+        assert!(source.find("float saturate").unwrap() < source.find("vec3 shade").unwrap());
+
+        let mut files = Vec::new();
+        preprocessor().process_with_files("main.frag", &DefineSet::new(), &mut files).unwrap();
+
+        let names: Vec<&str> = files.iter().map(|file| file.file_name().unwrap().to_str().unwrap()).collect();
+        assert_eq!(names, vec!["main.frag", "lighting.glsl", "math.glsl"]);
+    }
+
+    #[test]
+    fn injecting_defines() {
+        let defines = DefineSet::new().with("SHADOWS").with_value("MAX_LIGHTS", "64");
+        let source = preprocessor().process("main.frag", &defines).unwrap();
+        let lines: Vec<&str> = source.lines().take(3).collect();
+
+        assert_eq!(lines, vec!["#version 150 core", "#define MAX_LIGHTS 64", "#define SHADOWS 1"]);
+        assert_eq!(defines, DefineSet::new().with_value("MAX_LIGHTS", "64").with("SHADOWS"));
+    }
+
+    #[test]
+    fn reporting_missing_includes() {
+        match preprocessor().process("missing.frag", &DefineSet::new()) {
+            Err(RenderError::ShaderLoad(ref path, _)) => assert!(path.ends_with("missing.frag")),
+            _ => panic!("expected a load error")
+        }
+
+        match preprocessor().process("cycle.frag", &DefineSet::new()) {
+            Err(RenderError::IncludeCycle(ref files)) => {
+                assert_eq!(files.len(), 3);
+                assert!(files[0].ends_with("a.glsl") && files[1].ends_with("b.glsl") && files[2].ends_with("a.glsl"));
+            },
+            _ => panic!("expected an include cycle")
+        }
+
+        assert_eq!(include_target("  #  include <common/math.glsl>"), Some("common/math.glsl"));
+        assert_eq!(include_target("#include"), None);
+        assert_eq!(include_target("// #include \"a\""), None);
+    }
+}
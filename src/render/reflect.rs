@@ -0,0 +1,701 @@
+use std::collections::HashMap;
+use gfx::format::{ChannelType, Format, SurfaceType};
+use gfx::preset::blend::REPLACE;
+use gfx::preset::depth::LESS_EQUAL_WRITE;
+use gfx::pso::buffer::{ElemOffset, ElemStride, Element};
+use gfx::state::{Blend, ColorMask, Comparison, Stencil, StencilOp, MASK_ALL};
+use render::backend;
+use render::error::{RenderError, RenderResult};
+use render::pipeline::Init;
+
+const MAGIC: u32 = 0x0723_0203;
+
+const OP_NAME: u16 = 5;
+const OP_MEMBER_NAME: u16 = 6;
+const OP_ENTRY_POINT: u16 = 15;
+const OP_EXECUTION_MODE: u16 = 16;
+const OP_TYPE_BOOL: u16 = 20;
+const OP_TYPE_INT: u16 = 21;
+const OP_TYPE_FLOAT: u16 = 22;
+const OP_TYPE_VECTOR: u16 = 23;
+const OP_TYPE_MATRIX: u16 = 24;
+const OP_TYPE_IMAGE: u16 = 25;
+const OP_TYPE_SAMPLER: u16 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u16 = 27;
+const OP_TYPE_ARRAY: u16 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u16 = 29;
+const OP_TYPE_STRUCT: u16 = 30;
+const OP_TYPE_POINTER: u16 = 32;
+const OP_CONSTANT: u16 = 43;
+const OP_VARIABLE: u16 = 59;
+const OP_DECORATE: u16 = 71;
+const OP_MEMBER_DECORATE: u16 = 72;
+
+const EXECUTION_MODE_LOCAL_SIZE: u32 = 17;
+
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_INPUT: u32 = 1;
+const STORAGE_UNIFORM: u32 = 2;
+const STORAGE_OUTPUT: u32 = 3;
+const STORAGE_BUFFER: u32 = 12;
+
+/// Shader stage a module's entry point runs in.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Stage {
+    Vertex,
+    Hull,
+    Domain,
+    Geometry,
+    Pixel,
+    Compute,
+}
+
+/// Component type of scalars, vectors and matrices.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum BaseType {
+    Bool,
+    Int,
+    Uint,
+    Float,
+    Double,
+}
+
+/// Type of a shader variable or block member.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Type {
+    Scalar(BaseType),
+    Vector(BaseType, u8),
+    /// Matrix of columns, rows.
+    Matrix(BaseType, u8, u8),
+    /// Array with its length, zero for runtime sized arrays.
+    Array(Box<Type>, u32),
+    Struct(Vec<Member>),
+    /// Image with its dimensionality as encoded by SPIR-V, 1 being 2D.
+    Image(u32),
+    /// Image read and written without a sampler, with its dimensionality.
+    StorageImage(u32),
+    Sampler,
+    SampledImage(u32),
+}
+
+/// Member of a block or struct.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Member {
+    pub name: String,
+    /// Byte offset within the block, if decorated with one.
+    pub offset: Option<u32>,
+    pub ty: Type,
+}
+
+/// Stage input or output at a location.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Variable {
+    pub name: String,
+    pub location: u32,
+    pub ty: Type,
+}
+
+/// Uniform block, named after its block type as OpenGL names constant buffers.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct UniformBlock {
+    pub name: String,
+    pub set: u32,
+    pub binding: u32,
+    pub members: Vec<Member>,
+}
+
+/// Kind of an opaque shader resource.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ResourceKind {
+    /// Image sampled through a separate sampler.
+    Texture,
+    Sampler,
+    /// Image and sampler combined, as GLSL `sampler2D`.
+    SampledTexture,
+    /// Image read and written without a sampler.
+    StorageImage,
+    /// Buffer read and written by the shader.
+    StorageBuffer,
+}
+
+/// Opaque resource bound to a descriptor set and binding.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Resource {
+    pub name: String,
+    pub set: u32,
+    pub binding: u32,
+    pub kind: ResourceKind,
+}
+
+/// Interface of a SPIR-V module's entry point.
+///
+/// Built-in variables such as `gl_Position` are left out of inputs and outputs, which only list
+/// variables with locations, sorted by location.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ShaderReflection {
+    pub stage: Stage,
+    pub entry_point: String,
+    pub inputs: Vec<Variable>,
+    pub outputs: Vec<Variable>,
+    pub uniform_blocks: Vec<UniformBlock>,
+    pub resources: Vec<Resource>,
+    /// Workgroup size of a compute shader.
+    pub local_size: Option<[u32; 3]>,
+}
+
+#[derive(Clone, Debug)]
+enum RawType {
+    Scalar(BaseType),
+    Vector(u32, u32),
+    Matrix(u32, u32),
+    Array(u32, u32),
+    RuntimeArray(u32),
+    Struct(Vec<u32>),
+    Image(u32, bool),
+    Sampler,
+    SampledImage(u32),
+    Pointer(u32, u32),
+}
+
+#[derive(Clone, Debug, Default)]
+struct Decorations {
+    buffer_block: bool,
+    built_in: bool,
+    location: Option<u32>,
+    binding: Option<u32>,
+    set: Option<u32>,
+    offset: Option<u32>,
+}
+
+/// Everything the parser collects before types are resolved.
+#[derive(Default)]
+struct Module {
+    entry_point: Option<(u32, String)>,
+    local_size: Option<[u32; 3]>,
+    names: HashMap<u32, String>,
+    member_names: HashMap<(u32, u32), String>,
+    decorations: HashMap<u32, Decorations>,
+    member_decorations: HashMap<(u32, u32), Decorations>,
+    types: HashMap<u32, RawType>,
+    constants: HashMap<u32, u32>,
+    variables: Vec<(u32, u32, u32)>,
+}
+
+impl ShaderReflection {
+    /// Parses a SPIR-V module in either byte order.
+    pub fn parse(code: &[u8]) -> RenderResult<ShaderReflection> {
+        let words = words(code)?;
+        let module = Module::parse(&words)?;
+        module.reflect()
+    }
+
+    /// Input with a name, if the stage reads one.
+    pub fn input(&self, name: &str) -> Option<&Variable> {
+        self.inputs.iter().find(|input| input.name == name)
+    }
+
+    /// Output with a name, if the stage writes one.
+    pub fn output(&self, name: &str) -> Option<&Variable> {
+        self.outputs.iter().find(|output| output.name == name)
+    }
+}
+
+fn malformed(reason: &str) -> RenderError {
+    RenderError::ShaderReflection(reason.to_string())
+}
+
+fn words(code: &[u8]) -> RenderResult<Vec<u32>> {
+    if code.len() % 4 != 0 || code.len() < 20 {
+        return Err(malformed("module is not a whole number of words"));
+    }
+
+    let little = |bytes: &[u8]| bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24;
+    let big = |bytes: &[u8]| bytes[3] as u32 | (bytes[2] as u32) << 8 | (bytes[1] as u32) << 16 | (bytes[0] as u32) << 24;
+
+    if little(&code[..4]) == MAGIC {
+        Ok(code.chunks(4).map(little).collect())
+    } else if big(&code[..4]) == MAGIC {
+        Ok(code.chunks(4).map(big).collect())
+    } else {
+        Err(malformed("missing magic number"))
+    }
+}
+
+/// Nul-terminated UTF-8 string packed into words, lowest byte first.
+fn string(words: &[u32]) -> String {
+    let mut bytes = Vec::new();
+
+    'words: for word in words.iter() {
+        for shift in 0..4 {
+            let byte = (word >> (shift * 8)) as u8;
+            if byte == 0 {
+                break 'words;
+            }
+            bytes.push(byte);
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+impl Module {
+    fn parse(words: &[u32]) -> RenderResult<Module> {
+        let mut module = Module::default();
+        let mut cursor = 5;
+
+        while cursor < words.len() {
+            let count = (words[cursor] >> 16) as usize;
+            let opcode = (words[cursor] & 0xffff) as u16;
+
+            if count == 0 || cursor + count > words.len() {
+                return Err(malformed("instruction overruns the module"));
+            }
+
+            module.instruction(opcode, &words[cursor + 1..cursor + count])?;
+            cursor += count;
+        }
+
+        Ok(module)
+    }
+
+    fn instruction(&mut self, opcode: u16, operands: &[u32]) -> RenderResult<()> {
+        let operand = |index: usize| operands.get(index).cloned().ok_or_else(|| malformed("instruction is missing operands"));
+
+        match opcode {
+            OP_NAME => {
+                self.names.insert(operand(0)?, string(&operands[1..]));
+            },
+            OP_MEMBER_NAME => {
+                self.member_names.insert((operand(0)?, operand(1)?), string(&operands[2..]));
+            },
+            OP_ENTRY_POINT => {
+                // Only the first entry point is reflected.
+                if self.entry_point.is_none() {
+                    if operands.len() < 3 {
+                        return Err(malformed("instruction is missing operands"));
+                    }
+
+                    self.entry_point = Some((operand(0)?, string(&operands[2..])));
+                }
+            },
+            OP_EXECUTION_MODE => {
+                if operand(1)? == EXECUTION_MODE_LOCAL_SIZE {
+                    self.local_size = Some([operand(2)?, operand(3)?, operand(4)?]);
+                }
+            },
+            OP_TYPE_BOOL => {
+                self.types.insert(operand(0)?, RawType::Scalar(BaseType::Bool));
+            },
+            OP_TYPE_INT => {
+                let base = if operand(2)? == 0 { BaseType::Uint } else { BaseType::Int };
+                self.types.insert(operand(0)?, RawType::Scalar(base));
+            },
+            OP_TYPE_FLOAT => {
+                let base = if operand(1)? == 64 { BaseType::Double } else { BaseType::Float };
+                self.types.insert(operand(0)?, RawType::Scalar(base));
+            },
+            OP_TYPE_VECTOR => {
+                self.types.insert(operand(0)?, RawType::Vector(operand(1)?, operand(2)?));
+            },
+            OP_TYPE_MATRIX => {
+                self.types.insert(operand(0)?, RawType::Matrix(operand(1)?, operand(2)?));
+            },
+            OP_TYPE_IMAGE => {
+                // A sampled operand of 2 marks images used without a sampler.
+                self.types.insert(operand(0)?, RawType::Image(operand(2)?, operand(6)? == 2));
+            },
+            OP_TYPE_SAMPLER => {
+                self.types.insert(operand(0)?, RawType::Sampler);
+            },
+            OP_TYPE_SAMPLED_IMAGE => {
+                self.types.insert(operand(0)?, RawType::SampledImage(operand(1)?));
+            },
+            OP_TYPE_ARRAY => {
+                self.types.insert(operand(0)?, RawType::Array(operand(1)?, operand(2)?));
+            },
+            OP_TYPE_RUNTIME_ARRAY => {
+                self.types.insert(operand(0)?, RawType::RuntimeArray(operand(1)?));
+            },
+            OP_TYPE_STRUCT => {
+                self.types.insert(operand(0)?, RawType::Struct(operands[1..].to_vec()));
+            },
+            OP_TYPE_POINTER => {
+                self.types.insert(operand(0)?, RawType::Pointer(operand(1)?, operand(2)?));
+            },
+            OP_CONSTANT => {
+                self.constants.insert(operand(1)?, operand(2)?);
+            },
+            OP_VARIABLE => {
+                self.variables.push((operand(1)?, operand(0)?, operand(2)?));
+            },
+            OP_DECORATE => {
+                let decorations = self.decorations.entry(operand(0)?).or_insert_with(Decorations::default);
+                decorate(decorations, operand(1)?, operands.get(2).cloned());
+            },
+            OP_MEMBER_DECORATE => {
+                let decorations = self.member_decorations.entry((operand(0)?, operand(1)?)).or_insert_with(Decorations::default);
+                decorate(decorations, operand(2)?, operands.get(3).cloned());
+            },
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn reflect(&self) -> RenderResult<ShaderReflection> {
+        let (model, entry_point) = match self.entry_point {
+            Some((model, ref name)) => (model, name.clone()),
+            None => return Err(malformed("module has no entry point"))
+        };
+
+        let stage = match model {
+            0 => Stage::Vertex,
+            1 => Stage::Hull,
+            2 => Stage::Domain,
+            3 => Stage::Geometry,
+            4 => Stage::Pixel,
+            5 => Stage::Compute,
+            _ => return Err(malformed("unsupported execution model"))
+        };
+
+        let mut reflection = ShaderReflection {
+            stage,
+            entry_point,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            uniform_blocks: Vec::new(),
+            resources: Vec::new(),
+            local_size: self.local_size,
+        };
+
+        let no_decorations = Decorations::default();
+
+        for &(id, pointer, storage) in self.variables.iter() {
+            let pointee = match self.types.get(&pointer) {
+                Some(&RawType::Pointer(_, pointee)) => pointee,
+                _ => return Err(malformed("variable is not a pointer"))
+            };
+
+            let decorations = self.decorations.get(&id).unwrap_or(&no_decorations);
+            let name = self.names.get(&id).cloned().unwrap_or_default();
+            let (set, binding) = (decorations.set.unwrap_or(0), decorations.binding.unwrap_or(0));
+
+            match storage {
+                STORAGE_INPUT | STORAGE_OUTPUT => {
+                    let location = match decorations.location {
+                        Some(location) if !decorations.built_in => location,
+                        _ => continue
+                    };
+
+                    let variable = Variable { name, location, ty: self.resolve(pointee)? };
+
+                    if storage == STORAGE_INPUT {
+                        reflection.inputs.push(variable);
+                    } else {
+                        reflection.outputs.push(variable);
+                    }
+                },
+                STORAGE_UNIFORM | STORAGE_BUFFER => {
+                    let block = self.decorations.get(&pointee).unwrap_or(&no_decorations);
+                    let block_name = self.names.get(&pointee).cloned().unwrap_or(name);
+
+                    if storage == STORAGE_BUFFER || block.buffer_block {
+                        reflection.resources.push(Resource { name: block_name, set, binding, kind: ResourceKind::StorageBuffer });
+                    } else {
+                        let members = match self.resolve(pointee)? {
+                            Type::Struct(members) => members,
+                            _ => return Err(malformed("uniform block is not a struct"))
+                        };
+
+                        reflection.uniform_blocks.push(UniformBlock { name: block_name, set, binding, members });
+                    }
+                },
+                STORAGE_UNIFORM_CONSTANT => {
+                    let kind = match self.resolve(pointee)? {
+                        Type::Image(_) => ResourceKind::Texture,
+                        Type::StorageImage(_) => ResourceKind::StorageImage,
+                        Type::Sampler => ResourceKind::Sampler,
+                        Type::SampledImage(_) => ResourceKind::SampledTexture,
+                        _ => continue
+                    };
+
+                    reflection.resources.push(Resource { name, set, binding, kind });
+                },
+                _ => {}
+            }
+        }
+
+        reflection.inputs.sort_by_key(|input| input.location);
+        reflection.outputs.sort_by_key(|output| output.location);
+        reflection.uniform_blocks.sort_by_key(|block| (block.set, block.binding));
+        reflection.resources.sort_by_key(|resource| (resource.set, resource.binding));
+
+        Ok(reflection)
+    }
+
+    fn resolve(&self, id: u32) -> RenderResult<Type> {
+        let ty = match self.types.get(&id) {
+            Some(ty) => ty,
+            None => return Err(malformed("reference to an undeclared type"))
+        };
+
+        Ok(match *ty {
+            RawType::Scalar(base) => Type::Scalar(base),
+            RawType::Vector(component, count) => Type::Vector(self.base(component)?, count as u8),
+            RawType::Matrix(column, columns) => match self.resolve(column)? {
+                Type::Vector(base, rows) => Type::Matrix(base, columns as u8, rows),
+                _ => return Err(malformed("matrix column is not a vector"))
+            },
+            RawType::Array(element, length) => {
+                let length = match self.constants.get(&length) {
+                    Some(&length) => length,
+                    None => return Err(malformed("array length is not a constant"))
+                };
+
+                Type::Array(Box::new(self.resolve(element)?), length)
+            },
+            RawType::RuntimeArray(element) => Type::Array(Box::new(self.resolve(element)?), 0),
+            RawType::Struct(ref members) => {
+                let mut resolved = Vec::with_capacity(members.len());
+
+                for (index, &member) in members.iter().enumerate() {
+                    let key = (id, index as u32);
+
+                    resolved.push(Member {
+                        name: self.member_names.get(&key).cloned().unwrap_or_default(),
+                        offset: self.member_decorations.get(&key).and_then(|decorations| decorations.offset),
+                        ty: self.resolve(member)?,
+                    });
+                }
+
+                Type::Struct(resolved)
+            },
+            RawType::Image(dim, false) => Type::Image(dim),
+            RawType::Image(dim, true) => Type::StorageImage(dim),
+            RawType::Sampler => Type::Sampler,
+            RawType::SampledImage(image) => match self.types.get(&image) {
+                Some(&RawType::Image(dim, _)) => Type::SampledImage(dim),
+                _ => return Err(malformed("sampled image of a non-image type"))
+            },
+            RawType::Pointer(_, pointee) => self.resolve(pointee)?
+        })
+    }
+
+    fn base(&self, id: u32) -> RenderResult<BaseType> {
+        match self.types.get(&id) {
+            Some(&RawType::Scalar(base)) => Ok(base),
+            _ => Err(malformed("vector component is not a scalar"))
+        }
+    }
+}
+
+fn decorate(decorations: &mut Decorations, decoration: u32, value: Option<u32>) {
+    match decoration {
+        DECORATION_BUFFER_BLOCK => decorations.buffer_block = true,
+        DECORATION_BUILT_IN => decorations.built_in = true,
+        DECORATION_LOCATION => decorations.location = value,
+        DECORATION_BINDING => decorations.binding = value,
+        DECORATION_DESCRIPTOR_SET => decorations.set = value,
+        DECORATION_OFFSET => decorations.offset = value,
+        _ => {}
+    }
+}
+
+/// Vertex format of a stage input, four bytes per component.
+fn attribute_format(input: &Variable) -> RenderResult<(Format, ElemStride)> {
+    let (base, count) = match input.ty {
+        Type::Scalar(base) => (base, 1),
+        Type::Vector(base, count) => (base, count),
+        _ => return Err(RenderError::ShaderReflection(format!("vertex input {} is not a scalar or vector", input.name)))
+    };
+
+    let channel = match base {
+        BaseType::Float => ChannelType::Float,
+        BaseType::Int => ChannelType::Int,
+        BaseType::Uint => ChannelType::Uint,
+        _ => return Err(RenderError::ShaderReflection(format!("vertex input {} has no vertex format", input.name)))
+    };
+
+    let surface = match count {
+        1 => SurfaceType::R32,
+        2 => SurfaceType::R32_G32,
+        3 => SurfaceType::R32_G32_B32,
+        _ => SurfaceType::R32_G32_B32_A32
+    };
+
+    Ok((Format(surface, channel), 4 * count))
+}
+
+/// Pipeline layout derived from the reflected stages of a program.
+///
+/// Vertex inputs are packed into a single interleaved buffer in location order. Pixel outputs
+/// become color targets that replace what is there, and uniform blocks, textures and samplers
+/// are bound by name as the OpenGL backend reports them. `Init` borrows names from the layout,
+/// so the layout outlives the initializers it hands out.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct PipelineLayout {
+    attributes: Vec<(String, Element<Format>)>,
+    stride: ElemStride,
+    constants: Vec<String>,
+    color_targets: Vec<(String, ColorMask, Blend)>,
+    depth_target: bool,
+    samplers: Vec<String>,
+    textures: Vec<String>,
+}
+
+impl PipelineLayout {
+    pub fn new(stages: &[ShaderReflection]) -> RenderResult<PipelineLayout> {
+        let mut layout = PipelineLayout {
+            attributes: Vec::new(),
+            stride: 0,
+            constants: Vec::new(),
+            color_targets: Vec::new(),
+            depth_target: false,
+            samplers: Vec::new(),
+            textures: Vec::new(),
+        };
+
+        for stage in stages.iter() {
+            match stage.stage {
+                Stage::Vertex => {
+                    for input in stage.inputs.iter() {
+                        let (format, size) = attribute_format(input)?;
+                        layout.attributes.push((input.name.clone(), Element { format, offset: layout.stride as ElemOffset }));
+                        layout.stride += size;
+                    }
+                },
+                Stage::Pixel => {
+                    for output in stage.outputs.iter() {
+                        layout.color_targets.push((output.name.clone(), MASK_ALL, REPLACE));
+                    }
+                },
+                _ => {}
+            }
+
+            for block in stage.uniform_blocks.iter() {
+                insert(&mut layout.constants, &block.name);
+            }
+
+            for resource in stage.resources.iter() {
+                match resource.kind {
+                    ResourceKind::Texture => insert(&mut layout.textures, &resource.name),
+                    ResourceKind::Sampler => insert(&mut layout.samplers, &resource.name),
+                    ResourceKind::SampledTexture => {
+                        insert(&mut layout.textures, &resource.name);
+                        insert(&mut layout.samplers, &resource.name);
+                    },
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(layout)
+    }
+
+    /// Adds a depth target, tested and written with less-or-equal.
+    pub fn with_depth_target(mut self) -> PipelineLayout {
+        self.depth_target = true;
+        self
+    }
+
+    pub fn stride(&self) -> ElemStride {
+        self.stride
+    }
+
+    /// Color target state by output name.
+    pub fn color_target_mut(&mut self, name: &str) -> Option<&mut (String, ColorMask, Blend)> {
+        self.color_targets.iter_mut().find(|target| target.0 == name)
+    }
+
+    /// Named vertex elements in the form `Init` expects them.
+    pub fn attributes(&self) -> Vec<(&str, Element<Format>)> {
+        self.attributes.iter().map(|&(ref name, element)| (name.as_str(), element)).collect()
+    }
+
+    /// Initializer binding the layout, given the elements returned by `attributes`.
+    pub fn initializer<'a, B>(&'a self, attributes: &'a [(&'a str, Element<Format>)]) -> Init<'a, B> where B: backend::Backend {
+        Init {
+            constants: borrow(&self.constants),
+            globals: Vec::new(),
+            color_targets: self.color_targets.iter().map(|&(ref name, mask, blend)| (name.as_str(), mask, blend)).collect(),
+            raw_color_targets: Vec::new(),
+            depth_target: if self.depth_target {
+                Some((LESS_EQUAL_WRITE, Stencil::new(Comparison::Always, 0, (StencilOp::Keep, StencilOp::Keep, StencilOp::Keep))))
+            } else {
+                None
+            },
+            samplers: borrow(&self.samplers),
+            textures: borrow(&self.textures),
+            vertices: if attributes.is_empty() { Vec::new() } else { vec![(attributes, self.stride, 0)] },
+        }
+    }
+}
+
+fn borrow(names: &[String]) -> Vec<&str> {
+    names.iter().map(|name| name.as_str()).collect()
+}
+
+fn insert(names: &mut Vec<String>, name: &str) {
+    if !names.iter().any(|existing| existing == name) {
+        names.push(name.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use render::backend::OpenGL;
+
+    fn reflect(file: &str) -> ShaderReflection {
+        use std::fs::File;
+        use std::io::Read;
+
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/resources/shaders/spir").join(file);
+        let mut code = Vec::new();
+        File::open(path).and_then(|mut file| file.read_to_end(&mut code)).unwrap();
+
+        ShaderReflection::parse(&code).unwrap()
+    }
+
+    #[test]
+    fn reflecting_stages() {
+        let vertex = reflect("vert.spv");
+        assert_eq!(vertex.stage, Stage::Vertex);
+        assert_eq!(vertex.entry_point, "main");
+        assert!(vertex.inputs.is_empty() && vertex.local_size.is_none());
+        assert_eq!(vertex.outputs, vec![Variable { name: "fragColor".to_string(), location: 0, ty: Type::Vector(BaseType::Float, 3) }]);
+
+        let pixel = reflect("frag.spv");
+        assert_eq!(pixel.stage, Stage::Pixel);
+        assert_eq!(pixel.input("fragColor").map(|input| input.ty.clone()), Some(Type::Vector(BaseType::Float, 3)));
+        assert_eq!(pixel.output("outColor").map(|output| output.location), Some(0));
+        assert!(pixel.uniform_blocks.is_empty() && pixel.resources.is_empty());
+    }
+
+    #[test]
+    fn deriving_initializers() {
+        let layout = PipelineLayout::new(&[reflect("vert.spv"), reflect("frag.spv")]).unwrap();
+        let attributes = layout.attributes();
+        let init: Init<OpenGL> = layout.initializer(&attributes);
+
+        assert_eq!(init.color_targets, vec![("outColor", MASK_ALL, REPLACE)]);
+        assert!(init.vertices.is_empty() && init.constants.is_empty() && init.depth_target.is_none());
+    }
+
+    #[test]
+    fn rejecting_malformed_modules() {
+        assert!(ShaderReflection::parse(&[0, 1, 2, 3]).is_err());
+        assert!(ShaderReflection::parse(&[0u8; 20]).is_err());
+
+        let input = Variable { name: "a_Weights".to_string(), location: 0, ty: Type::Vector(BaseType::Float, 4) };
+        assert_eq!(attribute_format(&input).unwrap(), (Format(SurfaceType::R32_G32_B32_A32, ChannelType::Float), 16));
+    }
+}
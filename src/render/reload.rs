@@ -0,0 +1,135 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use core::platform::Platform;
+use render::backend;
+use render::command::EffectId;
+use render::effect::Effect;
+use render::error::RenderError;
+
+struct Watched {
+    effect: EffectId,
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+/// Polls the shader files of effects for changes.
+///
+/// Modification times are compared on every poll, so no background thread or platform
+/// notification API is involved. A file that cannot be read at poll time, as happens briefly
+/// while some editors save, is skipped until it reappears.
+pub struct ShaderWatcher {
+    watched: Vec<Watched>,
+}
+
+impl ShaderWatcher {
+    pub fn new() -> ShaderWatcher {
+        ShaderWatcher {
+            watched: Vec::new(),
+        }
+    }
+
+    /// Starts watching the files an effect was built from, and the files it depends on.
+    pub fn watch<B>(&mut self, effect: EffectId, source: &Effect<B>) where B: backend::Backend {
+        let description = source.description();
+
+        for path in description.files().iter().chain(description.dependencies()) {
+            self.watch_file(effect, path);
+        }
+    }
+
+    /// Starts watching a file on behalf of an effect.
+    pub fn watch_file(&mut self, effect: EffectId, path: &Path) {
+        self.watched.push(Watched {
+            effect,
+            path: path.to_path_buf(),
+            modified: modified(path),
+        });
+    }
+
+    pub fn unwatch(&mut self, effect: EffectId) {
+        self.watched.retain(|watched| watched.effect != effect);
+    }
+
+    pub fn is_watching(&self, effect: EffectId) -> bool {
+        self.watched.iter().any(|watched| watched.effect == effect)
+    }
+
+    /// Effects with at least one file modified since the last poll, in ascending order.
+    pub fn poll(&mut self) -> Vec<EffectId> {
+        let mut changed = Vec::new();
+
+        for watched in self.watched.iter_mut() {
+            let current = match modified(&watched.path) {
+                Some(time) => time,
+                None => continue
+            };
+
+            if watched.modified != Some(current) {
+                watched.modified = Some(current);
+                changed.push(watched.effect);
+            }
+        }
+
+        changed.sort();
+        changed.dedup();
+        changed
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Reloads every effect whose shader files changed since the last poll.
+///
+/// Effects that fail to reload keep their previous program; their errors are returned so they
+/// can be reported.
+pub fn reload_changed<B>(watcher: &mut ShaderWatcher, platform: &mut Platform<B>, effects: &mut [Effect<B>]) -> Vec<(EffectId, RenderError)>
+    where B: backend::Backend {
+
+    let mut errors = Vec::new();
+
+    for effect in watcher.poll() {
+        if let Some(target) = effects.get_mut(effect as usize) {
+            if let Err(e) = target.reload(platform) {
+                errors.push((effect, e));
+            }
+        }
+    }
+
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn detecting_changes() {
+        // A directory of its own per run, so concurrent test runs do not share the file.
+        let stamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let dir = env::temp_dir().join(format!("chopper-watch-test-{}-{}", stamp.as_secs(), stamp.subsec_nanos()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("shader.frag");
+
+        let mut watcher = ShaderWatcher::new();
+        watcher.watch_file(7, &path);
+
+        // Missing files are skipped rather than reported.
+        assert!(watcher.poll().is_empty());
+
+        File::create(&path).and_then(|mut file| file.write_all(b"void main() {}")).unwrap();
+        assert_eq!(watcher.poll(), vec![7]);
+        assert!(watcher.poll().is_empty());
+
+        watcher.unwatch(7);
+        assert!(!watcher.is_watching(7));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -2,10 +2,51 @@ use gfx;
 use system::entity::Entity;
 use std::collections::BTreeMap;
 use nalgebra::Vector3;
+use core::platform::Platform;
+use render::backend;
+use render::command::StageBuckets;
+use render::error::RenderResult;
+use render::target::TargetRegistry;
 
 pub trait RenderManager {
 }
 
+/// State shared by every pass of a frame: named render targets and per-stage draw buckets.
+pub struct Renderer<B: backend::Backend> {
+    targets: TargetRegistry<B>,
+    buckets: StageBuckets<B>,
+}
+
+impl<B> Renderer<B> where B: backend::Backend {
+    pub fn new(width: u16, height: u16) -> Renderer<B> {
+        Renderer {
+            targets: TargetRegistry::new(width, height),
+            buckets: StageBuckets::new(),
+        }
+    }
+
+    pub fn targets(&self) -> &TargetRegistry<B> {
+        &self.targets
+    }
+
+    pub fn targets_mut(&mut self) -> &mut TargetRegistry<B> {
+        &mut self.targets
+    }
+
+    pub fn buckets(&self) -> &StageBuckets<B> {
+        &self.buckets
+    }
+
+    pub fn buckets_mut(&mut self) -> &mut StageBuckets<B> {
+        &mut self.buckets
+    }
+
+    /// Follows a swapchain resize, recreating relatively sized targets.
+    pub fn resize(&mut self, platform: &mut Platform<B>, width: u16, height: u16) -> RenderResult<()> {
+        self.targets.resize(platform, width, height)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
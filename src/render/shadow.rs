@@ -0,0 +1,332 @@
+use gfx;
+use nalgebra::{Matrix4, Vector3};
+use core::platform::Platform;
+use system::bounds::{BoundsManager, Frustum};
+use system::camera::{projection_matrix, CameraManager, Projection};
+use system::entity::Entity;
+use system::scene;
+use render::backend::{self, ClipSpace};
+use render::culling::{self, VisibleSet};
+use render::error::{RenderError, RenderResult};
+
+/// Parameters of a cascaded shadow setup.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CascadeConfig {
+    /// Number of cascades the camera frustum is divided into.
+    pub cascades: usize,
+    /// Width and height of each cascade's shadow map in texels.
+    pub resolution: u16,
+    /// Blend between uniform (0) and logarithmic (1) split placement.
+    pub lambda: f32,
+    /// Distance the light's near plane is pulled back to catch casters outside the view.
+    pub caster_margin: f32,
+}
+
+impl Default for CascadeConfig {
+    fn default() -> Self {
+        CascadeConfig {
+            cascades: 4,
+            resolution: 2048,
+            lambda: 0.75f32,
+            caster_margin: 50.0f32,
+        }
+    }
+}
+
+/// Light-space projection covering one slice of the camera frustum.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cascade {
+    /// View depth at which this cascade starts.
+    pub near: f32,
+    /// View depth at which this cascade ends and the next begins.
+    pub far: f32,
+    pub view: Matrix4<f32>,
+    pub projection: Matrix4<f32>,
+    pub view_projection: Matrix4<f32>,
+    pub depth_range: (f32, f32),
+}
+
+impl Cascade {
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_matrix(&self.view_projection, self.depth_range)
+    }
+}
+
+/// Computes cascade split depths using the practical split scheme.
+///
+/// Returns `count + 1` depths from `near` to `far`, each a blend of the uniform and logarithmic
+/// split at that index weighted by `lambda`. Panics unless there is at least one cascade and
+/// `0 < near < far`, which the logarithmic split needs.
+pub fn split_distances(near: f32, far: f32, count: usize, lambda: f32) -> Vec<f32> {
+    assert!(count > 0, "at least one cascade is needed");
+    assert!(near > 0.0f32 && far > near, "clip planes {} and {} cannot be split", near, far);
+
+    (0..count + 1).map(|i| {
+        let fraction = i as f32 / count as f32;
+        let logarithmic = near * (far / near).powf(fraction);
+        let uniform = near + (far - near) * fraction;
+
+        lambda * logarithmic + (1.0f32 - lambda) * uniform
+    }).collect()
+}
+
+/// Fits an orthographic light projection around a slice of a camera's frustum.
+///
+/// The slice is enclosed in a bounding sphere so the projection size does not change as the
+/// camera rotates, and the projection is snapped to whole shadow map texels so the cascade does
+/// not shimmer as the camera moves.
+pub fn fit_cascade(cameras: &CameraManager, camera: Entity, near: f32, far: f32, direction: Vector3<f32>, resolution: u16, caster_margin: f32, clip: ClipSpace) -> Cascade {
+    let corners = slice_corners(cameras, camera, near, far);
+
+    let center = corners.iter().fold(Vector3::from_element(0.0f32), |acc, corner| acc + *corner) / 8.0f32;
+    let radius = corners.iter().map(|corner| (*corner - center).norm()).fold(0.0f32, f32::max);
+
+    // Quantize the radius so small numerical changes do not resize the cascade.
+    let radius = (radius * 16.0f32).ceil() / 16.0f32;
+
+    let direction = direction.normalize();
+    let z_axis = -direction;
+    let up = if z_axis[1].abs() > 0.99f32 {
+        Vector3::new(1.0f32, 0.0f32, 0.0f32)
+    } else {
+        Vector3::new(0.0f32, 1.0f32, 0.0f32)
+    };
+    let x_axis = up.cross(&z_axis).normalize();
+    let y_axis = z_axis.cross(&x_axis);
+    let eye = center - direction * (radius + caster_margin);
+
+    let mut world = Matrix4::identity();
+    for c in 0..3 {
+        world[(0, c)] = x_axis[c];
+        world[(1, c)] = y_axis[c];
+        world[(2, c)] = z_axis[c];
+        world[(3, c)] = eye[c];
+    }
+
+    let view = world.try_inverse().unwrap_or_else(|| Matrix4::identity());
+
+    let depth_range = if clip.zero_to_one_depth { (0.0f32, 1.0f32) } else { (-1.0f32, 1.0f32) };
+    let mut projection = projection_matrix(Projection::Orthographic { height: 2.0f32 * radius }, 1.0f32,
+                                           0.0f32, 2.0f32 * radius + caster_margin, depth_range, clip.y_down);
+
+    // Snap the projected world origin onto the texel grid.
+    let texels = resolution as f32 * 0.5f32;
+    let origin = scene::transform_point(&(view * projection), &Vector3::from_element(0.0f32));
+    for a in 0..2 {
+        let scaled = origin[a] * texels;
+        projection[(3, a)] += (scaled.round() - scaled) / texels;
+    }
+
+    Cascade {
+        near,
+        far,
+        view,
+        projection,
+        view_projection: view * projection,
+        depth_range,
+    }
+}
+
+/// World-space corners of the slice of a camera frustum between two view depths.
+fn slice_corners(cameras: &CameraManager, camera: Entity, near: f32, far: f32) -> [Vector3<f32>; 8] {
+    let depth_range = cameras.depth_range(camera);
+    let inverse_projection = cameras.projection(camera).try_inverse().unwrap_or_else(|| Matrix4::identity());
+    let inverse_view = cameras.view(camera).try_inverse().unwrap_or_else(|| Matrix4::identity());
+
+    let unproject = |x: f32, y: f32, z: f32| {
+        let clip = [x, y, z, 1.0f32];
+        let mut out = [0.0f32; 4];
+
+        for c in 0..4 {
+            for r in 0..4 {
+                out[c] += clip[r] * inverse_projection[(r, c)];
+            }
+        }
+
+        Vector3::new(out[0] / out[3], out[1] / out[3], out[2] / out[3])
+    };
+
+    let mut corners = [Vector3::from_element(0.0f32); 8];
+    let mut index = 0;
+
+    for &(x, y) in [(-1.0f32, -1.0f32), (1.0f32, -1.0f32), (-1.0f32, 1.0f32), (1.0f32, 1.0f32)].iter() {
+        let front = unproject(x, y, depth_range.0);
+        let back = unproject(x, y, depth_range.1);
+
+        for &depth in [near, far].iter() {
+            let t = (depth + front[2]) / (front[2] - back[2]);
+            corners[index] = scene::transform_point(&inverse_view, &(front + (back - front) * t));
+            index += 1;
+        }
+    }
+
+    corners
+}
+
+/// Depth targets for each cascade, allocated through the platform factory.
+pub struct ShadowMaps<B: backend::Backend> where B::DepthFormat: gfx::format::DepthFormat + gfx::format::TextureFormat {
+    pub resolution: u16,
+    pub depth_targets: Vec<gfx::handle::DepthStencilView<B::Resources, B::DepthFormat>>,
+    pub resources: Vec<gfx::handle::ShaderResourceView<B::Resources, <B::DepthFormat as gfx::format::Formatted>::View>>,
+}
+
+impl<B> ShadowMaps<B> where B: backend::Backend, B::DepthFormat: gfx::format::DepthFormat + gfx::format::TextureFormat {
+    pub fn new(platform: &mut Platform<B>, count: usize, resolution: u16) -> RenderResult<ShadowMaps<B>> {
+        use gfx::Factory;
+
+        let mut depth_targets = Vec::with_capacity(count);
+        let mut resources = Vec::with_capacity(count);
+
+        for _ in 0..count {
+            let (_, resource, target) = platform.create_depth_stencil::<B::DepthFormat>(resolution, resolution)
+                .map_err(RenderError::TargetCreation)?;
+
+            depth_targets.push(target);
+            resources.push(resource);
+        }
+
+        Ok(ShadowMaps {
+            resolution,
+            depth_targets,
+            resources,
+        })
+    }
+}
+
+/// Cascaded shadow maps for a single directional light.
+pub struct CascadedShadows<B: backend::Backend> where B::DepthFormat: gfx::format::DepthFormat + gfx::format::TextureFormat {
+    config: CascadeConfig,
+    cascades: Vec<Cascade>,
+    maps: ShadowMaps<B>,
+    casters: VisibleSet,
+}
+
+impl<B> CascadedShadows<B> where B: backend::Backend, B::DepthFormat: gfx::format::DepthFormat + gfx::format::TextureFormat {
+    pub fn new(platform: &mut Platform<B>, config: CascadeConfig) -> RenderResult<CascadedShadows<B>> {
+        let maps = ShadowMaps::new(platform, config.cascades, config.resolution)?;
+
+        Ok(CascadedShadows {
+            config,
+            cascades: Vec::with_capacity(config.cascades),
+            maps,
+            casters: VisibleSet::new(),
+        })
+    }
+
+    pub fn config(&self) -> CascadeConfig {
+        self.config
+    }
+
+    /// Cascades fitted by the last update, nearest first.
+    pub fn cascades(&self) -> &[Cascade] {
+        &self.cascades
+    }
+
+    pub fn maps(&self) -> &ShadowMaps<B> {
+        &self.maps
+    }
+
+    /// Refits every cascade to the camera's current frustum and the light's direction.
+    pub fn update(&mut self, cameras: &CameraManager, camera: Entity, direction: Vector3<f32>) {
+        let (near, far) = cameras.clip_planes(camera);
+        let splits = split_distances(near, far, self.config.cascades, self.config.lambda);
+
+        self.cascades.clear();
+
+        for window in splits.windows(2) {
+            self.cascades.push(fit_cascade(cameras, camera, window[0], window[1], direction,
+                                           self.config.resolution, self.config.caster_margin, B::clip_space()));
+        }
+    }
+
+    /// Renders shadow casters into each cascade.
+    ///
+    /// Every cascade's depth target is cleared and the casters overlapping its light frustum are
+    /// culled, then `draw` is invoked with the cascade index, the cascade, its casters and the
+    /// depth target to render into.
+    pub fn render<C, F>(&mut self, encoder: &mut gfx::Encoder<B::Resources, C>, bounds: &BoundsManager, mut draw: F)
+        where C: gfx::CommandBuffer<B::Resources>,
+              F: FnMut(&mut gfx::Encoder<B::Resources, C>, usize, &Cascade, &VisibleSet, &gfx::handle::DepthStencilView<B::Resources, B::DepthFormat>) {
+
+        for (index, cascade) in self.cascades.iter().enumerate() {
+            let target = &self.maps.depth_targets[index];
+            encoder.clear_depth(target, 1.0f32);
+
+            self.casters.clear();
+            culling::cull(&cascade.frustum(), &cascade.view, bounds, &mut self.casters);
+
+            draw(encoder, index, cascade, &self.casters, target);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+    use system::camera::Viewport;
+    use system::scene::SceneManager;
+
+    const CLIP: ClipSpace = ClipSpace { zero_to_one_depth: true, y_down: false };
+
+    fn approx(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1.0e-3
+    }
+
+    #[test]
+    fn splitting_depths() {
+        let uniform = split_distances(1.0f32, 101.0f32, 4, 0.0f32);
+        assert_eq!(uniform, vec![1.0f32, 26.0f32, 51.0f32, 76.0f32, 101.0f32]);
+
+        let logarithmic = split_distances(1.0f32, 10000.0f32, 4, 1.0f32);
+        assert!(approx(logarithmic[1], 10.0f32) && approx(logarithmic[2], 100.0f32));
+
+        assert_eq!(split_distances(0.5f32, 50.0f32, 1, 0.5f32), vec![0.5f32, 50.0f32]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn rejecting_empty_splits() {
+        split_distances(1.0f32, 100.0f32, 0, 0.5f32);
+    }
+
+    #[test]
+    #[should_panic]
+    fn rejecting_nonpositive_near_planes() {
+        split_distances(0.0f32, 100.0f32, 4, 0.5f32);
+    }
+
+    #[test]
+    fn enclosing_slices() {
+        let scene = SceneManager::new();
+        let mut cameras = CameraManager::new();
+
+        cameras.create_camera(0, Projection::Perspective { fov_y: PI / 2.0f32 }, Viewport::new(0.0f32, 0.0f32, 100.0f32, 100.0f32));
+        cameras.set_clip_planes(0, 1.0f32, 100.0f32);
+        cameras.update(&scene, CLIP);
+
+        let cascade = fit_cascade(&cameras, 0, 1.0f32, 10.0f32, Vector3::new(0.0f32, -1.0f32, 0.0f32), 1024, 10.0f32, CLIP);
+        let frustum = cascade.frustum();
+
+        for corner in slice_corners(&cameras, 0, 1.0f32, 10.0f32).iter() {
+            assert!(frustum.planes.iter().all(|plane| plane.signed_distance(corner) >= -1.0e-3));
+        }
+    }
+
+    #[test]
+    fn snapping_to_texels() {
+        let scene = SceneManager::new();
+        let mut cameras = CameraManager::new();
+
+        cameras.create_camera(0, Projection::Perspective { fov_y: PI / 2.0f32 }, Viewport::new(0.0f32, 0.0f32, 100.0f32, 100.0f32));
+        cameras.update(&scene, CLIP);
+
+        let cascade = fit_cascade(&cameras, 0, 1.0f32, 10.0f32, Vector3::new(0.3f32, -1.0f32, 0.2f32), 1024, 10.0f32, CLIP);
+        let origin = scene::transform_point(&cascade.view_projection, &Vector3::from_element(0.0f32));
+
+        for a in 0..2 {
+            let texel = origin[a] * 512.0f32;
+            assert!(approx(texel, texel.round()));
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::BTreeMap;
+use gfx;
+use gfx::format::Format;
+use gfx::memory::Typed;
+use core::platform::Platform;
+use render::backend;
+use render::error::{RenderError, RenderResult};
+use render::gbuffer::{Attachment, ColorAttachment, DepthAttachment};
+use render::graph::TargetDesc;
+use render::pipeline::Data;
+
+/// Size of a named target.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TargetSize {
+    /// Fraction of the swapchain size, e.g. 0.5 for half resolution. Follows swapchain resizes.
+    Relative(f32),
+    /// Fixed size in pixels.
+    Absolute(u16, u16),
+}
+
+impl TargetSize {
+    /// Size in pixels for a swapchain size, never smaller than one pixel.
+    pub fn resolve(&self, swapchain: (u16, u16)) -> (u16, u16) {
+        match *self {
+            TargetSize::Relative(scale) => {
+                let scaled = |extent: u16| ((extent as f32 * scale).round() as u16).max(1);
+                (scaled(swapchain.0), scaled(swapchain.1))
+            },
+            TargetSize::Absolute(width, height) => (width.max(1), height.max(1))
+        }
+    }
+}
+
+/// Creation options of a named target.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TargetOptions {
+    pub format: Format,
+    pub size: TargetSize,
+    /// Samples per pixel; one disables multisampling. Multisampled targets are never resolved,
+    /// so they can be rendered to but not sampled.
+    pub samples: u8,
+}
+
+impl TargetOptions {
+    /// Single-sampled options at the swapchain size.
+    pub fn new(format: Format) -> TargetOptions {
+        TargetOptions {
+            format,
+            size: TargetSize::Relative(1.0f32),
+            samples: 1,
+        }
+    }
+
+    pub fn with_size(mut self, size: TargetSize) -> TargetOptions {
+        self.size = size;
+        self
+    }
+
+    pub fn with_samples(mut self, samples: u8) -> TargetOptions {
+        self.samples = samples;
+        self
+    }
+
+    /// Description of the target at a swapchain size.
+    pub fn desc(&self, swapchain: (u16, u16)) -> TargetDesc {
+        let (width, height) = self.size.resolve(swapchain);
+        TargetDesc::new(self.format, width, height)
+    }
+}
+
+struct Entry<R: gfx::Resources> {
+    options: TargetOptions,
+    size: (u16, u16),
+    attachment: Attachment<R>,
+}
+
+/// Render targets owned by name.
+///
+/// Targets sized relative to the swapchain are recreated when it resizes. Views bound into
+/// pipeline data before a resize keep the old textures alive, so effects should bind targets
+/// again after one.
+pub struct TargetRegistry<B: backend::Backend> {
+    swapchain: (u16, u16),
+    targets: BTreeMap<String, Entry<B::Resources>>,
+}
+
+impl<B> TargetRegistry<B> where B: backend::Backend {
+    pub fn new(width: u16, height: u16) -> TargetRegistry<B> {
+        TargetRegistry {
+            swapchain: (width, height),
+            targets: BTreeMap::new(),
+        }
+    }
+
+    pub fn swapchain_size(&self) -> (u16, u16) {
+        self.swapchain
+    }
+
+    pub fn create_color(&mut self, platform: &mut Platform<B>, name: &str, options: TargetOptions) -> RenderResult<()> {
+        self.create(platform, name, options, false)
+    }
+
+    pub fn create_depth(&mut self, platform: &mut Platform<B>, name: &str, options: TargetOptions) -> RenderResult<()> {
+        self.create(platform, name, options, true)
+    }
+
+    pub fn destroy(&mut self, name: &str) -> RenderResult<()> {
+        match self.targets.remove(name) {
+            Some(_) => Ok(()),
+            None => Err(RenderError::NoSuchTarget(name.to_string()))
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.targets.contains_key(name)
+    }
+
+    pub fn options(&self, name: &str) -> RenderResult<TargetOptions> {
+        self.entry(name).map(|entry| entry.options)
+    }
+
+    /// Current size of a target in pixels.
+    pub fn size(&self, name: &str) -> RenderResult<(u16, u16)> {
+        self.entry(name).map(|entry| entry.size)
+    }
+
+    pub fn color(&self, name: &str) -> RenderResult<&ColorAttachment<B::Resources>> {
+        match self.entry(name)?.attachment {
+            Attachment::Color(ref attachment) => Ok(attachment),
+            Attachment::Depth(_) => Err(RenderError::NoSuchTarget(name.to_string()))
+        }
+    }
+
+    pub fn depth(&self, name: &str) -> RenderResult<&DepthAttachment<B::Resources>> {
+        match self.entry(name)?.attachment {
+            Attachment::Depth(ref attachment) => Ok(attachment),
+            Attachment::Color(_) => Err(RenderError::NoSuchTarget(name.to_string()))
+        }
+    }
+
+    /// Recreates every relatively sized target whose size changes with the swapchain.
+    pub fn resize(&mut self, platform: &mut Platform<B>, width: u16, height: u16) -> RenderResult<()> {
+        self.swapchain = (width, height);
+
+        for entry in self.targets.values_mut() {
+            let size = entry.options.size.resolve(self.swapchain);
+
+            if size != entry.size {
+                let depth = match entry.attachment {
+                    Attachment::Depth(_) => true,
+                    Attachment::Color(_) => false
+                };
+
+                entry.attachment = allocate(platform, &entry.options, size, depth)?;
+                entry.size = size;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Points pipeline data at named color targets, given as shader output and target name
+    /// pairs, and an optional depth target.
+    ///
+    /// Color targets are bound raw, so the effect must name them as raw color targets in the
+    /// targets' formats. Depth is bound through the backend's depth format, which the depth
+    /// target has to use; stencil reference values are left at zero.
+    pub fn bind(&self, data: &mut Data<B>, colors: &[(&str, &str)], depth: Option<&str>) -> RenderResult<()> {
+        let mut color_targets = BTreeMap::new();
+
+        for &(output, name) in colors.iter() {
+            color_targets.insert(output.to_string(), self.color(name)?.target.clone());
+        }
+
+        let depth_target = match depth {
+            Some(name) => {
+                let format = <B::DepthFormat as gfx::format::Formatted>::get_format();
+                if self.options(name)?.format != format {
+                    return Err(RenderError::Unsupported(format!("depth target {} is not in the backend's depth format {:?}", name, format)));
+                }
+
+                Some((Typed::new(self.depth(name)?.target.clone()), (0, 0)))
+            },
+            None => None
+        };
+
+        data.raw_color_targets = color_targets;
+        data.depth_target = depth_target;
+
+        Ok(())
+    }
+
+    fn create(&mut self, platform: &mut Platform<B>, name: &str, options: TargetOptions, depth: bool) -> RenderResult<()> {
+        if self.targets.contains_key(name) {
+            return Err(RenderError::DuplicateTarget(name.to_string()));
+        }
+
+        let size = options.size.resolve(self.swapchain);
+        let attachment = allocate(platform, &options, size, depth)?;
+
+        self.targets.insert(name.to_string(), Entry { options, size, attachment });
+        Ok(())
+    }
+
+    fn entry(&self, name: &str) -> RenderResult<&Entry<B::Resources>> {
+        match self.targets.get(name) {
+            Some(entry) => Ok(entry),
+            None => Err(RenderError::NoSuchTarget(name.to_string()))
+        }
+    }
+}
+
+fn allocate<B>(platform: &mut Platform<B>, options: &TargetOptions, size: (u16, u16), depth: bool) -> RenderResult<Attachment<B::Resources>>
+    where B: backend::Backend {
+
+    let (width, height) = size;
+
+    Ok(if depth {
+        Attachment::Depth(DepthAttachment::with_samples(platform, options.format, width, height, options.samples)?)
+    } else {
+        Attachment::Color(ColorAttachment::with_samples(platform, options.format, width, height, options.samples)?)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolving_sizes() {
+        assert_eq!(TargetSize::Relative(1.0f32).resolve((1280, 720)), (1280, 720));
+        assert_eq!(TargetSize::Relative(0.5f32).resolve((1280, 719)), (640, 360));
+        assert_eq!(TargetSize::Relative(0.001f32).resolve((100, 100)), (1, 1));
+        assert_eq!(TargetSize::Absolute(256, 128).resolve((1280, 720)), (256, 128));
+    }
+}
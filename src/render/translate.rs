@@ -0,0 +1,313 @@
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use core::platform::Platform;
+use render::backend::{self, ShaderTarget};
+use render::effect::{Effect, EffectBuilder};
+use render::error::{RenderError, RenderResult};
+use render::reflect::{PipelineLayout, ShaderReflection, Stage};
+
+/// Bumped whenever the translation steps change, so stale cache entries are not reused.
+const CACHE_VERSION: u32 = 1;
+
+/// 64-bit FNV-1a hash, stable across runs and platforms unlike the standard hasher.
+fn fnv(bytes: &[u8], mut hash: u64) -> u64 {
+    for &byte in bytes.iter() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+
+    hash
+}
+
+/// File extension `glslangValidator` infers a stage from.
+fn extension(stage: Stage) -> &'static str {
+    match stage {
+        Stage::Vertex => "vert",
+        Stage::Hull => "tesc",
+        Stage::Domain => "tese",
+        Stage::Geometry => "geom",
+        Stage::Pixel => "frag",
+        Stage::Compute => "comp"
+    }
+}
+
+/// Stage of a shader file, from its extension.
+pub fn stage_of(path: &Path) -> Option<Stage> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("vert") => Some(Stage::Vertex),
+        Some("tesc") => Some(Stage::Hull),
+        Some("tese") => Some(Stage::Domain),
+        Some("geom") => Some(Stage::Geometry),
+        Some("frag") => Some(Stage::Pixel),
+        Some("comp") => Some(Stage::Compute),
+        _ => None
+    }
+}
+
+/// Cache key of a source translated for a stage and target.
+pub fn cache_key(source: &[u8], stage: Stage, target: ShaderTarget) -> u64 {
+    let settings = format!("{}:{}:{:?}", CACHE_VERSION, extension(stage), target);
+    fnv(source, fnv(settings.as_bytes(), 0xcbf2_9ce4_8422_2325))
+}
+
+/// Translates GLSL 450 shaders into whatever a backend consumes.
+///
+/// Sources are compiled to SPIR-V with `glslangValidator`, which Vulkan takes as is, and
+/// cross-compiled from there with `spirv-cross` to GLSL for OpenGL and MSL for Metal. Results
+/// are cached on disk under a hash of the source and target, so each shader is translated once
+/// until it changes. Both tools are looked up on the `PATH` unless configured otherwise.
+pub struct ShaderTranslator {
+    cache: PathBuf,
+    glslang: PathBuf,
+    spirv_cross: PathBuf,
+}
+
+impl ShaderTranslator {
+    pub fn new<P>(cache: P) -> ShaderTranslator where P: AsRef<Path> {
+        ShaderTranslator {
+            cache: cache.as_ref().to_path_buf(),
+            glslang: PathBuf::from("glslangValidator"),
+            spirv_cross: PathBuf::from("spirv-cross"),
+        }
+    }
+
+    pub fn with_tools<P>(mut self, glslang: P, spirv_cross: P) -> ShaderTranslator where P: AsRef<Path> {
+        self.glslang = glslang.as_ref().to_path_buf();
+        self.spirv_cross = spirv_cross.as_ref().to_path_buf();
+        self
+    }
+
+    pub fn cache_dir(&self) -> &Path {
+        &self.cache
+    }
+
+    /// Path a translation is cached at.
+    pub fn cache_path(&self, source: &[u8], stage: Stage, target: ShaderTarget) -> PathBuf {
+        let extension = match target {
+            ShaderTarget::Glsl { .. } => "glsl",
+            ShaderTarget::Msl => "metal",
+            ShaderTarget::SpirV => "spv"
+        };
+
+        self.cache.join(format!("{:016x}.{}", cache_key(source, stage, target), extension))
+    }
+
+    /// Translates a shader file, whose stage is taken from its extension.
+    pub fn translate_file(&self, path: &Path, target: ShaderTarget) -> RenderResult<Vec<u8>> {
+        let stage = match stage_of(path) {
+            Some(stage) => stage,
+            None => return Err(RenderError::ShaderTranslation(format!("unknown shader stage of {}", path.display())))
+        };
+
+        self.translate(&read(path)?, stage, target)
+    }
+
+    /// Translates a shader source, reusing the cached result if there is one.
+    ///
+    /// The intermediate SPIR-V is cached alongside, so translating a source for several
+    /// targets, or reflecting it, compiles it once.
+    pub fn translate(&self, source: &[u8], stage: Stage, target: ShaderTarget) -> RenderResult<Vec<u8>> {
+        let cached = self.cache_path(source, stage, target);
+        if cached.is_file() {
+            return read(&cached);
+        }
+
+        let binary = self.compile(source, stage)?;
+        if let ShaderTarget::SpirV = target {
+            return read(&binary);
+        }
+
+        let output = cached.with_extension("tmp");
+        let mut cross = Command::new(&self.spirv_cross);
+        cross.arg(&binary).arg("--output").arg(&output);
+
+        match target {
+            ShaderTarget::Glsl { version, es } => {
+                cross.arg("--version").arg(version.to_string());
+                if es {
+                    cross.arg("--es");
+                }
+            },
+            _ => {
+                cross.arg("--msl");
+            }
+        }
+
+        run(cross, &self.spirv_cross)?;
+
+        // Renamed into place only once complete, so a failed run never leaves a partial entry.
+        fs::rename(&output, &cached).map_err(|e| RenderError::ShaderLoad(cached.display().to_string(), e))?;
+        read(&cached)
+    }
+
+    /// Compiles a source to SPIR-V with `glslangValidator` unless it is cached, returning the
+    /// path of the cached binary.
+    fn compile(&self, source: &[u8], stage: Stage) -> RenderResult<PathBuf> {
+        let cached = self.cache_path(source, stage, ShaderTarget::SpirV);
+        if cached.is_file() {
+            return Ok(cached);
+        }
+
+        fs::create_dir_all(&self.cache).map_err(|e| RenderError::ShaderLoad(self.cache.display().to_string(), e))?;
+
+        let input = cached.with_extension(extension(stage));
+        let output = cached.with_extension("tmp");
+
+        write(&input, source)?;
+
+        let mut compile = Command::new(&self.glslang);
+        compile.arg("-V").arg("-o").arg(&output).arg(&input);
+        let compiled = run(compile, &self.glslang);
+        let _ = fs::remove_file(&input);
+        compiled?;
+
+        fs::rename(&output, &cached).map_err(|e| RenderError::ShaderLoad(cached.display().to_string(), e))?;
+        Ok(cached)
+    }
+
+    /// Describes an effect from GLSL 450 vertex and pixel shader files translated for the
+    /// backend's shader model.
+    ///
+    /// The initializer is reflected from the shaders' SPIR-V whatever the target. The shader
+    /// files are recorded as the effect's dependencies, so a `ShaderWatcher` tracks them and
+    /// `reload` translates them again.
+    pub fn effect<B>(&self, model: &B::ShaderModel, vertex: &Path, pixel: &Path) -> RenderResult<EffectBuilder<B>> where B: backend::Backend {
+        let (vertex_code, pixel_code, layout) = self.program::<B>(model, vertex, pixel)?;
+
+        Ok(EffectBuilder::from_code(vertex_code, pixel_code)
+            .with_layout(layout)
+            .with_dependencies(vec![vertex.to_path_buf(), pixel.to_path_buf()]))
+    }
+
+    /// Translates the shader files of an effect described by `effect` again and swaps in the
+    /// rebuilt pipeline state.
+    ///
+    /// The reflected layout replaces the effect's, so blend and mask overrides set on it are
+    /// lost. On failure the error is returned and the effect keeps its previous program.
+    pub fn reload<B>(&self, platform: &mut Platform<B>, model: &B::ShaderModel, effect: &mut Effect<B>) -> RenderResult<()> where B: backend::Backend {
+        let description = {
+            let files = effect.description().dependencies();
+            if files.len() != 2 {
+                return Err(RenderError::ShaderTranslation("effect was not described by a translator".to_string()));
+            }
+
+            let (vertex_code, pixel_code, layout) = self.program::<B>(model, &files[0], &files[1])?;
+            effect.description().clone()
+                .with_program(vertex_code, pixel_code)
+                .with_layout(layout)
+        };
+
+        effect.rebuild(platform, description)
+    }
+
+    /// Translated vertex and pixel code along with the layout reflected from their SPIR-V.
+    fn program<B>(&self, model: &B::ShaderModel, vertex: &Path, pixel: &Path) -> RenderResult<(Vec<u8>, Vec<u8>, PipelineLayout)> where B: backend::Backend {
+        let target = B::shader_target(model);
+        let vertex_source = read_stage(vertex, Stage::Vertex)?;
+        let pixel_source = read_stage(pixel, Stage::Pixel)?;
+
+        let layout = PipelineLayout::new(&[
+            ShaderReflection::parse(&self.translate(&vertex_source, Stage::Vertex, ShaderTarget::SpirV)?)?,
+            ShaderReflection::parse(&self.translate(&pixel_source, Stage::Pixel, ShaderTarget::SpirV)?)?
+        ])?;
+
+        let vertex_code = self.translate(&vertex_source, Stage::Vertex, target)?;
+        let pixel_code = self.translate(&pixel_source, Stage::Pixel, target)?;
+
+        Ok((vertex_code, pixel_code, layout))
+    }
+}
+
+/// Reads a shader file, checking its extension names the expected stage.
+fn read_stage(path: &Path, stage: Stage) -> RenderResult<Vec<u8>> {
+    if stage_of(path) != Some(stage) {
+        return Err(RenderError::ShaderTranslation(format!("{} is not a {:?} shader", path.display(), stage)));
+    }
+
+    read(path)
+}
+
+fn read(path: &Path) -> RenderResult<Vec<u8>> {
+    let mut code = Vec::new();
+
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut code))
+        .map_err(|e| RenderError::ShaderLoad(path.display().to_string(), e))?;
+
+    Ok(code)
+}
+
+fn write(path: &Path, code: &[u8]) -> RenderResult<()> {
+    File::create(path)
+        .and_then(|mut file| file.write_all(code))
+        .map_err(|e| RenderError::ShaderLoad(path.display().to_string(), e))
+}
+
+/// Runs a tool, turning a failure into an error carrying its output.
+fn run(mut command: Command, tool: &Path) -> RenderResult<()> {
+    let output = command.output()
+        .map_err(|e| RenderError::ShaderTranslation(format!("could not run {}: {}", tool.display(), e)))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        // glslangValidator reports errors on stdout, spirv-cross on stderr.
+        let mut message = String::from_utf8_lossy(&output.stdout).into_owned();
+        message.push_str(&String::from_utf8_lossy(&output.stderr));
+
+        Err(RenderError::ShaderTranslation(format!("{} failed: {}", tool.display(), message.trim())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    const SOURCE: &'static [u8] = b"#version 450\nvoid main() {}\n";
+    const GLSL_150: ShaderTarget = ShaderTarget::Glsl { version: 150, es: false };
+
+    #[test]
+    fn keying_translations() {
+        assert_eq!(fnv(b"", 0xcbf2_9ce4_8422_2325), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv(b"a", 0xcbf2_9ce4_8422_2325), 0xaf63_dc4c_8601_ec8c);
+
+        let key = cache_key(SOURCE, Stage::Pixel, GLSL_150);
+        assert_eq!(key, cache_key(SOURCE, Stage::Pixel, GLSL_150));
+        assert!(key != cache_key(SOURCE, Stage::Vertex, GLSL_150));
+        assert!(key != cache_key(SOURCE, Stage::Pixel, ShaderTarget::Glsl { version: 300, es: true }));
+        assert!(key != cache_key(SOURCE, Stage::Pixel, ShaderTarget::SpirV));
+
+        assert_eq!(stage_of(Path::new("shaders/lit.frag")), Some(Stage::Pixel));
+        assert_eq!(stage_of(Path::new("shaders/lit.glsl")), None);
+    }
+
+    #[test]
+    fn reusing_cached_translations() {
+        // A directory of its own per run, so concurrent test runs do not share cache entries.
+        let stamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let cache = env::temp_dir().join(format!("chopper-translate-test-{}-{}", stamp.as_secs(), stamp.subsec_nanos()));
+        let translator = ShaderTranslator::new(&cache).with_tools("missing-glslang", "missing-spirv-cross");
+
+        match translator.translate(SOURCE, Stage::Pixel, GLSL_150) {
+            Err(RenderError::ShaderTranslation(ref message)) => assert!(message.contains("missing-glslang")),
+            _ => panic!("expected a translation error")
+        }
+
+        write(&translator.cache_path(SOURCE, Stage::Pixel, GLSL_150), b"#version 150\nvoid main() {}\n").unwrap();
+        assert_eq!(translator.translate(SOURCE, Stage::Pixel, GLSL_150).unwrap(), b"#version 150\nvoid main() {}\n".to_vec());
+
+        // Cached SPIR-V is cross-compiled without running glslangValidator again.
+        write(&translator.cache_path(SOURCE, Stage::Vertex, ShaderTarget::SpirV), b"spirv").unwrap();
+        assert_eq!(translator.translate(SOURCE, Stage::Vertex, ShaderTarget::SpirV).unwrap(), b"spirv".to_vec());
+        match translator.translate(SOURCE, Stage::Vertex, GLSL_150) {
+            Err(RenderError::ShaderTranslation(ref message)) => assert!(message.contains("missing-spirv-cross")),
+            _ => panic!("expected a translation error")
+        }
+
+        fs::remove_dir_all(&cache).unwrap();
+    }
+}
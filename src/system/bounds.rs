@@ -0,0 +1,587 @@
+use std::collections::{BTreeMap, BTreeSet};
+use nalgebra::{Matrix4, Vector3};
+use system::entity::Entity;
+use system::scene::{self, SceneManager, Subscriber, TransformEvent};
+
+pub type Bounds = u32;
+
+/// Axis-aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl Aabb {
+    pub fn new(min: Vector3<f32>, max: Vector3<f32>) -> Aabb {
+        Aabb { min, max }
+    }
+
+    /// Box spanning `extents` in every direction around `center`.
+    pub fn from_center(center: Vector3<f32>, extents: Vector3<f32>) -> Aabb {
+        Aabb {
+            min: center - extents,
+            max: center + extents,
+        }
+    }
+
+    pub fn center(&self) -> Vector3<f32> {
+        (self.min + self.max) * 0.5f32
+    }
+
+    /// Half the size of the box along each axis.
+    pub fn extents(&self) -> Vector3<f32> {
+        (self.max - self.min) * 0.5f32
+    }
+
+    /// Surface area of the box, used as a cost metric by the spatial index.
+    pub fn surface_area(&self) -> f32 {
+        let size = self.max - self.min;
+        2.0f32 * (size[0] * size[1] + size[1] * size[2] + size[2] * size[0])
+    }
+
+    /// Smallest box enclosing both boxes.
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        let mut out = *self;
+
+        for a in 0..3 {
+            out.min[a] = out.min[a].min(other.min[a]);
+            out.max[a] = out.max[a].max(other.max[a]);
+        }
+
+        out
+    }
+
+    pub fn contains(&self, other: &Aabb) -> bool {
+        (0..3).all(|a| self.min[a] <= other.min[a] && self.max[a] >= other.max[a])
+    }
+
+    pub fn contains_point(&self, point: &Vector3<f32>) -> bool {
+        (0..3).all(|a| self.min[a] <= point[a] && self.max[a] >= point[a])
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        (0..3).all(|a| self.min[a] <= other.max[a] && self.max[a] >= other.min[a])
+    }
+
+    /// Squared distance from a point to the nearest point of the box, zero if it lies inside.
+    pub fn distance_squared(&self, point: &Vector3<f32>) -> f32 {
+        (0..3).fold(0.0f32, |acc, a| {
+            let d = (self.min[a] - point[a]).max(0.0f32).max(point[a] - self.max[a]);
+            acc + d * d
+        })
+    }
+
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        self.distance_squared(&sphere.center) <= sphere.radius * sphere.radius
+    }
+
+    /// Grows the box by `margin` along every axis.
+    pub fn inflate(&self, margin: f32) -> Aabb {
+        Aabb::from_center(self.center(), self.extents() + Vector3::from_element(margin))
+    }
+
+    /// Box enclosing this box after it has been transformed by a scene matrix.
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Aabb {
+        let translation = scene::transform_point(matrix, &Vector3::from_element(0.0f32));
+        let mut out = Aabb::new(translation, translation);
+
+        for c in 0..3 {
+            for r in 0..3 {
+                let a = matrix[(r, c)] * self.min[r];
+                let b = matrix[(r, c)] * self.max[r];
+
+                out.min[c] += a.min(b);
+                out.max[c] += a.max(b);
+            }
+        }
+
+        out
+    }
+}
+
+/// Bounding sphere.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sphere {
+    pub center: Vector3<f32>,
+    pub radius: f32,
+}
+
+impl Sphere {
+    pub fn new(center: Vector3<f32>, radius: f32) -> Sphere {
+        Sphere { center, radius }
+    }
+
+    /// Sphere circumscribing a box.
+    pub fn from_aabb(aabb: &Aabb) -> Sphere {
+        Sphere {
+            center: aabb.center(),
+            radius: aabb.extents().norm(),
+        }
+    }
+
+    pub fn intersects(&self, other: &Sphere) -> bool {
+        let reach = self.radius + other.radius;
+        (self.center - other.center).norm_squared() <= reach * reach
+    }
+
+    /// Sphere enclosing this sphere after it has been transformed by a scene matrix.
+    ///
+    /// Non-uniform scale is handled conservatively by scaling the radius by the largest axis.
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Sphere {
+        let scale = (0..3)
+            .map(|c| Vector3::new(matrix[(0, c)], matrix[(1, c)], matrix[(2, c)]).norm())
+            .fold(0.0f32, f32::max);
+
+        Sphere {
+            center: scene::transform_point(matrix, &self.center),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+/// Plane in Hessian normal form.
+///
+/// Points for which `normal · p + distance` is positive lie in front of the plane.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Plane {
+    pub normal: Vector3<f32>,
+    pub distance: f32,
+}
+
+impl Plane {
+    /// Builds a plane from possibly unnormalized coefficients, normalizing them.
+    pub fn new(normal: Vector3<f32>, distance: f32) -> Plane {
+        let length = normal.norm();
+
+        Plane {
+            normal: normal / length,
+            distance: distance / length,
+        }
+    }
+
+    pub fn signed_distance(&self, point: &Vector3<f32>) -> f32 {
+        self.normal.dot(point) + self.distance
+    }
+}
+
+/// Convex volume bounded by six inward-facing planes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frustum {
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    pub fn new(planes: [Plane; 6]) -> Frustum {
+        Frustum { planes }
+    }
+
+    /// Extracts the left, right, bottom, top, near and far planes of a view-projection matrix in
+    /// the scene's row-vector layout.
+    ///
+    /// `depth_range` holds the normalized device depths of the near and far planes, as reported by
+    /// the camera manager, so the same extraction serves every clip-space convention.
+    pub fn from_matrix(matrix: &Matrix4<f32>, depth_range: (f32, f32)) -> Frustum {
+        let column = |c: usize| [matrix[(0, c)], matrix[(1, c)], matrix[(2, c)], matrix[(3, c)]];
+        let (x, y, z, w) = (column(0), column(1), column(2), column(3));
+
+        let lower = depth_range.0.min(depth_range.1);
+        let upper = depth_range.0.max(depth_range.1);
+
+        let plane = |f: &Fn(usize) -> f32| Plane::new(Vector3::new(f(0), f(1), f(2)), f(3));
+
+        let mut planes = [
+            plane(&|i| w[i] + x[i]),
+            plane(&|i| w[i] - x[i]),
+            plane(&|i| w[i] + y[i]),
+            plane(&|i| w[i] - y[i]),
+            plane(&|i| z[i] - lower * w[i]),
+            plane(&|i| upper * w[i] - z[i]),
+        ];
+
+        // Keep the near plane first in the pair regardless of reversed depth.
+        if depth_range.0 > depth_range.1 {
+            planes.swap(4, 5);
+        }
+
+        Frustum { planes }
+    }
+
+    /// Conservative sphere test: false only if the sphere lies entirely outside some plane.
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        self.planes.iter().all(|plane| plane.signed_distance(&sphere.center) >= -sphere.radius)
+    }
+
+    /// Conservative box test using the corner furthest along each plane normal.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            let mut positive = aabb.min;
+
+            for a in 0..3 {
+                if plane.normal[a] >= 0.0f32 {
+                    positive[a] = aabb.max[a];
+                }
+            }
+
+            plane.signed_distance(&positive) >= 0.0f32
+        })
+    }
+}
+
+/// Manages bounding volume components.
+///
+/// Each entity with bounds stores a local box and sphere. World-space volumes are refreshed from
+/// the scene manager's world matrices during `update`, which consumes changes through its own
+/// scene subscription. When hierarchical bounds are enabled, every entity additionally stores a
+/// box enclosing itself and all of its bounded descendants.
+pub struct BoundsManager {
+    bounds: BTreeMap<Entity, Bounds>,
+    subscriber: Option<Subscriber>,
+    hierarchical: bool,
+    refreshed: Vec<Entity>,
+    destroyed: Vec<Entity>,
+    removed: Vec<Entity>,
+
+    // Component data buffers.
+    entity: Vec<Entity>,
+    local_aabb: Vec<Aabb>,
+    local_sphere: Vec<Sphere>,
+    world_aabb: Vec<Aabb>,
+    world_sphere: Vec<Sphere>,
+    hierarchy_aabb: Vec<Aabb>,
+    stale: Vec<bool>,
+}
+
+impl BoundsManager {
+    pub fn new() -> BoundsManager {
+        BoundsManager {
+            bounds: BTreeMap::new(),
+            subscriber: None,
+            hierarchical: false,
+            refreshed: Vec::new(),
+            destroyed: Vec::new(),
+            removed: Vec::new(),
+            entity: Vec::new(),
+            local_aabb: Vec::new(),
+            local_sphere: Vec::new(),
+            world_aabb: Vec::new(),
+            world_sphere: Vec::new(),
+            hierarchy_aabb: Vec::new(),
+            stale: Vec::new(),
+        }
+    }
+
+    /// Creates bounds from a local box, deriving the local sphere from it.
+    pub fn create_bounds(&mut self, entity: Entity, local: Aabb) -> Bounds {
+        let next = self.entity.len() as u32;
+        let sphere = Sphere::from_aabb(&local);
+
+        self.entity.push(entity);
+        self.local_aabb.push(local);
+        self.local_sphere.push(sphere);
+        self.world_aabb.push(local);
+        self.world_sphere.push(sphere);
+        self.hierarchy_aabb.push(local);
+        self.stale.push(true);
+
+        self.bounds.insert(entity, next);
+
+        next
+    }
+
+    pub fn destroy_bounds(&mut self, bounds: Bounds) {
+        assert!(bounds < self.entity.len() as u32, "bounds {} do not exist", bounds);
+
+        let target = bounds as usize;
+        let last = self.entity.len() - 1;
+
+        let entity = self.entity[target];
+        let last_entity = self.entity[last];
+
+        self.entity.swap_remove(target);
+        self.local_aabb.swap_remove(target);
+        self.local_sphere.swap_remove(target);
+        self.world_aabb.swap_remove(target);
+        self.world_sphere.swap_remove(target);
+        self.hierarchy_aabb.swap_remove(target);
+        self.stale.swap_remove(target);
+
+        self.bounds.insert(last_entity, bounds);
+        self.bounds.remove(&entity);
+
+        // Ancestors still enclose the removed box until the next update recomputes them.
+        self.destroyed.push(entity);
+    }
+
+    pub fn has_bounds(&self, entity: Entity) -> bool {
+        self.bounds.contains_key(&entity)
+    }
+
+    pub fn bounds_for(&self, entity: Entity) -> Bounds {
+        match self.bounds.get(&entity) {
+            Some(bounds) => *bounds,
+            None => panic!("entity {} has no bounds", entity)
+        }
+    }
+
+    /// Replaces the local volumes of an entity. World bounds are refreshed on the next update.
+    pub fn set_local_bounds(&mut self, entity: Entity, aabb: Aabb, sphere: Sphere) {
+        let instance = self.bounds_for(entity) as usize;
+
+        self.local_aabb[instance] = aabb;
+        self.local_sphere[instance] = sphere;
+        self.stale[instance] = true;
+    }
+
+    pub fn local_aabb(&self, entity: Entity) -> Aabb {
+        self.local_aabb[self.bounds_for(entity) as usize]
+    }
+
+    pub fn local_sphere(&self, entity: Entity) -> Sphere {
+        self.local_sphere[self.bounds_for(entity) as usize]
+    }
+
+    pub fn world_aabb(&self, entity: Entity) -> Aabb {
+        self.world_aabb[self.bounds_for(entity) as usize]
+    }
+
+    pub fn world_sphere(&self, entity: Entity) -> Sphere {
+        self.world_sphere[self.bounds_for(entity) as usize]
+    }
+
+    /// Box enclosing an entity and all bounded descendants, if hierarchical bounds are enabled.
+    pub fn hierarchy_aabb(&self, entity: Entity) -> Option<Aabb> {
+        if !self.hierarchical {
+            return None
+        }
+
+        self.bounds.get(&entity).map(|&instance| self.hierarchy_aabb[instance as usize])
+    }
+
+    /// Enables or disables maintenance of hierarchical bounds.
+    pub fn set_hierarchical(&mut self, enabled: bool) {
+        self.hierarchical = enabled;
+
+        for stale in self.stale.iter_mut() {
+            *stale = true;
+        }
+    }
+
+    /// Entities whose world bounds were recomputed by the last update.
+    pub fn refreshed(&self) -> &[Entity] {
+        &self.refreshed
+    }
+
+    /// Entities dropped by the last update, either because their bounds were destroyed or
+    /// because their transform was.
+    pub fn removed(&self) -> &[Entity] {
+        &self.removed
+    }
+
+    /// Iterates over all entities with bounds.
+    pub fn entities(&self) -> ::std::slice::Iter<Entity> {
+        self.entity.iter()
+    }
+
+    /// Recomputes world bounds for every entity whose transform changed since the last update
+    /// or whose local bounds were replaced.
+    pub fn update(&mut self, scene: &mut SceneManager) {
+        let subscriber = match self.subscriber {
+            Some(subscriber) => subscriber,
+            None => {
+                let subscriber = scene.subscribe();
+                self.subscriber = Some(subscriber);
+                subscriber
+            }
+        };
+
+        let mut entities = Vec::new();
+        let mut transforms = Vec::new();
+        let mut events = Vec::new();
+        scene.changed_since(subscriber, &mut entities, &mut transforms);
+        scene.events_since(subscriber, &mut events);
+        scene.acknowledge(subscriber);
+
+        self.removed.clear();
+        self.removed.extend(self.destroyed.drain(..));
+
+        for event in events {
+            if let TransformEvent::Destroyed(entity) = event {
+                if self.bounds.contains_key(&entity) {
+                    self.removed.push(entity);
+                }
+            }
+        }
+
+        for entity in entities {
+            if let Some(&instance) = self.bounds.get(&entity) {
+                self.stale[instance as usize] = true;
+            }
+        }
+
+        self.refreshed.clear();
+
+        for a in 0..self.entity.len() {
+            if !self.stale[a] {
+                continue;
+            }
+
+            let entity = self.entity[a];
+            let world = if scene.has_transform(entity) {
+                scene.world_matrix(entity)
+            } else {
+                Matrix4::identity()
+            };
+
+            self.world_aabb[a] = self.local_aabb[a].transform(&world);
+            self.world_sphere[a] = self.local_sphere[a].transform(&world);
+            self.stale[a] = false;
+            self.refreshed.push(entity);
+        }
+
+        if self.hierarchical {
+            self.refresh_hierarchy(scene);
+        }
+    }
+
+    /// Recomputes hierarchical bounds for every refreshed entity and its ancestors, as well as
+    /// the ancestors of removed entities.
+    fn refresh_hierarchy(&mut self, scene: &SceneManager) {
+        let mut pending = BTreeSet::new();
+
+        for &entity in self.removed.iter() {
+            if scene.has_transform(entity) {
+                pending.extend(scene.ancestors(entity));
+            }
+        }
+
+        for &entity in self.refreshed.iter() {
+            pending.insert(entity);
+
+            if scene.has_transform(entity) {
+                pending.extend(scene.ancestors(entity));
+            }
+        }
+
+        for entity in pending {
+            if let Some(&instance) = self.bounds.get(&entity) {
+                if let Some(enclosing) = self.enclose(scene, entity) {
+                    self.hierarchy_aabb[instance as usize] = enclosing;
+                }
+            }
+        }
+    }
+
+    fn enclose(&self, scene: &SceneManager, entity: Entity) -> Option<Aabb> {
+        let own = self.bounds.get(&entity).map(|&instance| self.world_aabb[instance as usize]);
+
+        if !scene.has_transform(entity) {
+            return own;
+        }
+
+        scene.children(entity).fold(own, |acc, child| {
+            match (acc, self.enclose(scene, child)) {
+                (Some(a), Some(b)) => Some(a.union(&b)),
+                (a, b) => a.or(b)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box() -> Aabb {
+        Aabb::new(Vector3::from_element(-1.0f32), Vector3::from_element(1.0f32))
+    }
+
+    #[test]
+    fn transforming_boxes() {
+        let mut matrix = Matrix4::identity();
+        matrix[(3, 0)] = 5.0f32;
+        matrix[(0, 0)] = 2.0f32;
+
+        let aabb = unit_box().transform(&matrix);
+
+        assert_eq!(aabb.min, Vector3::new(3.0f32, -1.0f32, -1.0f32));
+        assert_eq!(aabb.max, Vector3::new(7.0f32, 1.0f32, 1.0f32));
+    }
+
+    #[test]
+    fn updating_world_bounds() {
+        let mut scene = SceneManager::new();
+        let mut manager = BoundsManager::new();
+        let entity = 3 as Entity;
+
+        scene.create_transform(entity);
+        manager.create_bounds(entity, unit_box());
+        manager.update(&mut scene);
+
+        scene.set_local_position(entity, Vector3::new(10.0f32, 0.0f32, 0.0f32));
+        manager.update(&mut scene);
+
+        assert_eq!(manager.refreshed(), &[entity]);
+        assert_eq!(manager.world_aabb(entity).center(), Vector3::new(10.0f32, 0.0f32, 0.0f32));
+        assert_eq!(manager.world_sphere(entity).center, Vector3::new(10.0f32, 0.0f32, 0.0f32));
+
+        manager.update(&mut scene);
+        assert_eq!(manager.refreshed().len(), 0);
+    }
+
+    #[test]
+    fn enclosing_descendants() {
+        let mut scene = SceneManager::new();
+        let mut manager = BoundsManager::new();
+        let parent = 3 as Entity;
+        let child = 5 as Entity;
+
+        scene.create_transform(parent);
+        scene.create_transform(child);
+        scene.set_local_position(child, Vector3::new(10.0f32, 0.0f32, 0.0f32));
+        scene.link(child, parent);
+
+        manager.set_hierarchical(true);
+        manager.create_bounds(parent, unit_box());
+        manager.create_bounds(child, unit_box());
+        manager.update(&mut scene);
+
+        let enclosing = manager.hierarchy_aabb(parent).unwrap();
+        assert_eq!(enclosing.min, Vector3::new(-1.0f32, -1.0f32, -1.0f32));
+        assert_eq!(enclosing.max, Vector3::new(11.0f32, 1.0f32, 1.0f32));
+    }
+
+    #[test]
+    fn shrinking_enclosing_bounds() {
+        let mut scene = SceneManager::new();
+        let mut manager = BoundsManager::new();
+        let (root, parent, child) = (2 as Entity, 3 as Entity, 5 as Entity);
+
+        for &entity in [root, parent, child].iter() {
+            scene.create_transform(entity);
+        }
+
+        scene.set_local_position(child, Vector3::new(10.0f32, 0.0f32, 0.0f32));
+        scene.link(parent, root);
+        scene.link(child, parent);
+
+        manager.set_hierarchical(true);
+        manager.create_bounds(root, unit_box());
+        manager.create_bounds(child, unit_box());
+        manager.update(&mut scene);
+        assert_eq!(manager.hierarchy_aabb(root).unwrap().max, Vector3::new(11.0f32, 1.0f32, 1.0f32));
+
+        scene.unlink(child);
+        manager.update(&mut scene);
+        assert_eq!(manager.hierarchy_aabb(root).unwrap(), unit_box());
+
+        scene.link(child, parent);
+        manager.update(&mut scene);
+        assert_eq!(manager.hierarchy_aabb(root).unwrap().max, Vector3::new(11.0f32, 1.0f32, 1.0f32));
+
+        let bounds = manager.bounds_for(child);
+        manager.destroy_bounds(bounds);
+        manager.update(&mut scene);
+        assert_eq!(manager.hierarchy_aabb(root).unwrap(), unit_box());
+    }
+}
@@ -0,0 +1,373 @@
+use std::collections::BTreeMap;
+use nalgebra::{Matrix4, Vector3};
+use system::entity::Entity;
+use system::bounds::Frustum;
+use system::raycast::Ray;
+use system::scene::{Pose, SceneManager};
+use render::backend::ClipSpace;
+
+pub type Camera = u32;
+
+/// Projection model of a camera.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    /// Perspective projection with a vertical field of view in radians.
+    Perspective { fov_y: f32 },
+    /// Orthographic projection spanning `height` world units vertically.
+    Orthographic { height: f32 },
+}
+
+/// Region of the render target a camera draws into, in pixels from the top left.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Viewport {
+        Viewport { x, y, width, height }
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.width / self.height
+    }
+}
+
+/// Manages camera components.
+///
+/// Cameras look down the local -Z axis of their entity's transform with +Y up. Matrices follow
+/// the scene's row-vector layout, so a world point is projected as `p * view * projection`. The
+/// clip-space conventions of the target backend are supplied to `update` via `Backend::clip_space`.
+pub struct CameraManager {
+    cameras: BTreeMap<Entity, Camera>,
+    clip: ClipSpace,
+
+    // Component data buffers.
+    entity: Vec<Entity>,
+    projection_kind: Vec<Projection>,
+    viewport: Vec<Viewport>,
+    near: Vec<f32>,
+    far: Vec<f32>,
+    reversed_z: Vec<bool>,
+    view: Vec<Matrix4<f32>>,
+    projection: Vec<Matrix4<f32>>,
+    view_projection: Vec<Matrix4<f32>>,
+    depth_range: Vec<(f32, f32)>,
+}
+
+impl CameraManager {
+    pub fn new() -> CameraManager {
+        CameraManager {
+            cameras: BTreeMap::new(),
+            clip: ClipSpace { zero_to_one_depth: false, y_down: false },
+            entity: Vec::new(),
+            projection_kind: Vec::new(),
+            viewport: Vec::new(),
+            near: Vec::new(),
+            far: Vec::new(),
+            reversed_z: Vec::new(),
+            view: Vec::new(),
+            projection: Vec::new(),
+            view_projection: Vec::new(),
+            depth_range: Vec::new(),
+        }
+    }
+
+    pub fn create_camera(&mut self, entity: Entity, projection: Projection, viewport: Viewport) -> Camera {
+        let next = self.entity.len() as u32;
+
+        self.entity.push(entity);
+        self.projection_kind.push(projection);
+        self.viewport.push(viewport);
+        self.near.push(0.1f32);
+        self.far.push(1000.0f32);
+        self.reversed_z.push(false);
+        self.view.push(Matrix4::identity());
+        self.projection.push(Matrix4::identity());
+        self.view_projection.push(Matrix4::identity());
+        self.depth_range.push((-1.0f32, 1.0f32));
+
+        self.cameras.insert(entity, next);
+
+        next
+    }
+
+    pub fn destroy_camera(&mut self, camera: Camera) {
+        assert!(camera < self.entity.len() as u32, "camera {} does not exist", camera);
+
+        let target = camera as usize;
+        let last = self.entity.len() - 1;
+
+        let entity = self.entity[target];
+        let last_entity = self.entity[last];
+
+        self.entity.swap_remove(target);
+        self.projection_kind.swap_remove(target);
+        self.viewport.swap_remove(target);
+        self.near.swap_remove(target);
+        self.far.swap_remove(target);
+        self.reversed_z.swap_remove(target);
+        self.view.swap_remove(target);
+        self.projection.swap_remove(target);
+        self.view_projection.swap_remove(target);
+        self.depth_range.swap_remove(target);
+
+        self.cameras.insert(last_entity, camera);
+        self.cameras.remove(&entity);
+    }
+
+    pub fn has_camera(&self, entity: Entity) -> bool {
+        self.cameras.contains_key(&entity)
+    }
+
+    pub fn camera_for(&self, entity: Entity) -> Camera {
+        match self.cameras.get(&entity) {
+            Some(camera) => *camera,
+            None => panic!("entity {} has no camera", entity)
+        }
+    }
+
+    /// Iterates over all entities with cameras.
+    pub fn entities(&self) -> ::std::slice::Iter<Entity> {
+        self.entity.iter()
+    }
+
+    pub fn set_projection(&mut self, entity: Entity, projection: Projection) {
+        let instance = self.camera_for(entity) as usize;
+        self.projection_kind[instance] = projection;
+    }
+
+    pub fn projection_kind(&self, entity: Entity) -> Projection {
+        self.projection_kind[self.camera_for(entity) as usize]
+    }
+
+    pub fn set_viewport(&mut self, entity: Entity, viewport: Viewport) {
+        let instance = self.camera_for(entity) as usize;
+        self.viewport[instance] = viewport;
+    }
+
+    pub fn viewport(&self, entity: Entity) -> Viewport {
+        self.viewport[self.camera_for(entity) as usize]
+    }
+
+    pub fn set_clip_planes(&mut self, entity: Entity, near: f32, far: f32) {
+        let instance = self.camera_for(entity) as usize;
+
+        self.near[instance] = near;
+        self.far[instance] = far;
+    }
+
+    pub fn clip_planes(&self, entity: Entity) -> (f32, f32) {
+        let instance = self.camera_for(entity) as usize;
+        (self.near[instance], self.far[instance])
+    }
+
+    /// Maps the near plane to the far end of the depth range for better floating point
+    /// precision. Pair with a greater-than depth test.
+    pub fn set_reversed_z(&mut self, entity: Entity, reversed: bool) {
+        let instance = self.camera_for(entity) as usize;
+        self.reversed_z[instance] = reversed;
+    }
+
+    pub fn reversed_z(&self, entity: Entity) -> bool {
+        self.reversed_z[self.camera_for(entity) as usize]
+    }
+
+    pub fn view(&self, entity: Entity) -> Matrix4<f32> {
+        self.view[self.camera_for(entity) as usize]
+    }
+
+    pub fn projection(&self, entity: Entity) -> Matrix4<f32> {
+        self.projection[self.camera_for(entity) as usize]
+    }
+
+    pub fn view_projection(&self, entity: Entity) -> Matrix4<f32> {
+        self.view_projection[self.camera_for(entity) as usize]
+    }
+
+    /// Normalized device depths of the near and far planes as of the last update.
+    pub fn depth_range(&self, entity: Entity) -> (f32, f32) {
+        self.depth_range[self.camera_for(entity) as usize]
+    }
+
+    /// World-space view frustum of the camera as of the last update.
+    pub fn frustum(&self, entity: Entity) -> Frustum {
+        let instance = self.camera_for(entity) as usize;
+        Frustum::from_matrix(&self.view_projection[instance], self.depth_range[instance])
+    }
+
+    /// World-space position of the camera as of the last update.
+    pub fn position(&self, entity: Entity) -> Vector3<f32> {
+        let view = self.view(entity);
+
+        match view.try_inverse() {
+            Some(world) => Vector3::new(world[(3, 0)], world[(3, 1)], world[(3, 2)]),
+            None => Vector3::from_element(0.0f32)
+        }
+    }
+
+    /// World-space ray through a pixel of the camera's viewport.
+    pub fn screen_ray(&self, entity: Entity, screen: (f32, f32)) -> Option<Ray> {
+        let instance = self.camera_for(entity) as usize;
+        let viewport = self.viewport[instance];
+
+        // Screen rows run downwards; mirror them when clip space does too so the unprojection
+        // always sees an upward NDC Y.
+        let screen = if self.clip.y_down {
+            (screen.0, 2.0f32 * viewport.y + viewport.height - screen.1)
+        } else {
+            screen
+        };
+
+        Ray::from_screen(screen, (viewport.x, viewport.y, viewport.width, viewport.height),
+                         &self.view[instance], &self.projection[instance], self.depth_range[instance])
+    }
+
+    /// Recomputes view and projection matrices from the scene's world transforms.
+    pub fn update(&mut self, scene: &SceneManager, clip: ClipSpace) {
+        self.clip = clip;
+
+        for a in 0..self.entity.len() {
+            let entity = self.entity[a];
+
+            let world = if scene.has_transform(entity) {
+                scene.world_matrix(entity)
+            } else {
+                Matrix4::identity()
+            };
+
+            let mut pose = Pose::from_matrix(&world);
+            pose.scale = Vector3::from_element(1.0f32);
+
+            let depth_range = match (clip.zero_to_one_depth, self.reversed_z[a]) {
+                (true, false) => (0.0f32, 1.0f32),
+                (true, true) => (1.0f32, 0.0f32),
+                (false, false) => (-1.0f32, 1.0f32),
+                (false, true) => (1.0f32, -1.0f32),
+            };
+
+            let projection = projection_matrix(self.projection_kind[a], self.viewport[a].aspect(),
+                                               self.near[a], self.far[a], depth_range, clip.y_down);
+            let view = pose.to_matrix().try_inverse().unwrap_or_else(|| Matrix4::identity());
+
+            self.view[a] = view;
+            self.projection[a] = projection;
+            self.view_projection[a] = view * projection;
+            self.depth_range[a] = depth_range;
+        }
+    }
+}
+
+/// Builds a right-handed projection matrix in the scene's row-vector layout.
+///
+/// View-space points at `-near` and `-far` along Z map to the first and second normalized
+/// device depths in `depth_range` respectively.
+pub fn projection_matrix(projection: Projection, aspect: f32, near: f32, far: f32, depth_range: (f32, f32), y_down: bool) -> Matrix4<f32> {
+    let (d_near, d_far) = depth_range;
+    let flip = if y_down { -1.0f32 } else { 1.0f32 };
+    let mut matrix = Matrix4::from_element(0.0f32);
+
+    match projection {
+        Projection::Perspective { fov_y } => {
+            let focal = 1.0f32 / (fov_y * 0.5f32).tan();
+            let a = (d_near * near - d_far * far) / (far - near);
+
+            matrix[(0, 0)] = focal / aspect;
+            matrix[(1, 1)] = focal * flip;
+            matrix[(2, 2)] = a;
+            matrix[(2, 3)] = -1.0f32;
+            matrix[(3, 2)] = d_near * near + a * near;
+        },
+        Projection::Orthographic { height } => {
+            let a = (d_near - d_far) / (far - near);
+
+            matrix[(0, 0)] = 2.0f32 / (height * aspect);
+            matrix[(1, 1)] = 2.0f32 / height * flip;
+            matrix[(2, 2)] = a;
+            matrix[(3, 2)] = d_near + a * near;
+            matrix[(3, 3)] = 1.0f32;
+        }
+    }
+
+    matrix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    const OPENGL: ClipSpace = ClipSpace { zero_to_one_depth: false, y_down: false };
+    const VULKAN: ClipSpace = ClipSpace { zero_to_one_depth: true, y_down: true };
+
+    fn project(matrix: &Matrix4<f32>, point: Vector3<f32>) -> Vector3<f32> {
+        let mut clip = [0.0f32; 4];
+        let input = [point[0], point[1], point[2], 1.0f32];
+
+        for c in 0..4 {
+            for r in 0..4 {
+                clip[c] += input[r] * matrix[(r, c)];
+            }
+        }
+
+        Vector3::new(clip[0] / clip[3], clip[1] / clip[3], clip[2] / clip[3])
+    }
+
+    fn approx(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1.0e-4
+    }
+
+    #[test]
+    fn mapping_clip_planes() {
+        let mut scene = SceneManager::new();
+        let mut manager = CameraManager::new();
+        let entity = 3 as Entity;
+
+        scene.create_transform(entity);
+        manager.create_camera(entity, Projection::Perspective { fov_y: PI / 2.0f32 }, Viewport::new(0.0f32, 0.0f32, 100.0f32, 100.0f32));
+        manager.set_clip_planes(entity, 1.0f32, 10.0f32);
+
+        manager.update(&scene, OPENGL);
+        let near = project(&manager.view_projection(entity), Vector3::new(0.0f32, 0.0f32, -1.0f32));
+        let far = project(&manager.view_projection(entity), Vector3::new(0.0f32, 0.0f32, -10.0f32));
+        assert!(approx(near[2], -1.0f32) && approx(far[2], 1.0f32));
+
+        manager.set_reversed_z(entity, true);
+        manager.update(&scene, VULKAN);
+        let near = project(&manager.view_projection(entity), Vector3::new(0.0f32, 0.0f32, -1.0f32));
+        let far = project(&manager.view_projection(entity), Vector3::new(0.0f32, 0.0f32, -10.0f32));
+        assert!(approx(near[2], 1.0f32) && approx(far[2], 0.0f32));
+    }
+
+    #[test]
+    fn following_transforms() {
+        let mut scene = SceneManager::new();
+        let mut manager = CameraManager::new();
+        let entity = 3 as Entity;
+
+        scene.create_transform(entity);
+        scene.set_local_position(entity, Vector3::new(5.0f32, 0.0f32, 0.0f32));
+        manager.create_camera(entity, Projection::Orthographic { height: 2.0f32 }, Viewport::new(0.0f32, 0.0f32, 100.0f32, 100.0f32));
+        manager.update(&scene, OPENGL);
+
+        let centered = project(&manager.view_projection(entity), Vector3::new(5.0f32, 0.5f32, -2.0f32));
+        assert!(approx(centered[0], 0.0f32) && approx(centered[1], 0.5f32));
+        assert_eq!(manager.position(entity), Vector3::new(5.0f32, 0.0f32, 0.0f32));
+    }
+
+    #[test]
+    fn casting_screen_rays() {
+        let scene = SceneManager::new();
+        let mut manager = CameraManager::new();
+        let entity = 3 as Entity;
+
+        manager.create_camera(entity, Projection::Perspective { fov_y: PI / 2.0f32 }, Viewport::new(0.0f32, 0.0f32, 100.0f32, 100.0f32));
+        manager.update(&scene, VULKAN);
+
+        let ray = manager.screen_ray(entity, (50.0f32, 50.0f32)).unwrap();
+        assert!(approx(ray.direction[2], -1.0f32));
+    }
+}
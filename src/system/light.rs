@@ -0,0 +1,315 @@
+use std::collections::BTreeMap;
+use nalgebra::{Matrix4, Vector3};
+use system::entity::Entity;
+use system::scene::{self, SceneManager};
+
+pub type Light = u32;
+
+/// Kind of light emitted by an entity.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
+pub enum LightKind {
+    /// Infinitely distant light shining along the entity's -Z axis.
+    Directional,
+    /// Omnidirectional light at the entity's position.
+    Point,
+    /// Cone of light from the entity's position along its -Z axis.
+    Spot,
+}
+
+impl LightKind {
+    fn index(&self) -> usize {
+        match *self {
+            LightKind::Directional => 0,
+            LightKind::Point => 1,
+            LightKind::Spot => 2,
+        }
+    }
+}
+
+// Light laid out for upload into a constant or structured buffer.
+//
+// The fourth component of `direction_kind` holds the light kind as 0, 1 or 2 for directional,
+// point and spot lights. `cone_shadow` holds the cosines of the inner and outer cone angles, the
+// shadow flag and a spare slot.
+gfx_defines! {
+    constant PackedLight {
+        position_range: [f32; 4] = "position_range",
+        direction_kind: [f32; 4] = "direction_kind",
+        color_intensity: [f32; 4] = "color_intensity",
+        cone_shadow: [f32; 4] = "cone_shadow",
+    }
+}
+
+/// Manages light components.
+///
+/// Lights take their world position and direction from the scene manager during `update`,
+/// which also rebuilds the packed light array grouped by kind: directional lights first, then
+/// point lights, then spot lights.
+pub struct LightManager {
+    lights: BTreeMap<Entity, Light>,
+    packed: Vec<PackedLight>,
+    packed_entities: Vec<Entity>,
+    counts: [usize; 3],
+
+    // Component data buffers.
+    entity: Vec<Entity>,
+    kind: Vec<LightKind>,
+    color: Vec<Vector3<f32>>,
+    intensity: Vec<f32>,
+    range: Vec<f32>,
+    inner_angle: Vec<f32>,
+    outer_angle: Vec<f32>,
+    shadows: Vec<bool>,
+    position: Vec<Vector3<f32>>,
+    direction: Vec<Vector3<f32>>,
+}
+
+impl LightManager {
+    pub fn new() -> LightManager {
+        LightManager {
+            lights: BTreeMap::new(),
+            packed: Vec::new(),
+            packed_entities: Vec::new(),
+            counts: [0; 3],
+            entity: Vec::new(),
+            kind: Vec::new(),
+            color: Vec::new(),
+            intensity: Vec::new(),
+            range: Vec::new(),
+            inner_angle: Vec::new(),
+            outer_angle: Vec::new(),
+            shadows: Vec::new(),
+            position: Vec::new(),
+            direction: Vec::new(),
+        }
+    }
+
+    /// Creates a white light of unit intensity and a range of ten units.
+    pub fn create_light(&mut self, entity: Entity, kind: LightKind) -> Light {
+        let next = self.entity.len() as u32;
+
+        self.entity.push(entity);
+        self.kind.push(kind);
+        self.color.push(Vector3::from_element(1.0f32));
+        self.intensity.push(1.0f32);
+        self.range.push(10.0f32);
+        self.inner_angle.push(0.0f32);
+        self.outer_angle.push(::std::f32::consts::PI / 4.0f32);
+        self.shadows.push(false);
+        self.position.push(Vector3::from_element(0.0f32));
+        self.direction.push(Vector3::new(0.0f32, 0.0f32, -1.0f32));
+
+        self.lights.insert(entity, next);
+
+        next
+    }
+
+    pub fn destroy_light(&mut self, light: Light) {
+        assert!(light < self.entity.len() as u32, "light {} does not exist", light);
+
+        let target = light as usize;
+        let last = self.entity.len() - 1;
+
+        let entity = self.entity[target];
+        let last_entity = self.entity[last];
+
+        self.entity.swap_remove(target);
+        self.kind.swap_remove(target);
+        self.color.swap_remove(target);
+        self.intensity.swap_remove(target);
+        self.range.swap_remove(target);
+        self.inner_angle.swap_remove(target);
+        self.outer_angle.swap_remove(target);
+        self.shadows.swap_remove(target);
+        self.position.swap_remove(target);
+        self.direction.swap_remove(target);
+
+        self.lights.insert(last_entity, light);
+        self.lights.remove(&entity);
+    }
+
+    pub fn has_light(&self, entity: Entity) -> bool {
+        self.lights.contains_key(&entity)
+    }
+
+    pub fn light_for(&self, entity: Entity) -> Light {
+        match self.lights.get(&entity) {
+            Some(light) => *light,
+            None => panic!("entity {} has no light", entity)
+        }
+    }
+
+    /// Iterates over all entities with lights.
+    pub fn entities(&self) -> ::std::slice::Iter<Entity> {
+        self.entity.iter()
+    }
+
+    pub fn set_kind(&mut self, entity: Entity, kind: LightKind) {
+        let instance = self.light_for(entity) as usize;
+        self.kind[instance] = kind;
+    }
+
+    pub fn kind(&self, entity: Entity) -> LightKind {
+        self.kind[self.light_for(entity) as usize]
+    }
+
+    /// Sets the linear color of the light, without intensity applied.
+    pub fn set_color(&mut self, entity: Entity, color: Vector3<f32>) {
+        let instance = self.light_for(entity) as usize;
+        self.color[instance] = color;
+    }
+
+    pub fn color(&self, entity: Entity) -> Vector3<f32> {
+        self.color[self.light_for(entity) as usize]
+    }
+
+    pub fn set_intensity(&mut self, entity: Entity, intensity: f32) {
+        let instance = self.light_for(entity) as usize;
+        self.intensity[instance] = intensity;
+    }
+
+    pub fn intensity(&self, entity: Entity) -> f32 {
+        self.intensity[self.light_for(entity) as usize]
+    }
+
+    /// Sets the distance beyond which point and spot lights contribute nothing.
+    pub fn set_range(&mut self, entity: Entity, range: f32) {
+        let instance = self.light_for(entity) as usize;
+        self.range[instance] = range;
+    }
+
+    pub fn range(&self, entity: Entity) -> f32 {
+        self.range[self.light_for(entity) as usize]
+    }
+
+    /// Sets the half-angles in radians of a spot light's full-intensity and falloff cones.
+    pub fn set_cone_angles(&mut self, entity: Entity, inner: f32, outer: f32) {
+        let instance = self.light_for(entity) as usize;
+
+        self.inner_angle[instance] = inner;
+        self.outer_angle[instance] = outer;
+    }
+
+    pub fn cone_angles(&self, entity: Entity) -> (f32, f32) {
+        let instance = self.light_for(entity) as usize;
+        (self.inner_angle[instance], self.outer_angle[instance])
+    }
+
+    pub fn set_shadows(&mut self, entity: Entity, enabled: bool) {
+        let instance = self.light_for(entity) as usize;
+        self.shadows[instance] = enabled;
+    }
+
+    pub fn shadows(&self, entity: Entity) -> bool {
+        self.shadows[self.light_for(entity) as usize]
+    }
+
+    /// World-space position of the light as of the last update.
+    pub fn world_position(&self, entity: Entity) -> Vector3<f32> {
+        self.position[self.light_for(entity) as usize]
+    }
+
+    /// World-space unit direction the light shines along as of the last update.
+    pub fn world_direction(&self, entity: Entity) -> Vector3<f32> {
+        self.direction[self.light_for(entity) as usize]
+    }
+
+    /// Packed lights built by the last update, grouped by kind.
+    pub fn packed(&self) -> &[PackedLight] {
+        &self.packed
+    }
+
+    /// Entities owning each packed light, in the same order.
+    pub fn packed_entities(&self) -> &[Entity] {
+        &self.packed_entities
+    }
+
+    /// Number of packed lights of a kind. Offsets into the packed array follow from the counts
+    /// of the kinds before it.
+    pub fn count(&self, kind: LightKind) -> usize {
+        self.counts[kind.index()]
+    }
+
+    /// Derives world positions and directions from the scene and rebuilds the packed array.
+    pub fn update(&mut self, scene: &SceneManager) {
+        for a in 0..self.entity.len() {
+            let entity = self.entity[a];
+
+            let world = if scene.has_transform(entity) {
+                scene.world_matrix(entity)
+            } else {
+                Matrix4::identity()
+            };
+
+            self.position[a] = scene::transform_point(&world, &Vector3::from_element(0.0f32));
+            self.direction[a] = scene::transform_vector(&world, &Vector3::new(0.0f32, 0.0f32, -1.0f32)).normalize();
+        }
+
+        let mut order: Vec<usize> = (0..self.entity.len()).collect();
+        order.sort_by_key(|&a| (self.kind[a], self.entity[a]));
+
+        self.packed.clear();
+        self.packed_entities.clear();
+        self.counts = [0; 3];
+
+        for a in order {
+            self.counts[self.kind[a].index()] += 1;
+            self.packed_entities.push(self.entity[a]);
+            self.packed.push(self.pack(a));
+        }
+    }
+
+    fn pack(&self, instance: usize) -> PackedLight {
+        let (position, direction, color) = (self.position[instance], self.direction[instance], self.color[instance]);
+
+        PackedLight {
+            position_range: [position[0], position[1], position[2], self.range[instance]],
+            direction_kind: [direction[0], direction[1], direction[2], self.kind[instance].index() as f32],
+            color_intensity: [color[0], color[1], color[2], self.intensity[instance]],
+            cone_shadow: [self.inner_angle[instance].cos(),
+                          self.outer_angle[instance].cos(),
+                          if self.shadows[instance] { 1.0f32 } else { 0.0f32 },
+                          0.0f32],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deriving_world_state() {
+        let mut scene = SceneManager::new();
+        let mut manager = LightManager::new();
+        let entity = 3 as Entity;
+
+        scene.create_transform(entity);
+        scene.set_local_position(entity, Vector3::new(1.0f32, 2.0f32, 3.0f32));
+        manager.create_light(entity, LightKind::Point);
+        manager.update(&scene);
+
+        assert_eq!(manager.world_position(entity), Vector3::new(1.0f32, 2.0f32, 3.0f32));
+        assert_eq!(manager.world_direction(entity), Vector3::new(0.0f32, 0.0f32, -1.0f32));
+    }
+
+    #[test]
+    fn packing_lights() {
+        let scene = SceneManager::new();
+        let mut manager = LightManager::new();
+
+        manager.create_light(1, LightKind::Spot);
+        manager.create_light(2, LightKind::Point);
+        manager.create_light(3, LightKind::Directional);
+        manager.create_light(4, LightKind::Point);
+        manager.set_intensity(4, 5.0f32);
+        manager.set_shadows(4, true);
+        manager.update(&scene);
+
+        assert_eq!(manager.packed_entities(), &[3, 2, 4, 1]);
+        assert_eq!(manager.count(LightKind::Point), 2);
+        assert_eq!(manager.packed()[2].color_intensity[3], 5.0f32);
+        assert_eq!(manager.packed()[2].cone_shadow[2], 1.0f32);
+        assert_eq!(manager.packed()[0].direction_kind[3], 0.0f32);
+    }
+}
@@ -1,5 +1,10 @@
 pub mod entity;
 pub mod scene;
+pub mod bounds;
+pub mod spatial;
+pub mod raycast;
+pub mod camera;
+pub mod light;
 
 use nalgebra;
 use self::entity::Entity;
@@ -0,0 +1,363 @@
+use std::cmp::Ordering;
+use nalgebra::{Matrix4, Vector3};
+use system::entity::Entity;
+use system::bounds::{Aabb, BoundsManager, Sphere};
+use system::scene::{self, SceneManager};
+use system::spatial::SpatialIndex;
+
+const EPSILON: f32 = 1.0e-6;
+
+/// Half-line starting at `origin` and extending along the unit vector `direction`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Vector3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+/// Intersection of a ray with a surface, in the space of the ray.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Intersection {
+    pub distance: f32,
+    pub normal: Vector3<f32>,
+}
+
+/// Surface of an entity struck by a ray, in world space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit {
+    pub entity: Entity,
+    pub distance: f32,
+    pub point: Vector3<f32>,
+    pub normal: Vector3<f32>,
+}
+
+/// Indexed triangle list in the local space of an entity.
+#[derive(Clone, Copy, Debug)]
+pub struct TriangleMesh<'a> {
+    pub positions: &'a [Vector3<f32>],
+    pub indices: &'a [u32],
+}
+
+impl Ray {
+    /// Creates a ray, normalizing its direction.
+    pub fn new(origin: Vector3<f32>, direction: Vector3<f32>) -> Ray {
+        Ray {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    /// Builds a world-space ray through a point on screen.
+    ///
+    /// `viewport` is `(x, y, width, height)` in pixels with the origin at the top left, and
+    /// `depth_range` holds the normalized device depths of the near and far planes, which differ
+    /// between clip-space conventions.
+    pub fn from_screen(screen: (f32, f32), viewport: (f32, f32, f32, f32), view: &Matrix4<f32>, projection: &Matrix4<f32>, depth_range: (f32, f32)) -> Option<Ray> {
+        let inverse = match (view * projection).try_inverse() {
+            Some(inverse) => inverse,
+            None => return None
+        };
+
+        let x = 2.0f32 * (screen.0 - viewport.0) / viewport.2 - 1.0f32;
+        let y = 1.0f32 - 2.0f32 * (screen.1 - viewport.1) / viewport.3;
+
+        let unproject = |z: f32| {
+            let mut out = Vector3::from_element(0.0f32);
+            let clip = [x, y, z, 1.0f32];
+            let mut w = 0.0f32;
+
+            for r in 0..4 {
+                for c in 0..3 {
+                    out[c] += clip[r] * inverse[(r, c)];
+                }
+
+                w += clip[r] * inverse[(r, 3)];
+            }
+
+            out / w
+        };
+
+        let near = unproject(depth_range.0);
+        let far = unproject(depth_range.1);
+
+        Some(Ray::new(near, far - near))
+    }
+
+    pub fn at(&self, distance: f32) -> Vector3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    /// Slab test against a box. Rays starting inside the box report a hit at distance zero.
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<Intersection> {
+        let mut near = ::std::f32::NEG_INFINITY;
+        let mut far = ::std::f32::INFINITY;
+        let mut normal = Vector3::from_element(0.0f32);
+
+        for a in 0..3 {
+            if self.direction[a].abs() < EPSILON {
+                if self.origin[a] < aabb.min[a] || self.origin[a] > aabb.max[a] {
+                    return None;
+                }
+
+                continue;
+            }
+
+            let inverse = 1.0f32 / self.direction[a];
+            let mut t0 = (aabb.min[a] - self.origin[a]) * inverse;
+            let mut t1 = (aabb.max[a] - self.origin[a]) * inverse;
+            let mut sign = -1.0f32;
+
+            if t0 > t1 {
+                ::std::mem::swap(&mut t0, &mut t1);
+                sign = 1.0f32;
+            }
+
+            if t0 > near {
+                near = t0;
+                normal = Vector3::from_element(0.0f32);
+                normal[a] = sign;
+            }
+
+            far = far.min(t1);
+
+            if near > far {
+                return None;
+            }
+        }
+
+        if far < 0.0f32 {
+            return None;
+        }
+
+        if near < 0.0f32 {
+            return Some(Intersection { distance: 0.0f32, normal: -self.direction });
+        }
+
+        Some(Intersection { distance: near, normal })
+    }
+
+    pub fn intersect_sphere(&self, sphere: &Sphere) -> Option<Intersection> {
+        let offset = self.origin - sphere.center;
+        let b = offset.dot(&self.direction);
+        let c = offset.norm_squared() - sphere.radius * sphere.radius;
+
+        if c > 0.0f32 && b > 0.0f32 {
+            return None;
+        }
+
+        let discriminant = b * b - c;
+        if discriminant < 0.0f32 {
+            return None;
+        }
+
+        let distance = (-b - discriminant.sqrt()).max(0.0f32);
+        let normal = (self.at(distance) - sphere.center).normalize();
+
+        Some(Intersection { distance, normal })
+    }
+
+    /// Möller-Trumbore intersection against a double-sided triangle.
+    ///
+    /// The returned normal always faces back towards the ray origin.
+    pub fn intersect_triangle(&self, a: &Vector3<f32>, b: &Vector3<f32>, c: &Vector3<f32>) -> Option<Intersection> {
+        let edge1 = *b - *a;
+        let edge2 = *c - *a;
+
+        let p = self.direction.cross(&edge2);
+        let determinant = edge1.dot(&p);
+
+        if determinant.abs() < EPSILON {
+            return None;
+        }
+
+        let inverse = 1.0f32 / determinant;
+        let s = self.origin - *a;
+        let u = s.dot(&p) * inverse;
+
+        if u < 0.0f32 || u > 1.0f32 {
+            return None;
+        }
+
+        let q = s.cross(&edge1);
+        let v = self.direction.dot(&q) * inverse;
+
+        if v < 0.0f32 || u + v > 1.0f32 {
+            return None;
+        }
+
+        let distance = edge2.dot(&q) * inverse;
+        if distance < 0.0f32 {
+            return None;
+        }
+
+        let mut normal = edge1.cross(&edge2).normalize();
+        if normal.dot(&self.direction) > 0.0f32 {
+            normal = -normal;
+        }
+
+        Some(Intersection { distance, normal })
+    }
+
+    /// Closest intersection with a triangle mesh placed by a scene world matrix.
+    ///
+    /// The ray is carried into the mesh's local space without renormalizing, so local ray
+    /// parameters equal world distances.
+    pub fn intersect_mesh(&self, world: &Matrix4<f32>, mesh: &TriangleMesh) -> Option<Intersection> {
+        let inverse = match world.try_inverse() {
+            Some(inverse) => inverse,
+            None => return None
+        };
+
+        let local = Ray {
+            origin: scene::transform_point(&inverse, &self.origin),
+            direction: scene::transform_vector(&inverse, &self.direction),
+        };
+
+        let mut closest: Option<Intersection> = None;
+
+        for triangle in mesh.indices.chunks(3) {
+            if triangle.len() < 3 {
+                break;
+            }
+
+            let (a, b, c) = (&mesh.positions[triangle[0] as usize],
+                             &mesh.positions[triangle[1] as usize],
+                             &mesh.positions[triangle[2] as usize]);
+
+            if let Some(hit) = local.intersect_triangle(a, b, c) {
+                if closest.map_or(true, |closest| hit.distance < closest.distance) {
+                    closest = Some(hit);
+                }
+            }
+        }
+
+        // Normals transform by the inverse transpose to stay perpendicular under scale.
+        closest.map(|hit| Intersection {
+            distance: hit.distance,
+            normal: scene::transform_vector(&inverse.transpose(), &hit.normal).normalize(),
+        })
+    }
+}
+
+/// Casts a ray against entity world bounds, collecting hits sorted nearest first.
+pub fn raycast(ray: &Ray, max_distance: f32, index: &SpatialIndex, bounds: &BoundsManager, hits: &mut Vec<Hit>) {
+    raycast_meshes(ray, max_distance, index, bounds, None, |_| None, hits);
+}
+
+/// Casts a ray against entity triangle meshes, collecting hits sorted nearest first.
+///
+/// Candidates are gathered from the spatial index using their world bounds. `meshes` supplies the
+/// local-space mesh of a candidate; entities without a mesh are reported at their bounds.
+pub fn raycast_meshes<'a, F>(ray: &Ray, max_distance: f32, index: &SpatialIndex, bounds: &BoundsManager, scene: Option<&SceneManager>, mut meshes: F, hits: &mut Vec<Hit>)
+    where F: FnMut(Entity) -> Option<TriangleMesh<'a>> {
+
+    let mut candidates = Vec::new();
+    index.query(|aabb| ray.intersect_aabb(aabb).map_or(false, |hit| hit.distance <= max_distance), &mut candidates);
+
+    let start = hits.len();
+
+    for entity in candidates {
+        let mesh = match scene {
+            Some(scene) if scene.has_transform(entity) => meshes(entity).map(|mesh| (scene.world_matrix(entity), mesh)),
+            _ => None
+        };
+
+        let intersection = match mesh {
+            Some((world, mesh)) => ray.intersect_mesh(&world, &mesh),
+            None => ray.intersect_aabb(&bounds.world_aabb(entity))
+        };
+
+        if let Some(intersection) = intersection {
+            if intersection.distance <= max_distance {
+                hits.push(Hit {
+                    entity,
+                    distance: intersection.distance,
+                    point: ray.at(intersection.distance),
+                    normal: intersection.normal,
+                });
+            }
+        }
+    }
+
+    hits[start..].sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap_or(Ordering::Equal));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box_at(x: f32) -> Aabb {
+        Aabb::from_center(Vector3::new(x, 0.0f32, 0.0f32), Vector3::from_element(0.5f32))
+    }
+
+    #[test]
+    fn intersecting_boxes() {
+        let ray = Ray::new(Vector3::new(-5.0f32, 0.0f32, 0.0f32), Vector3::new(1.0f32, 0.0f32, 0.0f32));
+        let hit = ray.intersect_aabb(&unit_box_at(0.0f32)).unwrap();
+
+        assert_eq!(hit.distance, 4.5f32);
+        assert_eq!(hit.normal, Vector3::new(-1.0f32, 0.0f32, 0.0f32));
+        assert!(ray.intersect_aabb(&unit_box_at(-10.0f32)).is_none());
+    }
+
+    #[test]
+    fn intersecting_triangles() {
+        let ray = Ray::new(Vector3::new(0.25f32, 0.25f32, 5.0f32), Vector3::new(0.0f32, 0.0f32, -1.0f32));
+        let hit = ray.intersect_triangle(&Vector3::new(0.0f32, 0.0f32, 0.0f32),
+                                         &Vector3::new(1.0f32, 0.0f32, 0.0f32),
+                                         &Vector3::new(0.0f32, 1.0f32, 0.0f32)).unwrap();
+
+        assert_eq!(hit.distance, 5.0f32);
+        assert_eq!(hit.normal, Vector3::new(0.0f32, 0.0f32, 1.0f32));
+    }
+
+    #[test]
+    fn intersecting_placed_meshes() {
+        let positions = [Vector3::new(0.0f32, 0.0f32, 0.0f32),
+                         Vector3::new(1.0f32, 0.0f32, 0.0f32),
+                         Vector3::new(0.0f32, 1.0f32, 0.0f32)];
+        let indices = [0u32, 1, 2];
+        let mesh = TriangleMesh { positions: &positions, indices: &indices };
+
+        let mut world = Matrix4::identity();
+        world[(3, 2)] = -2.0f32;
+
+        let ray = Ray::new(Vector3::new(0.25f32, 0.25f32, 5.0f32), Vector3::new(0.0f32, 0.0f32, -1.0f32));
+        let hit = ray.intersect_mesh(&world, &mesh).unwrap();
+
+        assert_eq!(hit.distance, 7.0f32);
+    }
+
+    #[test]
+    fn casting_through_scene() {
+        let mut index = SpatialIndex::new();
+        let mut bounds = BoundsManager::new();
+        let mut scene = SceneManager::new();
+
+        for a in 0..4 {
+            let entity = a as Entity;
+            scene.create_transform(entity);
+            scene.set_local_position(entity, Vector3::new(a as f32 * 3.0f32, 0.0f32, 0.0f32));
+            bounds.create_bounds(entity, unit_box_at(0.0f32));
+        }
+
+        bounds.update(&mut scene);
+        index.update(&bounds);
+
+        let ray = Ray::new(Vector3::new(20.0f32, 0.0f32, 0.0f32), Vector3::new(-1.0f32, 0.0f32, 0.0f32));
+        let mut hits = Vec::new();
+        raycast(&ray, 15.0f32, &index, &bounds, &mut hits);
+
+        let entities: Vec<Entity> = hits.iter().map(|hit| hit.entity).collect();
+        assert_eq!(entities, vec![3, 2]);
+        assert_eq!(hits[0].point, Vector3::new(9.5f32, 0.0f32, 0.0f32));
+    }
+
+    #[test]
+    fn unprojecting_screen_points() {
+        let identity = Matrix4::identity();
+        let ray = Ray::from_screen((50.0f32, 50.0f32), (0.0f32, 0.0f32, 100.0f32, 100.0f32), &identity, &identity, (-1.0f32, 1.0f32)).unwrap();
+
+        assert_eq!(ray.origin, Vector3::new(0.0f32, 0.0f32, -1.0f32));
+        assert_eq!(ray.direction, Vector3::new(0.0f32, 0.0f32, 1.0f32));
+    }
+}
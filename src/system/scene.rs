@@ -1,11 +1,122 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, VecDeque};
 use nalgebra::{Matrix4, Vector3, UnitQuaternion, U1, U3, Rotation};
 use system::entity::Entity;
 
 pub type Transform = u32;
 
+/// Handle identifying a consumer of transform changes.
+///
+/// Slots are reused once a consumer unsubscribes, so a handle also carries the generation of the
+/// slot it was issued for. Stale handles are rejected rather than reading a newer consumer's
+/// cursor.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Subscriber {
+    slot: usize,
+    generation: u32,
+}
+
+/// Position of a consumer in the change history, or `None` while its slot is free.
+struct Cursor {
+    generation: u32,
+    version: Option<u64>,
+}
+
+/// Lifecycle notification delivered to change subscribers.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TransformEvent {
+    Created(Entity),
+    Destroyed(Entity),
+}
+
 type Quaternion<F> = UnitQuaternion<F>;
 
+/// Transforms a point by a matrix laid out like the scene's world matrices.
+///
+/// Scene matrices keep translation in the bottom row and are applied to row vectors, so the
+/// point is treated as `[x, y, z, 1] * matrix`.
+pub fn transform_point(matrix: &Matrix4<f32>, point: &Vector3<f32>) -> Vector3<f32> {
+    let mut out = Vector3::new(matrix[(3, 0)], matrix[(3, 1)], matrix[(3, 2)]);
+
+    for c in 0..3 {
+        for r in 0..3 {
+            out[c] += point[r] * matrix[(r, c)];
+        }
+    }
+
+    out
+}
+
+/// Transforms a direction by a matrix laid out like the scene's world matrices, ignoring the
+/// translation row.
+pub fn transform_vector(matrix: &Matrix4<f32>, vector: &Vector3<f32>) -> Vector3<f32> {
+    let mut out = Vector3::from_element(0.0f32);
+
+    for c in 0..3 {
+        for r in 0..3 {
+            out[c] += vector[r] * matrix[(r, c)];
+        }
+    }
+
+    out
+}
+
+/// World-space translation, rotation and scale of a transform.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pose {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Pose {
+    /// Splits a world matrix into its translation, rotation and scale components.
+    pub fn from_matrix(matrix: &Matrix4<f32>) -> Pose {
+        let mut basis = matrix.fixed_slice::<U3, U3>(0, 0).clone_owned();
+
+        let scale = Vector3::new(basis.column(0).norm(),
+                                 basis.column(1).norm(),
+                                 basis.column(2).norm());
+
+        for c in 0..3 {
+            for r in 0..3 {
+                basis[(r, c)] /= scale[c];
+            }
+        }
+
+        Pose {
+            translation: Vector3::new(matrix[(3, 0)], matrix[(3, 1)], matrix[(3, 2)]),
+            rotation: Quaternion::from_rotation_matrix(&Rotation::from_matrix_unchecked(basis)),
+            scale,
+        }
+    }
+
+    /// Recombines the pose into a world matrix laid out like those held by the scene manager.
+    pub fn to_matrix(&self) -> Matrix4<f32> {
+        let mut matrix = Matrix4::identity();
+        let rotation = self.rotation.to_rotation_matrix();
+        let basis = rotation.matrix();
+
+        for c in 0..3 {
+            for r in 0..3 {
+                matrix[(r, c)] = basis[(r, c)] * self.scale[c];
+            }
+
+            matrix[(3, c)] = self.translation[c];
+        }
+
+        matrix
+    }
+
+    /// Blends between two poses, spherically interpolating the rotation.
+    pub fn interpolate(&self, other: &Pose, alpha: f32) -> Pose {
+        Pose {
+            translation: self.translation + (other.translation - self.translation) * alpha,
+            rotation: self.rotation.slerp(&other.rotation, alpha),
+            scale: self.scale + (other.scale - self.scale) * alpha,
+        }
+    }
+}
+
 pub struct SceneManager {
     transforms: BTreeMap<Entity, Transform>,
 
@@ -17,7 +128,19 @@ pub struct SceneManager {
     child: Vec<Transform>,
     last_sibling: Vec<Transform>,
     next_sibling: Vec<Transform>,
-    dirty: Vec<bool>,
+    changed: Vec<u64>,
+
+    // Change tracking state.
+    version: u64,
+    reset_version: u64,
+    cursors: Vec<Cursor>,
+    events: VecDeque<(u64, TransformEvent)>,
+
+    // Interpolation state, only populated while interpolation is enabled.
+    interpolating: bool,
+    snapshot_version: u64,
+    previous: Vec<Pose>,
+    teleported: Vec<bool>,
 }
 
 impl SceneManager {
@@ -31,7 +154,15 @@ impl SceneManager {
             child: Vec::new(),
             last_sibling: Vec::new(),
             next_sibling: Vec::new(),
-            dirty: Vec::new(),
+            changed: Vec::new(),
+            version: 0,
+            reset_version: 0,
+            cursors: Vec::new(),
+            events: VecDeque::new(),
+            interpolating: false,
+            snapshot_version: 0,
+            previous: Vec::new(),
+            teleported: Vec::new(),
         }
     }
 
@@ -45,7 +176,15 @@ impl SceneManager {
         self.child.push(Transform::max_value());
         self.last_sibling.push(Transform::max_value());
         self.next_sibling.push(Transform::max_value());
-        self.dirty.push(false);
+
+        if self.interpolating {
+            self.previous.push(Pose::from_matrix(&Matrix4::identity()));
+            self.teleported.push(true);
+        }
+
+        self.version += 1;
+        self.changed.push(self.version);
+        self.record(TransformEvent::Created(entity));
 
         // FIXME: Use returned value to guard against orphaned transforms?
         self.transforms.insert(entity, next);
@@ -62,6 +201,25 @@ impl SceneManager {
         let entity = self.entity[target];
         let last_entity = self.entity[last];
 
+        self.unlink(entity);
+        self.version += 1;
+
+        // Children become roots that stay where they are in the world.
+        let mut child = self.child[target];
+        while child != Transform::max_value() {
+            let next = self.next_sibling[child as usize];
+
+            self.local[child as usize] = self.world[child as usize];
+            self.parent[child as usize] = Transform::max_value();
+            self.last_sibling[child as usize] = Transform::max_value();
+            self.next_sibling[child as usize] = Transform::max_value();
+            self.changed[child as usize] = self.version;
+
+            child = next;
+        }
+
+        self.child[target] = Transform::max_value();
+
         self.entity.swap_remove(target);
         self.local.swap_remove(target);
         self.world.swap_remove(target);
@@ -69,10 +227,30 @@ impl SceneManager {
         self.child.swap_remove(target);
         self.last_sibling.swap_remove(target);
         self.next_sibling.swap_remove(target);
-        self.dirty.swap_remove(target);
+        self.changed.swap_remove(target);
+
+        if self.interpolating {
+            self.previous.swap_remove(target);
+            self.teleported.swap_remove(target);
+        }
+
+        // Links to the transform moved into the freed slot follow it there.
+        if target != last {
+            let moved = last as Transform;
+
+            for links in [&mut self.parent, &mut self.child, &mut self.last_sibling, &mut self.next_sibling].iter_mut() {
+                for link in links.iter_mut() {
+                    if *link == moved {
+                        *link = transform;
+                    }
+                }
+            }
+        }
 
         self.transforms.insert(last_entity, transform);
         self.transforms.remove(&entity);
+
+        self.record(TransformEvent::Destroyed(entity));
     }
 
     pub fn has_transform(&self, entity: Entity) -> bool {
@@ -183,6 +361,9 @@ impl SceneManager {
             slice[1] = position[1];
             slice[2] = position[2];
         }
+
+        self.version += 1;
+        self.changed[instance as usize] = self.version;
     }
 
     pub fn world_position(&self, entity: Entity) -> Vector3<f32> {
@@ -193,6 +374,12 @@ impl SceneManager {
         Vector3::new(slice[0], slice[1], slice[2])
     }
 
+    /// Full world matrix of an entity's transform.
+    pub fn world_matrix(&self, entity: Entity) -> Matrix4<f32> {
+        let instance = self.transform_for(entity) as usize;
+        self.world[instance]
+    }
+
     pub fn set_world_rotation(&mut self, entity: Entity, rotation: Quaternion<f32>) {
         let instance = self.transform_for(entity);
 
@@ -208,6 +395,9 @@ impl SceneManager {
                 slice[a] = rot_slice[a];
             }
         }
+
+        self.version += 1;
+        self.changed[instance as usize] = self.version;
     }
 
     pub fn world_rotation(&self, entity: Entity) -> Quaternion<f32> {
@@ -270,6 +460,7 @@ impl SceneManager {
         self.local[child as usize] = relative;
         self.parent[child as usize] = parent;
 
+        self.version += 1;
         self.transform(child, parent_world);
     }
 
@@ -280,6 +471,16 @@ impl SceneManager {
             return
         }
 
+        // Former ancestors are reported as changed so that consumers keeping hierarchical data,
+        // such as enclosing bounds, notice the descendant they lost.
+        self.version += 1;
+
+        let mut ancestor = self.parent[instance];
+        while ancestor != Transform::max_value() {
+            self.changed[ancestor as usize] = self.version;
+            ancestor = self.parent[ancestor as usize];
+        }
+
         if self.last_sibling[instance] == Transform::max_value() {
             self.child[self.parent[instance] as usize] = self.next_sibling[instance]
         } else {
@@ -295,6 +496,82 @@ impl SceneManager {
         self.last_sibling[instance] = Transform::max_value();
     }
 
+    /// Returns the parent of an entity, if it has one.
+    pub fn parent(&self, entity: Entity) -> Option<Entity> {
+        let instance = self.transform_for(entity) as usize;
+
+        match self.parent[instance] {
+            parent if parent == Transform::max_value() => None,
+            parent => Some(self.entity[parent as usize])
+        }
+    }
+
+    /// Iterates over the immediate children of an entity in link order.
+    pub fn children(&self, entity: Entity) -> Children {
+        let instance = self.transform_for(entity) as usize;
+
+        Children {
+            manager: self,
+            current: self.child[instance],
+        }
+    }
+
+    /// Iterates over the ancestors of an entity, starting with its parent and ending at the root.
+    pub fn ancestors(&self, entity: Entity) -> Ancestors {
+        let instance = self.transform_for(entity) as usize;
+
+        Ancestors {
+            manager: self,
+            current: self.parent[instance],
+        }
+    }
+
+    /// Iterates over all descendants of an entity in depth-first (pre-order) order.
+    pub fn descendants_depth_first(&self, entity: Entity) -> DepthFirst {
+        let instance = self.transform_for(entity);
+
+        DepthFirst {
+            manager: self,
+            root: instance,
+            current: instance,
+        }
+    }
+
+    /// Iterates over all descendants of an entity in breadth-first order.
+    pub fn descendants_breadth_first(&self, entity: Entity) -> BreadthFirst {
+        let instance = self.transform_for(entity) as usize;
+
+        let mut queue = VecDeque::new();
+        let mut child = self.child[instance];
+        while child != Transform::max_value() {
+            queue.push_back(child);
+            child = self.next_sibling[child as usize];
+        }
+
+        BreadthFirst {
+            manager: self,
+            queue,
+        }
+    }
+
+    /// Iterates over every entity whose transform has no parent.
+    pub fn roots(&self) -> Roots {
+        Roots {
+            manager: self,
+            current: 0,
+        }
+    }
+
+    /// Number of links between an entity and the root of its hierarchy.
+    pub fn depth(&self, entity: Entity) -> usize {
+        self.ancestors(entity).count()
+    }
+
+    /// Checks whether `ancestor` appears anywhere above `descendant` in the hierarchy.
+    pub fn is_ancestor_of(&self, ancestor: Entity, descendant: Entity) -> bool {
+        self.ancestors(descendant).any(|entity| entity == ancestor)
+    }
+
     /// Applies parent transform to local to renormalize.
     pub fn apply(&mut self, transform: Transform) {
         let parent = self.parent[transform as usize];
@@ -304,14 +581,18 @@ impl SceneManager {
             self.world[parent as usize]
         };
 
+        self.version += 1;
         self.transform(transform, world);
-        self.dirty[transform as usize] = true
     }
 
     /// Applies a given transformation matrix to the local transform.
+    ///
+    /// The transform and every descendant it propagates to are stamped with the current change
+    /// version so that subscribers observe the updated world matrices.
     pub fn transform(&mut self, transform: Transform, trans_mat: Matrix4<f32>) {
         let updated = self.local[transform as usize] * trans_mat;
         self.world[transform as usize] = updated;
+        self.changed[transform as usize] = self.version;
 
         let mut child = self.child[transform as usize];
         while child < Transform::max_value() {
@@ -321,21 +602,322 @@ impl SceneManager {
     }
 
     /// Resets the dirtiness of all transforms.
+    ///
+    /// This only affects `dirty`; subscribers registered through `subscribe` track their own
+    /// position in the change history and are not disturbed.
     pub fn reset(&mut self) {
-        for a in 0..self.entity.len() {
-            self.dirty[a as usize] = false;
-        }
+        self.reset_version = self.version;
     }
 
     /// Builds vectors of entities that have been modified since last reset and their transforms.
+    ///
+    /// This reads the same change history as subscribers, so besides transforms moved through
+    /// the local setters it also reports newly created transforms, those moved through the world
+    /// setters and every descendant a change propagated to.
     pub fn dirty(&self, entities: &mut Vec<Entity>, transforms: &mut Vec<Matrix4<f32>>) {
+        self.collect_since(self.reset_version, entities, transforms);
+    }
+
+    /// Registers a new consumer of transform changes.
+    ///
+    /// The subscriber starts at the current change version and will only observe modifications,
+    /// creations and destructions that happen after this call.
+    pub fn subscribe(&mut self) -> Subscriber {
+        let version = self.version;
+        let free = self.cursors.iter().position(|cursor| cursor.version.is_none());
+
+        match free {
+            Some(slot) => {
+                let cursor = &mut self.cursors[slot];
+                cursor.generation = cursor.generation.wrapping_add(1);
+                cursor.version = Some(version);
+
+                Subscriber { slot, generation: cursor.generation }
+            },
+            None => {
+                self.cursors.push(Cursor { generation: 0, version: Some(version) });
+                Subscriber { slot: self.cursors.len() - 1, generation: 0 }
+            }
+        }
+    }
+
+    /// Removes a consumer, releasing any lifecycle events it was holding back.
+    pub fn unsubscribe(&mut self, subscriber: Subscriber) {
+        self.cursor(subscriber);
+        self.cursors[subscriber.slot].version = None;
+        self.prune();
+    }
+
+    /// Builds vectors of entities modified since the subscriber last acknowledged and their
+    /// world transforms.
+    pub fn changed_since(&self, subscriber: Subscriber, entities: &mut Vec<Entity>, transforms: &mut Vec<Matrix4<f32>>) {
+        let cursor = self.cursor(subscriber);
+        self.collect_since(cursor, entities, transforms);
+    }
+
+    /// Builds the list of transforms created or destroyed since the subscriber last acknowledged,
+    /// in the order they occurred.
+    pub fn events_since(&self, subscriber: Subscriber, events: &mut Vec<TransformEvent>) {
+        let cursor = self.cursor(subscriber);
+
+        for &(version, event) in self.events.iter() {
+            if version > cursor {
+                events.push(event);
+            }
+        }
+    }
+
+    /// Marks everything up to the current version as seen by the subscriber.
+    pub fn acknowledge(&mut self, subscriber: Subscriber) {
+        self.cursor(subscriber);
+        self.cursors[subscriber.slot].version = Some(self.version);
+        self.prune();
+    }
+
+    /// Enables or disables retention of the previous tick's world poses.
+    ///
+    /// Enabling interpolation seeds the previous poses from the current world matrices, so the
+    /// first interpolated frame renders the current state.
+    pub fn set_interpolation(&mut self, enabled: bool) {
+        self.interpolating = enabled;
+        self.previous.clear();
+        self.teleported.clear();
+
+        if enabled {
+            for a in 0..self.entity.len() {
+                self.previous.push(Pose::from_matrix(&self.world[a]));
+                self.teleported.push(false);
+            }
+
+            self.snapshot_version = self.version;
+        }
+    }
+
+    /// Records the current world poses as the previous tick's state.
+    ///
+    /// This should be called once at the start of every fixed simulation step, before any
+    /// transforms are modified. Teleport flags are cleared.
+    pub fn snapshot(&mut self) {
+        if !self.interpolating {
+            return
+        }
+
+        for a in 0..self.entity.len() {
+            if self.changed[a] > self.snapshot_version || self.teleported[a] {
+                self.previous[a] = Pose::from_matrix(&self.world[a]);
+            }
+
+            self.teleported[a] = false;
+        }
+
+        self.snapshot_version = self.version;
+    }
+
+    /// Flags an entity as having jumped this tick so that it renders at its current pose instead
+    /// of being blended from the previous one.
+    pub fn teleport(&mut self, entity: Entity) {
+        let instance = self.transform_for(entity) as usize;
+
+        if self.interpolating {
+            self.teleported[instance] = true;
+        }
+    }
+
+    /// World matrix of an entity blended between the previous and current tick.
+    ///
+    /// An `alpha` of zero yields the previous pose and one the current pose. If interpolation is
+    /// disabled or the entity was teleported, the current world matrix is returned unchanged.
+    pub fn interpolated_world(&self, entity: Entity, alpha: f32) -> Matrix4<f32> {
+        let instance = self.transform_for(entity) as usize;
+        self.interpolate_instance(instance, alpha)
+    }
+
+    /// Builds vectors of entities that changed during the last simulated tick and their
+    /// interpolated world transforms.
+    pub fn interpolated(&self, alpha: f32, entities: &mut Vec<Entity>, transforms: &mut Vec<Matrix4<f32>>) {
+        for a in 0..self.entity.len() {
+            if self.changed[a] > self.snapshot_version || (self.interpolating && self.teleported[a]) {
+                entities.push(self.entity[a]);
+                transforms.push(self.interpolate_instance(a, alpha));
+            }
+        }
+    }
+
+    fn interpolate_instance(&self, instance: usize, alpha: f32) -> Matrix4<f32> {
+        if !self.interpolating || self.teleported[instance] || self.changed[instance] <= self.snapshot_version {
+            return self.world[instance];
+        }
+
+        let current = Pose::from_matrix(&self.world[instance]);
+        self.previous[instance].interpolate(&current, alpha).to_matrix()
+    }
+
+    fn cursor(&self, subscriber: Subscriber) -> u64 {
+        match self.cursors.get(subscriber.slot) {
+            Some(&Cursor { generation, version: Some(version) }) if generation == subscriber.generation => version,
+            _ => panic!("subscriber {:?} is not registered", subscriber)
+        }
+    }
+
+    fn collect_since(&self, version: u64, entities: &mut Vec<Entity>, transforms: &mut Vec<Matrix4<f32>>) {
         for a in 0..self.entity.len() {
-            if self.dirty[a as usize] {
-                entities.push(self.entity[a as usize]);
-                transforms.push(self.world[a as usize]);
+            if self.changed[a] > version {
+                entities.push(self.entity[a]);
+                transforms.push(self.world[a]);
             }
         }
     }
+
+    fn record(&mut self, event: TransformEvent) {
+        if self.cursors.iter().any(|cursor| cursor.version.is_some()) {
+            self.events.push_back((self.version, event));
+        }
+    }
+
+    /// Drops lifecycle events that every subscriber has already acknowledged.
+    fn prune(&mut self) {
+        let oldest = self.cursors.iter()
+            .filter_map(|cursor| cursor.version)
+            .min()
+            .unwrap_or(self.version);
+
+        while let Some(&(version, _)) = self.events.front() {
+            if version > oldest {
+                break;
+            }
+
+            self.events.pop_front();
+        }
+    }
+}
+
+/// Iterator over the immediate children of a transform.
+pub struct Children<'a> {
+    manager: &'a SceneManager,
+    current: Transform,
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = Entity;
+
+    fn next(&mut self) -> Option<Entity> {
+        if self.current == Transform::max_value() {
+            return None
+        }
+
+        let instance = self.current as usize;
+        self.current = self.manager.next_sibling[instance];
+
+        Some(self.manager.entity[instance])
+    }
+}
+
+/// Iterator walking from a transform up to the root of its hierarchy.
+pub struct Ancestors<'a> {
+    manager: &'a SceneManager,
+    current: Transform,
+}
+
+impl<'a> Iterator for Ancestors<'a> {
+    type Item = Entity;
+
+    fn next(&mut self) -> Option<Entity> {
+        if self.current == Transform::max_value() {
+            return None
+        }
+
+        let instance = self.current as usize;
+        self.current = self.manager.parent[instance];
+
+        Some(self.manager.entity[instance])
+    }
+}
+
+/// Pre-order iterator over the descendants of a transform.
+///
+/// Walks the child and sibling links directly so no traversal stack needs to be allocated.
+pub struct DepthFirst<'a> {
+    manager: &'a SceneManager,
+    root: Transform,
+    current: Transform,
+}
+
+impl<'a> Iterator for DepthFirst<'a> {
+    type Item = Entity;
+
+    fn next(&mut self) -> Option<Entity> {
+        if self.current == Transform::max_value() {
+            return None
+        }
+
+        let child = self.manager.child[self.current as usize];
+        if child != Transform::max_value() {
+            self.current = child;
+            return Some(self.manager.entity[child as usize]);
+        }
+
+        let mut current = self.current;
+        while current != self.root {
+            let sibling = self.manager.next_sibling[current as usize];
+            if sibling != Transform::max_value() {
+                self.current = sibling;
+                return Some(self.manager.entity[sibling as usize]);
+            }
+
+            current = self.manager.parent[current as usize];
+        }
+
+        self.current = Transform::max_value();
+        None
+    }
+}
+
+/// Level-order iterator over the descendants of a transform.
+pub struct BreadthFirst<'a> {
+    manager: &'a SceneManager,
+    queue: VecDeque<Transform>,
+}
+
+impl<'a> Iterator for BreadthFirst<'a> {
+    type Item = Entity;
+
+    fn next(&mut self) -> Option<Entity> {
+        let instance = match self.queue.pop_front() {
+            Some(instance) => instance as usize,
+            None => return None
+        };
+
+        let mut child = self.manager.child[instance];
+        while child != Transform::max_value() {
+            self.queue.push_back(child);
+            child = self.manager.next_sibling[child as usize];
+        }
+
+        Some(self.manager.entity[instance])
+    }
+}
+
+/// Iterator over all transforms without a parent, in instance order.
+pub struct Roots<'a> {
+    manager: &'a SceneManager,
+    current: usize,
+}
+
+impl<'a> Iterator for Roots<'a> {
+    type Item = Entity;
+
+    fn next(&mut self) -> Option<Entity> {
+        while self.current < self.manager.entity.len() {
+            let instance = self.current;
+            self.current += 1;
+
+            if self.manager.parent[instance] == Transform::max_value() {
+                return Some(self.manager.entity[instance]);
+            }
+        }
+
+        None
+    }
 }
 
 #[cfg(test)]
@@ -483,6 +1065,14 @@ mod tests {
         assert_eq!(entities.len(), 1);
         assert_eq!(transforms.len(), 1);
         assert_eq!(entities[0], entity);
+
+        manager.reset();
+        manager.create_transform(4 as Entity);
+        entities.clear();
+        transforms.clear();
+
+        manager.dirty(&mut entities, &mut transforms);
+        assert_eq!(entities, vec![4 as Entity]);
     }
 
     #[test]
@@ -504,4 +1094,217 @@ mod tests {
         assert_eq!(entities.len(), 0);
         assert_eq!(transforms.len(), 0);
     }
+
+    fn build_hierarchy(manager: &mut SceneManager) {
+        // 1 -> (2 -> (4, 5), 3 -> (6)), 7
+        for entity in 1..8 {
+            manager.create_transform(entity as Entity);
+        }
+
+        manager.link(2, 1);
+        manager.link(3, 1);
+        manager.link(4, 2);
+        manager.link(5, 2);
+        manager.link(6, 3);
+    }
+
+    #[test]
+    fn iterating_children() {
+        let mut manager = SceneManager::new();
+        build_hierarchy(&mut manager);
+
+        assert_eq!(manager.children(1).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(manager.children(2).collect::<Vec<_>>(), vec![4, 5]);
+        assert_eq!(manager.children(7).count(), 0);
+        assert_eq!(manager.parent(4), Some(2));
+        assert_eq!(manager.parent(1), None);
+    }
+
+    #[test]
+    fn iterating_descendants() {
+        let mut manager = SceneManager::new();
+        build_hierarchy(&mut manager);
+
+        assert_eq!(manager.descendants_depth_first(1).collect::<Vec<_>>(), vec![2, 4, 5, 3, 6]);
+        assert_eq!(manager.descendants_breadth_first(1).collect::<Vec<_>>(), vec![2, 3, 4, 5, 6]);
+        assert_eq!(manager.descendants_depth_first(2).collect::<Vec<_>>(), vec![4, 5]);
+        assert_eq!(manager.descendants_depth_first(7).count(), 0);
+    }
+
+    #[test]
+    fn iterating_ancestors() {
+        let mut manager = SceneManager::new();
+        build_hierarchy(&mut manager);
+
+        assert_eq!(manager.ancestors(6).collect::<Vec<_>>(), vec![3, 1]);
+        assert_eq!(manager.roots().collect::<Vec<_>>(), vec![1, 7]);
+        assert_eq!(manager.depth(5), 2);
+        assert_eq!(manager.depth(1), 0);
+        assert!(manager.is_ancestor_of(1, 5));
+        assert!(!manager.is_ancestor_of(3, 5));
+    }
+
+    #[test]
+    fn destroying_linked_transforms() {
+        let mut manager = SceneManager::new();
+        build_hierarchy(&mut manager);
+
+        manager.set_local_position(1, Vector3::new(1.0f32, 0.0f32, 0.0f32));
+        manager.set_local_position(2, Vector3::new(0.0f32, 2.0f32, 0.0f32));
+
+        // Destroying 2 moves 7 into its slot and orphans 4 and 5 where they are.
+        let transform = manager.transform_for(2);
+        manager.destroy_transform(transform);
+
+        assert_eq!(manager.ancestors(4).count(), 0);
+        assert_eq!(manager.depth(5), 0);
+        assert_eq!(manager.world_position(4), Vector3::new(1.0f32, 2.0f32, 0.0f32));
+        assert_eq!(manager.children(1).collect::<Vec<_>>(), vec![3]);
+        assert_eq!(manager.roots().collect::<Vec<_>>(), vec![1, 7, 4, 5]);
+
+        // Destroying 4 moves 6 into its slot, so the links to 6 must follow.
+        let transform = manager.transform_for(4);
+        manager.destroy_transform(transform);
+
+        assert_eq!(manager.children(3).collect::<Vec<_>>(), vec![6]);
+        assert_eq!(manager.ancestors(6).collect::<Vec<_>>(), vec![3, 1]);
+        assert!(manager.is_ancestor_of(1, 6));
+
+        manager.set_local_position(1, Vector3::new(0.0f32, 0.0f32, 4.0f32));
+
+        assert_eq!(manager.world_position(6), Vector3::new(0.0f32, 0.0f32, 4.0f32));
+        assert_eq!(manager.world_position(5), Vector3::new(1.0f32, 2.0f32, 0.0f32));
+    }
+
+    #[test]
+    fn subscribing_to_changes() {
+        let mut manager = SceneManager::new();
+        let entity = 3 as Entity;
+
+        manager.create_transform(entity);
+
+        let renderer = manager.subscribe();
+        let physics = manager.subscribe();
+
+        manager.set_local_position(entity, Vector3::new(1.0f32, 2.0f32, 3.0f32));
+
+        let mut entities = Vec::new();
+        let mut transforms = Vec::new();
+
+        manager.changed_since(renderer, &mut entities, &mut transforms);
+        assert_eq!(entities, vec![entity]);
+
+        manager.acknowledge(renderer);
+        entities.clear();
+        transforms.clear();
+
+        manager.changed_since(renderer, &mut entities, &mut transforms);
+        assert_eq!(entities.len(), 0);
+
+        manager.changed_since(physics, &mut entities, &mut transforms);
+        assert_eq!(entities, vec![entity]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn rejecting_stale_subscribers() {
+        let mut manager = SceneManager::new();
+
+        let stale = manager.subscribe();
+        manager.unsubscribe(stale);
+
+        let current = manager.subscribe();
+        assert!(current != stale);
+
+        let mut events = Vec::new();
+        manager.events_since(stale, &mut events);
+    }
+
+    #[test]
+    fn propagating_changes_to_children() {
+        let mut manager = SceneManager::new();
+        let parent = 3 as Entity;
+        let child = 5 as Entity;
+
+        manager.create_transform(parent);
+        manager.create_transform(child);
+        manager.link(child, parent);
+
+        let subscriber = manager.subscribe();
+        manager.set_local_position(parent, Vector3::new(3.0f32, 5.0f32, 0.5f32));
+
+        let mut entities = Vec::new();
+        let mut transforms = Vec::new();
+        manager.changed_since(subscriber, &mut entities, &mut transforms);
+
+        assert_eq!(entities, vec![parent, child]);
+    }
+
+    #[test]
+    fn observing_lifecycle_events() {
+        let mut manager = SceneManager::new();
+        let subscriber = manager.subscribe();
+
+        let transform = manager.create_transform(3);
+        manager.destroy_transform(transform);
+
+        let mut events = Vec::new();
+        manager.events_since(subscriber, &mut events);
+        assert_eq!(events, vec![TransformEvent::Created(3), TransformEvent::Destroyed(3)]);
+
+        manager.acknowledge(subscriber);
+        events.clear();
+
+        manager.events_since(subscriber, &mut events);
+        assert_eq!(events.len(), 0);
+    }
+
+    #[test]
+    fn interpolating_transforms() {
+        let mut manager = SceneManager::new();
+        let entity = 3 as Entity;
+
+        manager.create_transform(entity);
+        manager.set_interpolation(true);
+
+        manager.snapshot();
+        manager.set_local_position(entity, Vector3::new(2.0f32, 4.0f32, 0.0f32));
+
+        let halfway = manager.interpolated_world(entity, 0.5f32);
+        assert_eq!(Pose::from_matrix(&halfway).translation, Vector3::new(1.0f32, 2.0f32, 0.0f32));
+
+        let mut entities = Vec::new();
+        let mut transforms = Vec::new();
+        manager.interpolated(0.5f32, &mut entities, &mut transforms);
+
+        assert_eq!(entities, vec![entity]);
+        assert_eq!(transforms[0], halfway);
+    }
+
+    #[test]
+    fn teleporting_transforms() {
+        let mut manager = SceneManager::new();
+        let entity = 3 as Entity;
+
+        manager.create_transform(entity);
+        manager.set_interpolation(true);
+
+        manager.snapshot();
+        manager.set_local_position(entity, Vector3::new(2.0f32, 4.0f32, 0.0f32));
+        manager.teleport(entity);
+
+        let world = manager.interpolated_world(entity, 0.5f32);
+        assert_eq!(Pose::from_matrix(&world).translation, Vector3::new(2.0f32, 4.0f32, 0.0f32));
+    }
+
+    #[test]
+    fn round_tripping_poses() {
+        let pose = Pose {
+            translation: Vector3::new(1.0f32, 2.0f32, 3.0f32),
+            rotation: Quaternion::identity(),
+            scale: Vector3::new(2.0f32, 2.0f32, 2.0f32),
+        };
+
+        assert_eq!(Pose::from_matrix(&pose.to_matrix()), pose);
+    }
 }
\ No newline at end of file
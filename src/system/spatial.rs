@@ -0,0 +1,362 @@
+use std::collections::BTreeMap;
+use nalgebra::Vector3;
+use system::entity::Entity;
+use system::bounds::{Aabb, BoundsManager, Frustum, Sphere};
+
+const NIL: usize = ::std::usize::MAX;
+
+/// Distance leaf boxes are inflated by so that small movements do not require a refit.
+const DEFAULT_MARGIN: f32 = 0.1;
+
+/// Ratio of current to freshly built tree cost above which the tree is rebuilt.
+const DEFAULT_REBUILD_RATIO: f32 = 1.5;
+
+#[derive(Clone, Copy, Debug)]
+struct Node {
+    aabb: Aabb,
+    parent: usize,
+    left: usize,
+    right: usize,
+    entity: Entity,
+}
+
+impl Node {
+    fn is_leaf(&self) -> bool {
+        self.left == NIL
+    }
+}
+
+/// Bounding volume hierarchy over entity world bounds.
+///
+/// Leaves hold a box inflated by a margin around each entity's tight world box. Movement that
+/// stays within the inflated box costs nothing; movement that escapes it refits the leaf and its
+/// ancestors in place. Insertions, removals, or refits that degrade the tree past the rebuild
+/// ratio trigger a full top-down rebuild on the next update.
+pub struct SpatialIndex {
+    nodes: Vec<Node>,
+    root: usize,
+    leaves: BTreeMap<Entity, usize>,
+    tight: BTreeMap<Entity, Aabb>,
+    margin: f32,
+    rebuild_ratio: f32,
+    built_cost: f32,
+    structure_changed: bool,
+    refitted: bool,
+}
+
+impl SpatialIndex {
+    pub fn new() -> SpatialIndex {
+        SpatialIndex {
+            nodes: Vec::new(),
+            root: NIL,
+            leaves: BTreeMap::new(),
+            tight: BTreeMap::new(),
+            margin: DEFAULT_MARGIN,
+            rebuild_ratio: DEFAULT_REBUILD_RATIO,
+            built_cost: 0.0f32,
+            structure_changed: false,
+            refitted: false,
+        }
+    }
+
+    /// Sets the distance leaf boxes are inflated by.
+    pub fn set_margin(&mut self, margin: f32) {
+        self.margin = margin;
+        self.structure_changed = true;
+    }
+
+    /// Sets the cost growth ratio that triggers a full rebuild.
+    pub fn set_rebuild_ratio(&mut self, ratio: f32) {
+        self.rebuild_ratio = ratio;
+    }
+
+    pub fn len(&self) -> usize {
+        self.tight.len()
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.tight.contains_key(&entity)
+    }
+
+    /// Adds an entity to the index. It becomes queryable after the next update.
+    pub fn insert(&mut self, entity: Entity, aabb: Aabb) {
+        self.tight.insert(entity, aabb);
+        self.structure_changed = true;
+    }
+
+    /// Removes an entity from the index. It stops being reported after the next update.
+    pub fn remove(&mut self, entity: Entity) {
+        if self.tight.remove(&entity).is_some() {
+            self.structure_changed = true;
+        }
+    }
+
+    /// Pulls refreshed world bounds from the bounds manager, dropping removed entities,
+    /// refitting moved leaves and rebuilding the tree if its structure changed or its quality
+    /// degraded.
+    pub fn update(&mut self, bounds: &BoundsManager) {
+        for &entity in bounds.removed() {
+            self.remove(entity);
+        }
+
+        for &entity in bounds.refreshed() {
+            let aabb = bounds.world_aabb(entity);
+
+            match self.leaves.get(&entity).cloned() {
+                Some(leaf) if !self.structure_changed => {
+                    self.tight.insert(entity, aabb);
+                    self.refit(leaf, aabb);
+                },
+                _ => self.insert(entity, aabb)
+            }
+        }
+
+        if self.structure_changed || (self.refitted && self.cost() > self.built_cost * self.rebuild_ratio) {
+            self.rebuild();
+        }
+
+        self.refitted = false;
+    }
+
+    /// Discards the tree and builds it again from the current set of entities.
+    pub fn rebuild(&mut self) {
+        self.nodes.clear();
+        self.leaves.clear();
+
+        let mut items: Vec<(Entity, Aabb)> = self.tight.iter()
+            .map(|(&entity, aabb)| (entity, aabb.inflate(self.margin)))
+            .collect();
+
+        self.root = if items.is_empty() {
+            NIL
+        } else {
+            self.build(&mut items[..], NIL)
+        };
+
+        self.built_cost = self.cost();
+        self.structure_changed = false;
+    }
+
+    /// Collects every entity whose bounds intersect a box.
+    pub fn query_aabb(&self, aabb: &Aabb, entities: &mut Vec<Entity>) {
+        self.query(|node| node.intersects(aabb), entities);
+    }
+
+    /// Collects every entity whose bounds intersect a sphere.
+    pub fn query_sphere(&self, center: Vector3<f32>, radius: f32, entities: &mut Vec<Entity>) {
+        let sphere = Sphere::new(center, radius);
+        self.query(|node| node.intersects_sphere(&sphere), entities);
+    }
+
+    /// Collects every entity whose bounds intersect a frustum.
+    pub fn query_frustum(&self, frustum: &Frustum, entities: &mut Vec<Entity>) {
+        self.query(|node| frustum.intersects_aabb(node), entities);
+    }
+
+    /// Walks the tree with a box predicate, testing leaves against their tight bounds.
+    pub fn query<F>(&self, test: F, entities: &mut Vec<Entity>) where F: Fn(&Aabb) -> bool {
+        if self.root == NIL {
+            return
+        }
+
+        let mut stack = vec![self.root];
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+
+            if !test(&node.aabb) {
+                continue;
+            }
+
+            if node.is_leaf() {
+                if let Some(tight) = self.tight.get(&node.entity) {
+                    if test(tight) {
+                        entities.push(node.entity);
+                    }
+                }
+            } else {
+                stack.push(node.right);
+                stack.push(node.left);
+            }
+        }
+    }
+
+    /// Sum of internal node surface areas, the usual BVH traversal cost estimate.
+    fn cost(&self) -> f32 {
+        self.nodes.iter()
+            .filter(|node| !node.is_leaf())
+            .map(|node| node.aabb.surface_area())
+            .sum()
+    }
+
+    fn refit(&mut self, leaf: usize, aabb: Aabb) {
+        if self.nodes[leaf].aabb.contains(&aabb) {
+            return
+        }
+
+        self.nodes[leaf].aabb = aabb.inflate(self.margin);
+        self.refitted = true;
+
+        let mut current = self.nodes[leaf].parent;
+        while current != NIL {
+            let (left, right) = (self.nodes[current].left, self.nodes[current].right);
+            self.nodes[current].aabb = self.nodes[left].aabb.union(&self.nodes[right].aabb);
+            current = self.nodes[current].parent;
+        }
+    }
+
+    /// Builds a subtree by splitting at the median centroid along the widest axis.
+    fn build(&mut self, items: &mut [(Entity, Aabb)], parent: usize) -> usize {
+        let index = self.nodes.len();
+
+        if items.len() == 1 {
+            let (entity, aabb) = items[0];
+
+            self.nodes.push(Node {
+                aabb,
+                parent,
+                left: NIL,
+                right: NIL,
+                entity,
+            });
+
+            self.leaves.insert(entity, index);
+            return index;
+        }
+
+        let enclosing = items[1..].iter().fold(items[0].1, |acc, &(_, ref aabb)| acc.union(aabb));
+        let centroids = items[1..].iter().fold(Aabb::new(items[0].1.center(), items[0].1.center()), |acc, &(_, ref aabb)| {
+            acc.union(&Aabb::new(aabb.center(), aabb.center()))
+        });
+
+        let spread = centroids.max - centroids.min;
+        let axis = if spread[0] >= spread[1] && spread[0] >= spread[2] {
+            0
+        } else if spread[1] >= spread[2] {
+            1
+        } else {
+            2
+        };
+
+        items.sort_by(|a, b| {
+            a.1.center()[axis].partial_cmp(&b.1.center()[axis]).unwrap_or(::std::cmp::Ordering::Equal)
+        });
+
+        self.nodes.push(Node {
+            aabb: enclosing,
+            parent,
+            left: NIL,
+            right: NIL,
+            entity: 0,
+        });
+
+        let middle = items.len() / 2;
+        let (low, high) = items.split_at_mut(middle);
+
+        let left = self.build(low, index);
+        let right = self.build(high, index);
+
+        self.nodes[index].left = left;
+        self.nodes[index].right = right;
+
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use system::scene::SceneManager;
+
+    fn unit_box_at(x: f32) -> Aabb {
+        Aabb::from_center(Vector3::new(x, 0.0f32, 0.0f32), Vector3::from_element(0.5f32))
+    }
+
+    #[test]
+    fn querying_boxes() {
+        let mut index = SpatialIndex::new();
+
+        for a in 0..16 {
+            index.insert(a as Entity, unit_box_at(a as f32 * 2.0f32));
+        }
+
+        index.rebuild();
+
+        let mut entities = Vec::new();
+        index.query_aabb(&Aabb::new(Vector3::new(3.9f32, -1.0f32, -1.0f32), Vector3::new(8.1f32, 1.0f32, 1.0f32)), &mut entities);
+        entities.sort();
+
+        assert_eq!(entities, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn querying_spheres() {
+        let mut index = SpatialIndex::new();
+
+        for a in 0..16 {
+            index.insert(a as Entity, unit_box_at(a as f32 * 2.0f32));
+        }
+
+        index.rebuild();
+
+        let mut entities = Vec::new();
+        index.query_sphere(Vector3::new(10.0f32, 0.0f32, 0.0f32), 1.0f32, &mut entities);
+
+        assert_eq!(entities, vec![5]);
+    }
+
+    #[test]
+    fn tracking_movement() {
+        let mut scene = SceneManager::new();
+        let mut bounds = BoundsManager::new();
+        let mut index = SpatialIndex::new();
+        let entity = 3 as Entity;
+
+        scene.create_transform(entity);
+        bounds.create_bounds(entity, unit_box_at(0.0f32));
+        bounds.update(&mut scene);
+        index.update(&bounds);
+
+        scene.set_local_position(entity, Vector3::new(20.0f32, 0.0f32, 0.0f32));
+        bounds.update(&mut scene);
+        index.update(&bounds);
+
+        let mut entities = Vec::new();
+        index.query_aabb(&unit_box_at(0.0f32), &mut entities);
+        assert_eq!(entities.len(), 0);
+
+        index.query_aabb(&unit_box_at(20.0f32), &mut entities);
+        assert_eq!(entities, vec![entity]);
+    }
+
+    #[test]
+    fn dropping_removed_entities() {
+        let mut scene = SceneManager::new();
+        let mut bounds = BoundsManager::new();
+        let mut index = SpatialIndex::new();
+        let (a, b) = (3 as Entity, 5 as Entity);
+
+        let transform = scene.create_transform(a);
+        scene.create_transform(b);
+        bounds.create_bounds(a, unit_box_at(0.0f32));
+        bounds.create_bounds(b, unit_box_at(0.0f32));
+        bounds.update(&mut scene);
+        index.update(&bounds);
+        assert_eq!(index.len(), 2);
+
+        scene.destroy_transform(transform);
+        bounds.update(&mut scene);
+        index.update(&bounds);
+        assert!(!index.contains(a));
+
+        let instance = bounds.bounds_for(b);
+        bounds.destroy_bounds(instance);
+        bounds.update(&mut scene);
+        index.update(&bounds);
+        assert_eq!(index.len(), 0);
+
+        let mut entities = Vec::new();
+        index.query_aabb(&unit_box_at(0.0f32), &mut entities);
+        assert_eq!(entities.len(), 0);
+    }
+}
@@ -0,0 +1,5 @@
+#include "common/math.glsl"
+
+vec3 shade(float intensity) {
+    return vec3(intensity);
+}
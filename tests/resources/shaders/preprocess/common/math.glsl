@@ -0,0 +1,3 @@
+float saturate(float value) {
+    return clamp(value, 0.0, 1.0);
+}
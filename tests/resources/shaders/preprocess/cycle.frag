@@ -0,0 +1,9 @@
+#version 150 core
+
+#include "cycle/a.glsl"
+
+out vec4 o_Color;
+
+void main() {
+    o_Color = vec4(a());
+}
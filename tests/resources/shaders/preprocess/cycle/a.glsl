@@ -0,0 +1,5 @@
+#include "cycle/b.glsl"
+
+float a() {
+    return 1.0;
+}
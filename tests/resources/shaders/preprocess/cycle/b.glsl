@@ -0,0 +1,5 @@
+#include "cycle/a.glsl"
+
+float b() {
+    return 2.0;
+}
@@ -0,0 +1,14 @@
+#version 150 core
+
+#include "common/lighting.glsl"
+#include "common/math.glsl"
+
+out vec4 o_Color;
+
+void main() {
+#ifdef SHADOWS
+    o_Color = vec4(shade(saturate(0.5)) * 0.5, 1.0);
+#else
+    o_Color = vec4(shade(saturate(0.5)), 1.0);
+#endif
+}